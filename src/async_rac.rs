@@ -1,13 +1,47 @@
-﻿use crate::shared::{ClientError, Credentials};
+﻿#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::credentials::{CredentialProvider, resolve_password_async};
+use crate::encoding::TextEncoding;
+use crate::framing::{PayloadDecoder, SIZE_SETTLE_TIME, SizeDecoder, StatusDecoder};
+use crate::input::InputPolicy;
+use crate::metrics::{self, MetricsRecorder, Operation};
+use crate::protocol::{
+    Expect, FetchAll, FetchNew, FetchSize, Messages, Register, Request, Response, SendMessage, Step,
+};
+use crate::runtime;
+use crate::shared::{
+    ClientConfig, ClientError, Credentials, Protocol, ProtocolVersion, SecretString,
+};
+use crate::trace::{self, trace_record};
+use crate::transport::{self, AsyncConnector, AsyncTransport, TcpConnector};
+use async_native_tls::TlsConnector;
+use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+use futures_util::lock::Mutex;
 use std::borrow::Cow;
-use std::pin::Pin;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio_native_tls::TlsConnector;
-
-trait Io: AsyncRead + AsyncWrite {}
-impl<T: AsyncRead + AsyncWrite + ?Sized> Io for T {}
-type DynStream = Pin<Box<dyn Io + Send>>;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// A connection to the server, plain or over TLS.
+type DynStream = Box<dyn AsyncTransport>;
+
+/// Runs an I/O operation, failing with `TimedOut` if it takes longer than `timeout`.
+async fn timed<T>(
+    timeout: Option<Duration>,
+    operation: impl Future<Output = std::io::Result<T>>,
+) -> std::io::Result<T> {
+    match timeout {
+        Some(timeout) => runtime::timeout(timeout, operation)
+            .await
+            .unwrap_or_else(|| {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "operation timed out",
+                ))
+            }),
+        None => operation.await,
+    }
+}
 
 /// A client for interacting with a RAC server.
 ///
@@ -22,7 +56,7 @@ type DynStream = Pin<Box<dyn Io + Send>>;
 ///
 /// let credentials = Credentials {
 ///     username: "test_user".to_string(),
-///     password: Some("password123".to_string()),
+///     password: Some("password123".into()),
 /// };
 ///
 /// let mut client = RacClient::new(
@@ -35,14 +69,35 @@ type DynStream = Pin<Box<dyn Io + Send>>;
 pub struct RacClient {
     /// The current size of messages in the client.
     current_messages_size: usize,
+    /// Where the final message of the last fetch starts in the history.
+    last_message_offset: usize,
     /// The address of the RAC server.
     address: String,
     /// The username for authentication.
     username: String,
     /// The password for authentication, if required.
-    password: Option<String>,
+    password: Option<SecretString>,
+    /// Source of the password, used when no password is set directly.
+    password_provider: Option<Arc<dyn CredentialProvider>>,
+    /// Receiver of request measurements.
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    /// Opener of connections, TCP if not set.
+    connector: Option<Arc<dyn AsyncConnector>>,
+    /// Injector of network faults, used for resilience tests.
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
     /// Whether to use TLS encryption.
     use_tls: bool,
+    /// The version of the protocol.
+    version: ProtocolVersion,
+    /// Timeout for connecting and for every read or write.
+    timeout: Option<Duration>,
+    /// How long to wait for more digits of the messages size.
+    size_settle_time: Duration,
+    /// The encoding of the messages on the server.
+    encoding: TextEncoding,
+    /// What to do with messages that contain control characters.
+    input_policy: InputPolicy,
 }
 
 impl RacClient {
@@ -56,13 +111,36 @@ impl RacClient {
     pub fn new(address: &str, credentials: Credentials, use_tls: bool) -> Self {
         Self {
             current_messages_size: 0,
+            last_message_offset: 0,
             address: address.to_string(),
             username: credentials.username,
             password: credentials.password,
+            password_provider: None,
+            metrics: None,
+            connector: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
             use_tls,
+            version: ProtocolVersion::V2,
+            timeout: None,
+            size_settle_time: SIZE_SETTLE_TIME,
+            encoding: TextEncoding::default(),
+            input_policy: InputPolicy::default(),
         }
     }
 
+    /// Creates a new client from the settings in `config`.
+    ///
+    /// The `protocol` field of the config is not checked.
+    pub fn from_config(config: &ClientConfig) -> Self {
+        let mut client = Self::new(&config.address, config.credentials.clone(), config.use_tls);
+        client.timeout = config.timeout;
+        client.version = config.version;
+        client.encoding = config.encoding;
+        client.input_policy = config.input_policy;
+        client
+    }
+
     /// Updates the client's credentials.
     ///
     /// This method allows you to change the username and password for the client.
@@ -71,6 +149,34 @@ impl RacClient {
         self.password = credentials.password;
     }
 
+    /// Updates the source of the password.
+    ///
+    /// The provider is asked for the password every time the client authenticates,
+    /// unless a password is set directly in the credentials.
+    pub fn update_password_provider(&mut self, provider: Option<Arc<dyn CredentialProvider>>) {
+        self.password_provider = provider;
+    }
+
+    /// Updates the receiver of request counts, latencies, received bytes and errors.
+    pub fn update_metrics(&mut self, metrics: Option<Arc<dyn MetricsRecorder>>) {
+        self.metrics = metrics;
+    }
+
+    /// Updates the opener of connections.
+    ///
+    /// `None` means connecting over TCP, which is the default.
+    pub fn update_connector(&mut self, connector: Option<Arc<dyn AsyncConnector>>) {
+        self.connector = connector;
+    }
+
+    /// Updates the injector of network faults.
+    ///
+    /// Every connection made after this goes through the injector. Only use it in tests.
+    #[cfg(feature = "chaos")]
+    pub fn update_chaos(&mut self, chaos: Option<Arc<Chaos>>) {
+        self.chaos = chaos;
+    }
+
     /// Updates the client's TLS usage.
     ///
     /// This method allows you to enable or disable TLS encryption for the connection.
@@ -78,6 +184,49 @@ impl RacClient {
         self.use_tls = use_tls;
     }
 
+    /// Updates the version of the protocol spoken by the server.
+    ///
+    /// With RACv1, messages are sent without authentication and `register_user` fails
+    /// with `ClientError::UnsupportedOperation`.
+    pub fn update_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Updates the timeout for connecting and for every read or write.
+    ///
+    /// `None` means waiting forever, which is the default.
+    pub fn update_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Updates how long the client waits for more digits of the messages size.
+    ///
+    /// The size has no terminator, so when a server sends it without a null byte or whitespace
+    /// after it, the client can only tell it is complete when no more digits arrive for this
+    /// long. Every such size read takes at least this long, and a size whose digits arrive
+    /// further apart is cut short. Raise it for slow links and set it to zero for servers
+    /// that are known to send the size in one piece.
+    ///
+    /// [`SIZE_SETTLE_TIME`] is the default.
+    pub fn update_size_settle_time(&mut self, settle_time: Duration) {
+        self.size_settle_time = settle_time;
+    }
+
+    /// Updates the encoding of the messages on the server.
+    ///
+    /// Fetched messages are decoded and sent messages are encoded with it.
+    /// Lossy UTF-8 is the default.
+    pub fn update_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Updates what to do with messages that contain line breaks or other control characters.
+    ///
+    /// Such messages are rejected with `ClientError::InvalidInput` by default.
+    pub fn update_input_policy(&mut self, policy: InputPolicy) {
+        self.input_policy = policy;
+    }
+
     /// Updates the client's address to the server.
     ///
     /// This method allows you to change the address of the RAC server.
@@ -85,51 +234,214 @@ impl RacClient {
         self.address = address;
     }
 
+    /// Updates the size of messages known to the client.
+    ///
+    /// The next call to `fetch_new_messages` will fetch messages starting from this size.
+    /// This is used to resume from a size saved earlier, for example in a `HistoryCache`.
+    pub fn update_current_messages_size(&mut self, size: usize) {
+        self.current_messages_size = size;
+    }
+
     /// Attempts to establish a TCP connection to the RAC server.
-    async fn get_stream(&self) -> Result<DynStream, ClientError> {
-        let stream = TcpStream::connect(&self.address)
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(address = %self.address, tls = self.use_tls))
+    )]
+    async fn get_stream(&self, operation: Operation) -> Result<DynStream, ClientError> {
+        let started = Instant::now();
+        let result = self.get_stream_inner(operation).await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::Connect,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::get_stream`] without reporting it to the metrics recorder.
+    async fn get_stream_inner(&self, operation: Operation) -> Result<DynStream, ClientError> {
+        let connect = match &self.connector {
+            Some(connector) => connector.connect(&self.address),
+            None => TcpConnector.connect(&self.address),
+        };
+        let stream = timed(self.timeout, connect)
             .await
-            .map_err(ClientError::ConnectionError)?;
+            .map_err(|e| ClientError::connect(operation, e))?;
 
+        let stream = self.wrap_chaos(stream);
         if self.use_tls {
-            let connector = TlsConnector::from(
-                native_tls::TlsConnector::new()
-                    .map_err(|e| ClientError::TlsInitializationError(e.to_string()))?,
-            );
-
-            let domain =
-                self.address
-                    .split(':')
-                    .next()
-                    .ok_or(ClientError::TlsInitializationError(
-                        "Invalid address format".to_string(),
-                    ))?;
-
-            let tls_stream = connector
-                .connect(domain, stream)
+            self.tls_handshake(operation, stream).await
+        } else {
+            Ok(stream)
+        }
+    }
+
+    /// Passes the stream through the fault injector, if there is one.
+    ///
+    /// Null padding is only injected into plain connections; below TLS it would break
+    /// the records.
+    fn wrap_chaos(&self, stream: DynStream) -> DynStream {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            if self.use_tls {
+                return Box::new(chaos.wrap_framed(stream));
+            }
+            return Box::new(chaos.wrap(stream));
+        }
+        stream
+    }
+
+    /// Performs the TLS handshake over an established connection.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn tls_handshake(
+        &self,
+        operation: Operation,
+        stream: DynStream,
+    ) -> Result<DynStream, ClientError> {
+        let domain = transport::tls_domain(&self.address)?;
+        let connector = TlsConnector::new();
+        let handshake = connector.connect(domain, stream);
+        let tls_stream = match self.timeout {
+            Some(timeout) => runtime::timeout(timeout, handshake).await.ok_or_else(|| {
+                ClientError::tls_handshake(
+                    operation,
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timed out"),
+                )
+            })?,
+            None => handshake.await,
+        }
+        .map_err(|e| ClientError::tls_handshake(operation, e))?;
+
+        Ok(Box::new(tls_stream))
+    }
+
+    /// Runs `request` over a new connection.
+    async fn run<R: Request>(&self, mut request: R) -> Result<R::Output, ClientError> {
+        let op = request.operation();
+        let mut stream = self.get_stream(op).await?;
+        let mut received = 0;
+        let mut response = None;
+        loop {
+            let (data, expect) = match request.resume(response.take())? {
+                Step::Send { data, expect } => (data, expect),
+                Step::Done(output) => {
+                    if received > 0 {
+                        trace_record!("bytes_received", received);
+                        metrics::record_bytes(&self.metrics, &self.address, op, received);
+                    }
+                    return Ok(output);
+                }
+            };
+            timed(self.timeout, stream.write_all(&data))
                 .await
-                .map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
+                .map_err(|e| ClientError::write(op, e))?;
+            response = match expect {
+                Expect::Nothing => None,
+                Expect::Size => {
+                    let (size, n) = self.read_size(op, &mut stream).await?;
+                    received += n;
+                    Some(Response::Size(size))
+                }
+                Expect::Payload(len) => {
+                    let payload = self.read_payload(op, &mut stream, len).await?;
+                    received += payload.len();
+                    Some(Response::Payload(payload))
+                }
+                Expect::Status => Some(Response::Status(self.read_status(op, &mut stream).await?)),
+            };
+        }
+    }
 
-            Ok(Box::pin(tls_stream))
-        } else {
-            Ok(Box::pin(stream))
+    /// Reads the size of the message history.
+    ///
+    /// The size has no terminator, so once some digits arrived, the client waits up to
+    /// the size settle time for more of them before it considers the size complete,
+    /// see [`Self::update_size_settle_time`]. Returns the size and the number of bytes read.
+    async fn read_size(
+        &self,
+        operation: Operation,
+        stream: &mut DynStream,
+    ) -> Result<(usize, usize), ClientError> {
+        let mut decoder = SizeDecoder::new();
+        let mut buf = [0u8; 64];
+        let mut received = 0;
+        loop {
+            let result = if decoder.has_digits() {
+                runtime::timeout(self.size_settle_time, stream.read(&mut buf))
+                    .await
+                    .unwrap_or(Ok(0))
+            } else {
+                timed(self.timeout, stream.read(&mut buf)).await
+            };
+            let frame = match result {
+                Ok(0) => decoder.finish(),
+                Ok(n) => {
+                    received += n;
+                    match decoder.feed(&buf[..n]) {
+                        Ok(Some(size)) => Ok(size),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => return Err(ClientError::read(operation, e)),
+            };
+            return frame
+                .map(|size| (size, received))
+                .map_err(|e| trace::frame_error(operation, e));
         }
     }
 
-    /// Removes null bytes from the data vector.
+    /// Reads the reply to an authenticated send or a registration.
     ///
-    /// This is required because some servers that are written in C
-    /// may send null bytes in the response, which can cause issues
-    /// when parsing the response.
-    fn remove_nulls(data: &mut Vec<u8>) {
-        data.retain(|&x| x != 0);
+    /// Returns `None` if the server closed the connection without a status, which means success.
+    async fn read_status(
+        &self,
+        operation: Operation,
+        stream: &mut DynStream,
+    ) -> Result<Option<u8>, ClientError> {
+        let mut decoder = StatusDecoder::new();
+        let mut buf = [0u8; 16];
+        loop {
+            let n = timed(self.timeout, stream.read(&mut buf))
+                .await
+                .map_err(|e| ClientError::read(operation, e))?;
+            if n == 0 {
+                return Ok(decoder.finish());
+            }
+            if let Some(status) = decoder.feed(&buf[..n]) {
+                return Ok(Some(status));
+            }
+        }
+    }
+
+    /// Reads a payload of `len` bytes, such as the messages that follow the size.
+    async fn read_payload(
+        &self,
+        operation: Operation,
+        stream: &mut DynStream,
+        len: usize,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut decoder = PayloadDecoder::new(len);
+        let mut buf = vec![0u8; len.min(8192)];
+        let mut payload = decoder.feed(&[]);
+        while payload.is_none() {
+            let n = timed(self.timeout, stream.read(&mut buf))
+                .await
+                .map_err(|e| ClientError::read(operation, e))?;
+            if n == 0 {
+                return Err(ClientError::closed(operation));
+            }
+            payload = decoder.feed(&buf[..n]);
+        }
+        Ok(payload.unwrap_or_default())
     }
 
     /// Tests the connection to the RAC server.
     ///
     /// This method attempts to establish a TCP connection and returns `Ok(())` if successful.
     pub async fn test_connection(&self) -> Result<(), ClientError> {
-        self.get_stream().await?;
+        self.get_stream(Operation::Connect).await?;
         Ok(())
     }
 
@@ -139,132 +451,103 @@ impl RacClient {
     ///
     /// Returns `ClientError::NoPassword` if no password specified for the client.
     /// Returns `ClientError::UsernameAlreadyTaken` if the username is already in use.
+    /// Returns `ClientError::UnsupportedOperation` if the client or the server only speaks RACv1.
     /// Returns `ClientError::UnexpectedResponse` if got unexpected response from server.
-    pub async fn register_user(&mut self) -> Result<(), ClientError> {
-        // Getting the TCP stream to the RAC server.
-        let mut stream = self.get_stream().await?;
-
-        // Sending the username and password to the RAC server.
-        if self.password.is_some() {
-            stream
-                .write_all(
-                    format!(
-                        "\x03{}\n{}",
-                        self.username,
-                        self.password.as_deref().unwrap()
-                    )
-                    .as_bytes(),
-                )
-                .await
-                .map_err(ClientError::StreamWriteError)?;
-            let mut buf = [0u8; 2];
-            let n = stream
-                .read(&mut buf)
-                .await
-                .map_err(ClientError::StreamReadError)?;
-            if n == 0 {
-                return Ok(());
-            }
-            match buf[0] {
-                0x01 => Err(ClientError::UsernameAlreadyTaken),
-                _ => Err(ClientError::UnexpectedResponse(
-                    String::from_utf8_lossy(&buf[..n]).to_string(),
-                )),
-            }
-        } else {
-            Err(ClientError::NoPassword)
-        }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, username = %self.username),
+            err(level = "warn")
+        )
+    )]
+    pub async fn register_user(&self) -> Result<(), ClientError> {
+        let started = Instant::now();
+        let result = self.register_user_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::RegisterUser,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::register_user`] without reporting it to the metrics recorder.
+    async fn register_user_inner(&self) -> Result<(), ClientError> {
+        let request = Register::for_client(
+            self.version,
+            &self.username,
+            resolve_password_async(self.version, &self.password, &self.password_provider).await?,
+        )?;
+        self.run(request).await
     }
 
     /// Fetches the total size of all messages on the server and updates the client's internal state.
     ///
     /// This is useful for determining the amount of data to fetch for all messages.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
     pub async fn fetch_messages_size(&mut self) -> Result<(), ClientError> {
-        // Getting the TCP stream to the RAC server.
-        let mut stream = self.get_stream().await?;
-
-        // Trying to send 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buf = vec![0u8; 1024];
-        let n = stream
-            .read(&mut buf)
-            .await
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
-
-        Self::remove_nulls(&mut buf);
+        let started = Instant::now();
+        let result = self.fetch_messages_size_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchMessagesSize,
+            started,
+            result,
+        )
+    }
 
-        // Then, converting it to utf8 and parsing the size to usize.
-        let response = String::from_utf8_lossy(&buf[..n]);
-        if let Ok(size) = response.parse::<usize>() {
-            self.current_messages_size = size;
-            Ok(())
-        } else {
-            Err(ClientError::ParseError(
-                "Failed to parse messages size".to_string(),
-            ))
-        }
+    /// Runs [`Self::fetch_messages_size`] without reporting it to the metrics recorder.
+    async fn fetch_messages_size_inner(&mut self) -> Result<(), ClientError> {
+        self.current_messages_size = self.run(FetchSize::new()).await?;
+        Ok(())
     }
 
     /// Fetches all messages from the RAC server.
     ///
     /// This method retrieves all messages stored on the server and updates the
     /// client's internal message size tracker.
-    pub async fn fetch_all_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        let mut stream = self.get_stream().await?;
-
-        // Sending 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-        let mut head = vec![0u8; 1024];
-        let n = stream
-            .read(&mut head)
-            .await
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_all_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_all_messages_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchAllMessages,
+            started,
+            result,
+        )
+    }
 
-        Self::remove_nulls(&mut head);
-        let response = String::from_utf8_lossy(&head[..n]);
-        let size = response
-            .parse::<usize>()
-            .map_err(|_| ClientError::ParseError("Failed to parse messages size".to_string()))?;
+    /// Runs [`Self::fetch_all_messages`] without reporting it to the metrics recorder.
+    async fn fetch_all_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self
+            .run(FetchAll::new().with_encoding(self.encoding))
+            .await?;
         self.current_messages_size = size;
-
-        // Sending 0x01 byte to get all messages.
-        stream
-            .write_all(&[0x01])
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buffer = vec![0u8; self.current_messages_size];
-        stream
-            .read_exact(&mut buffer)
-            .await
-            .map_err(ClientError::StreamReadError)?;
-
-        Self::remove_nulls(&mut buffer);
-
-        let response = String::from_utf8_lossy(&buffer).into_owned();
-
-        let vec_messages = response
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect();
-
-        Ok(vec_messages)
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Fetches only new messages that have arrived since the last fetch.
@@ -272,61 +555,46 @@ impl RacClient {
     /// This method compares the current message size on the server with the client's
     /// stored size and retrieves only the difference. The client's internal message
     /// size tracker is updated upon successful fetch.
-    pub async fn fetch_new_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        // For this approach, we will not use fetch_messages_size function,
-        // because it is necessary to fetch messages size AND THEN get new messages
-        // IN THE SAME STREAM. Welcome to the Sugoma's bullshit protocol.
-
-        let mut stream = self.get_stream().await?;
-
-        // Sending 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-        let mut head = vec![0u8; 1024];
-        let n = stream
-            .read(&mut head)
-            .await
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
-
-        Self::remove_nulls(&mut head);
-
-        // Then, converting it to utf8 and parsing the size to usize.
-        let response = String::from_utf8_lossy(&head[..n]);
-        let size = response
-            .parse::<usize>()
-            .map_err(|_| ClientError::ParseError("Failed to parse messages size".to_string()))?;
-
-        // Now, we can get new messages.
-        stream
-            .write_all(format!("\x02{}", self.current_messages_size).as_bytes())
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buffer = vec![0u8; size - self.current_messages_size];
-        stream
-            .read_exact(&mut buffer)
-            .await
-            .map_err(ClientError::StreamReadError)?;
-        let response = String::from_utf8_lossy(&buffer).into_owned();
-
-        Self::remove_nulls(&mut buffer);
-
-        let vec_messages = response
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect();
+    ///
+    /// A message that the server hasn't finished writing is left for the next fetch,
+    /// so messages are never cut in half, even in the middle of a multi-byte character.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                address = %self.address,
+                from = self.current_messages_size,
+                bytes_received = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_new_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_new_messages_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchNewMessages,
+            started,
+            result,
+        )
+    }
 
-        // Setting the new messages size.
+    /// Runs [`Self::fetch_new_messages`] without reporting it to the metrics recorder.
+    async fn fetch_new_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        // The size and the new messages have to be fetched over the same connection.
+        let request =
+            FetchNew::new(Protocol::Rac, self.current_messages_size).with_encoding(self.encoding);
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(request).await?;
         self.current_messages_size = size;
-
-        Ok(vec_messages)
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Sends a message to the server.
@@ -336,11 +604,13 @@ impl RacClient {
     /// # Example
     ///
     /// ```no_run
-    /// # use rac_rs::async_client::Client;
+    /// # use rac_rs::async_rac::RacClient;
     /// # use rac_rs::shared::ClientError;
-    /// # let mut client = Client::new("", Default::default(), false);
-    /// client.send_message("<{username}> Hello everyone!")?;
-    /// # Ok::<(), ClientError>(())
+    /// # async fn run() -> Result<(), ClientError> {
+    /// # let client = RacClient::new("", Default::default(), false);
+    /// client.send_message("<{username}> Hello everyone!").await?;
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn send_message(&self, message: &str) -> Result<(), ClientError> {
         // Replacing the `{username}` placeholder with the actual username.
@@ -348,48 +618,50 @@ impl RacClient {
         self.send_custom_message(&message).await
     }
 
-    /// Sends a raw message to the server without any modifications.
+    /// Sends a raw message to the server without replacing `{username}`.
+    ///
+    /// Line breaks and other control characters are handled according to the input policy,
+    /// see [`Self::update_input_policy`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_message",
+            skip_all,
+            fields(
+                address = %self.address,
+                message_bytes = message.len(),
+                authenticated = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
     pub async fn send_custom_message(&self, message: &str) -> Result<(), ClientError> {
-        let mut stream = self.get_stream().await?;
-
-        // Sending the message to the RAC server.
-
-        if self.password.is_some() {
-            stream
-                .write_all(
-                    format!(
-                        "\x02{}\n{}\n{}",
-                        self.username,
-                        self.password.as_deref().unwrap(),
-                        message
-                    )
-                    .as_bytes(),
-                )
-                .await
-                .map_err(ClientError::StreamWriteError)?;
-            let mut buf = [0u8; 16];
-            let n = stream
-                .read(&mut buf)
+        let started = Instant::now();
+        let result = self.send_custom_message_inner(message).await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::SendMessage,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::send_custom_message`] without reporting it to the metrics recorder.
+    async fn send_custom_message_inner(&self, message: &str) -> Result<(), ClientError> {
+        let messages = self.input_policy.apply(message)?;
+        let requests = SendMessage::for_client(
+            self.version,
+            &self.username,
+            resolve_password_async(self.version, &self.password, &self.password_provider).await?,
+            messages.iter().map(|message| self.encoding.encode(message)),
+        )?;
+        let total = requests.len();
+        for (sent, request) in requests.into_iter().enumerate() {
+            self.run(request)
                 .await
-                .map_err(ClientError::StreamReadError)?;
-            if n == 0 {
-                return Ok(());
-            }
-            return match buf[0] {
-                0x01 => Err(ClientError::UserDoesNotExist),
-                0x02 => Err(ClientError::IncorrectPassword),
-                _ => Err(ClientError::UnexpectedResponse(
-                    String::from_utf8_lossy(&buf[..n]).to_string(),
-                )),
-            };
+                .map_err(|e| ClientError::partial_send(sent, total, e))?;
         }
-
-        // If user is not authorized, we can send the message directly, without an attempt to authorize.
-        stream
-            .write_all(format!("\x01{}", message).as_bytes())
-            .await
-            .map_err(ClientError::StreamWriteError)?;
-
         Ok(())
     }
 
@@ -398,9 +670,11 @@ impl RacClient {
     /// This clears the address, username, password, and message size.
     pub fn reset(&mut self) {
         self.current_messages_size = 0;
+        self.last_message_offset = 0;
         self.address.clear();
         self.username.clear();
         self.password = None;
+        self.password_provider = None;
     }
 
     /// Returns the current size of messages known to the client.
@@ -410,11 +684,45 @@ impl RacClient {
         self.current_messages_size
     }
 
+    /// Returns the offset in the message history where the final message of the last fetch
+    /// starts, counted in the bytes sent by the server.
+    ///
+    /// It is the messages size to resume from to fetch that message again, which
+    /// [`HistoryCache`](crate::cache::HistoryCache) uses to detect a history that was reset.
+    pub fn last_message_offset(&self) -> usize {
+        self.last_message_offset
+    }
+
     /// Returns the current state of TLS usage.
     pub fn tls(&self) -> bool {
         self.use_tls
     }
 
+    /// Returns the version of the protocol.
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the timeout for connecting and for every read or write.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns how long the client waits for more digits of the messages size.
+    pub fn size_settle_time(&self) -> Duration {
+        self.size_settle_time
+    }
+
+    /// Returns the encoding of the messages on the server.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Returns what is done with messages that contain line breaks or other control characters.
+    pub fn input_policy(&self) -> InputPolicy {
+        self.input_policy
+    }
+
     /// Returns a reference to the server address.
     pub fn address(&self) -> &str {
         &self.address
@@ -425,3 +733,132 @@ impl RacClient {
         &self.username
     }
 }
+
+/// A handle to a RAC client that can be cloned and used by many tasks at the same time.
+///
+/// All clones share the settings of the [`RacClient`] the handle was made from. Every request
+/// runs on a connection of its own, so sends and registrations never wait for each other.
+/// Fetches run one at a time, so concurrent calls to `fetch_new_messages` never return the
+/// same messages twice.
+///
+/// # Example
+///
+/// ```no_run
+/// use rac_rs::async_rac::{RacClient, RacHandle};
+///
+/// # async fn run() -> Result<(), rac_rs::shared::ClientError> {
+/// let client = RacClient::new("127.0.0.1:42666", Default::default(), false);
+/// let handle = RacHandle::new(client);
+/// let sender = handle.clone();
+/// let (sent, fetched) = futures_util::join!(
+///     sender.send_message("<{username}> Hello everyone!"),
+///     handle.fetch_new_messages(),
+/// );
+/// sent?;
+/// for message in fetched? {
+///     println!("{message}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RacHandle {
+    /// The state shared by all clones.
+    shared: Arc<Shared>,
+}
+
+/// The state of a [`RacHandle`].
+#[derive(Debug)]
+struct Shared {
+    /// The client that sends messages and registers users.
+    client: RacClient,
+    /// The client that fetches, held by the running fetch.
+    fetcher: Mutex<RacClient>,
+    /// The current size of messages known to the fetcher.
+    current_messages_size: AtomicUsize,
+}
+
+impl RacHandle {
+    /// Creates a handle that takes over the settings and the size of messages of `client`.
+    pub fn new(client: RacClient) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                current_messages_size: AtomicUsize::new(client.current_messages_size),
+                fetcher: Mutex::new(client.clone()),
+                client,
+            }),
+        }
+    }
+
+    /// Runs `fetch` on the fetcher and stores the size of messages it ends with.
+    async fn fetch<T>(
+        &self,
+        fetch: impl AsyncFnOnce(&mut RacClient) -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut fetcher = self.shared.fetcher.lock().await;
+        let result = fetch(&mut fetcher).await;
+        self.shared
+            .current_messages_size
+            .store(fetcher.current_messages_size, Ordering::Relaxed);
+        result
+    }
+
+    /// Tests the connection to the RAC server.
+    ///
+    /// See [`RacClient::test_connection`].
+    pub async fn test_connection(&self) -> Result<(), ClientError> {
+        self.shared.client.test_connection().await
+    }
+
+    /// Registers a new user on the RAC server.
+    ///
+    /// See [`RacClient::register_user`].
+    pub async fn register_user(&self) -> Result<(), ClientError> {
+        self.shared.client.register_user().await
+    }
+
+    /// Fetches the total size of all messages on the server and updates the handle's internal state.
+    pub async fn fetch_messages_size(&self) -> Result<(), ClientError> {
+        self.fetch(async |fetcher| fetcher.fetch_messages_size().await)
+            .await
+    }
+
+    /// Fetches all messages from the RAC server.
+    pub async fn fetch_all_messages(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        self.fetch(async |fetcher| fetcher.fetch_all_messages().await)
+            .await
+    }
+
+    /// Fetches only new messages that have arrived since the last fetch of any clone.
+    pub async fn fetch_new_messages(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        self.fetch(async |fetcher| fetcher.fetch_new_messages().await)
+            .await
+    }
+
+    /// Sends a message to the server.
+    ///
+    /// The placeholder `{username}` in the message will be replaced with the client's username.
+    pub async fn send_message(&self, message: &str) -> Result<(), ClientError> {
+        self.shared.client.send_message(message).await
+    }
+
+    /// Sends a raw message to the server without replacing `{username}`.
+    pub async fn send_custom_message(&self, message: &str) -> Result<(), ClientError> {
+        self.shared.client.send_custom_message(message).await
+    }
+
+    /// Returns the current size of messages known to the handle.
+    pub fn current_messages_size(&self) -> usize {
+        self.shared.current_messages_size.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the server address.
+    pub fn address(&self) -> &str {
+        &self.shared.client.address
+    }
+
+    /// Returns a reference to the client's username.
+    pub fn username(&self) -> &str {
+        &self.shared.client.username
+    }
+}
@@ -1,13 +1,301 @@
-﻿use crate::shared::{ClientError, Credentials};
-use futures_util::{SinkExt, StreamExt};
+﻿#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::credentials::{CredentialProvider, resolve_password_async};
+use crate::encoding::TextEncoding;
+use crate::framing::{FrameError, SizeDecoder, StatusDecoder};
+use crate::input::InputPolicy;
+use crate::metrics::{self, MetricsRecorder, Operation};
+use crate::protocol::{
+    self, Expect, FetchAll, FetchNew, FetchSize, Messages, Register, Response, SendMessage, Step,
+};
+use crate::runtime;
+use crate::shared::{
+    BoxError, ClientConfig, ClientError, ConnectionState, Credentials, ErrorKind, Protocol,
+    ProtocolVersion, SecretString,
+};
+use crate::trace::{self, trace_event, trace_record};
+use crate::transport::{self, AsyncConnector, AsyncTransport, TcpConnector};
+use async_native_tls::TlsConnector;
+use async_tungstenite::{WebSocketReceiver, WebSocketSender, WebSocketStream, client_async};
+use futures_util::StreamExt;
+use futures_util::lock::Mutex;
 use std::borrow::Cow;
-use tokio::net::TcpStream;
-use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tungstenite::Message;
 use tungstenite::client::IntoClientRequest;
+use tungstenite::error::UrlError;
+use tungstenite::handshake::client::Request;
+
+/// A connection to the server, plain or over TLS.
+type DynStream = Box<dyn AsyncTransport>;
 
 /// WebSocket stream behind the scenes.
-type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+type WsStream = WebSocketStream<DynStream>;
+
+/// The WebSocket connection, if there is one, and the state it is in.
+#[derive(Debug, Default)]
+enum Connection {
+    /// No connection was made yet, or the client was reset.
+    #[default]
+    Disconnected,
+    /// `prepare` is running, or its future was dropped before it finished.
+    Connecting,
+    /// The connection is open.
+    Connected(Arc<Pipeline>),
+    /// The connection broke or the server closed it.
+    Closed,
+}
+
+impl Connection {
+    /// Returns the public view of the state.
+    fn state(&self) -> ConnectionState {
+        match self {
+            Connection::Disconnected => ConnectionState::Disconnected,
+            Connection::Connecting => ConnectionState::Connecting,
+            Connection::Connected(_) => ConnectionState::Connected,
+            Connection::Closed => ConnectionState::Closed,
+        }
+    }
+
+    /// Returns the open connection, or `ClientError::NoConnectionWRAC` if there is none.
+    fn pipeline(&self) -> Result<Arc<Pipeline>, ClientError> {
+        match self {
+            Connection::Connected(pipeline) => Ok(pipeline.clone()),
+            _ => Err(ClientError::NoConnectionWRAC),
+        }
+    }
+
+    /// Marks the connection as closed if `error` broke `pipeline` and it is still the open one.
+    fn close_if_broken(&mut self, pipeline: &Arc<Pipeline>, error: &ClientError) {
+        if error.kind() == ErrorKind::Io
+            && let Connection::Connected(open) = self
+            && Arc::ptr_eq(open, pipeline)
+        {
+            trace_event!(debug, "connection closed");
+            *self = Connection::Closed;
+        }
+    }
+}
+
+/// A WebSocket connection that requests of many tasks can run over at the same time.
+///
+/// The server replies in the order the requests were sent, so every step that expects a reply
+/// takes a ticket once it is sent, and replies are handed out by ticket in the order they arrive.
+#[derive(Debug)]
+struct Pipeline {
+    /// The half that requests are sent over.
+    sender: Mutex<WebSocketSender<DynStream>>,
+    /// The half that replies are read from, by one task at a time.
+    receiver: Mutex<WebSocketReceiver<DynStream>>,
+    /// Pairing of replies with the steps waiting for them.
+    tickets: std::sync::Mutex<Tickets>,
+}
+
+/// The tickets of a [`Pipeline`].
+#[derive(Debug, Default)]
+struct Tickets {
+    /// The number of replies expected so far, which is the next ticket to take.
+    sent: u64,
+    /// The number of replies read so far.
+    received: u64,
+    /// Replies read by a task other than the one waiting for them.
+    ready: HashMap<u64, Message>,
+    /// Whether the connection broke, which fails every step still waiting for a reply.
+    broken: bool,
+}
+
+/// Marks a [`Pipeline`] as broken when dropped before [`Sending::finish`].
+///
+/// A step dropped in the middle of sending may still reach the server without a ticket,
+/// which would pair every later reply with the wrong step.
+struct Sending<'a>(&'a Pipeline);
+
+impl Sending<'_> {
+    /// Returns the ticket of the reply to the sent step, taking it if `replies` is set.
+    fn finish(self, replies: bool) -> u64 {
+        let mut tickets = self.0.tickets();
+        std::mem::forget(self);
+        let ticket = tickets.sent;
+        if replies {
+            tickets.sent += 1;
+        }
+        ticket
+    }
+}
+
+impl Drop for Sending<'_> {
+    fn drop(&mut self) {
+        self.0.tickets().broken = true;
+    }
+}
+
+impl Pipeline {
+    /// Creates a pipeline over `ws`.
+    fn new(ws: WsStream) -> Self {
+        let (sender, receiver) = ws.split();
+        Self {
+            sender: Mutex::new(sender),
+            receiver: Mutex::new(receiver),
+            tickets: std::sync::Mutex::new(Tickets::default()),
+        }
+    }
+
+    /// Locks the tickets.
+    fn tickets(&self) -> MutexGuard<'_, Tickets> {
+        self.tickets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `request`, sending every step as a binary message.
+    async fn exchange<R: protocol::Request>(
+        &self,
+        metrics: &Option<Arc<dyn MetricsRecorder>>,
+        address: &str,
+        timeout: Option<Duration>,
+        mut request: R,
+    ) -> Result<R::Output, ClientError> {
+        let op = request.operation();
+        let mut received = 0;
+        let mut response = None;
+        loop {
+            let (data, expect) = match request.resume(response.take())? {
+                Step::Send { data, expect } => (data, expect),
+                Step::Done(output) => {
+                    if received > 0 {
+                        trace_record!("bytes_received", received);
+                        metrics::record_bytes(metrics, address, op, received);
+                    }
+                    return Ok(output);
+                }
+            };
+            let replies = !matches!(expect, Expect::Nothing);
+            let ticket = self.send(op, timeout, data.to_vec(), replies).await?;
+            response = match expect {
+                Expect::Nothing => None,
+                Expect::Size => {
+                    let data = into_data(self.receive(op, timeout, ticket).await?);
+                    received += data.len();
+                    let size = SizeDecoder::decode(&data).map_err(|e| trace::frame_error(op, e))?;
+                    Some(Response::Size(size))
+                }
+                Expect::Payload(_) => {
+                    let data = into_data(self.receive(op, timeout, ticket).await?);
+                    received += data.len();
+                    Some(Response::Payload(data))
+                }
+                // The server replies with an empty message on success and the status on failure.
+                Expect::Status => {
+                    let status = match self.receive(op, timeout, ticket).await? {
+                        Message::Binary(buf) => StatusDecoder::decode(&buf),
+                        message => Err(FrameError::InvalidStatus(format!("{message:?}"))),
+                    };
+                    Some(Response::Status(
+                        status.map_err(|e| trace::frame_error(op, e))?,
+                    ))
+                }
+            };
+        }
+    }
+
+    /// Sends `data` as a binary message and returns the ticket of the reply.
+    ///
+    /// The ticket is only taken if the server `replies` to the message.
+    async fn send(
+        &self,
+        operation: Operation,
+        timeout: Option<Duration>,
+        data: Vec<u8>,
+        replies: bool,
+    ) -> Result<u64, ClientError> {
+        let mut sender = self.sender.lock().await;
+        if self.tickets().broken {
+            return Err(ClientError::closed(operation));
+        }
+        let sending = Sending(self);
+        timed(timeout, sender.send(Message::Binary(data.into())))
+            .await
+            .map_err(|e| ClientError::ws_send(operation, e))?
+            .map_err(|e| ClientError::ws_send(operation, e))?;
+        Ok(sending.finish(replies))
+    }
+
+    /// Waits for the reply with `ticket`, reading replies of other steps on the way.
+    async fn receive(
+        &self,
+        operation: Operation,
+        timeout: Option<Duration>,
+        ticket: u64,
+    ) -> Result<Message, ClientError> {
+        let mut receiver = self.receiver.lock().await;
+        loop {
+            {
+                let mut tickets = self.tickets();
+                if let Some(message) = tickets.ready.remove(&ticket) {
+                    return Ok(message);
+                }
+                if tickets.broken {
+                    return Err(ClientError::closed(operation));
+                }
+            }
+            let message = Self::read(operation, &mut receiver, timeout)
+                .await
+                .inspect_err(|_| self.tickets().broken = true)?;
+            let mut tickets = self.tickets();
+            let number = tickets.received;
+            tickets.received += 1;
+            if number == ticket {
+                return Ok(message);
+            }
+            tickets.ready.insert(number, message);
+        }
+    }
+
+    /// Reads the next text or binary message.
+    async fn read(
+        operation: Operation,
+        receiver: &mut WebSocketReceiver<DynStream>,
+        timeout: Option<Duration>,
+    ) -> Result<Message, ClientError> {
+        loop {
+            let message = timed(timeout, receiver.next())
+                .await
+                .map_err(|e| ClientError::ws_read(operation, e))?
+                .ok_or(ClientError::closed(operation))?
+                .map_err(|e| ClientError::ws_read(operation, e))?;
+            match message {
+                Message::Text(_) | Message::Binary(_) => return Ok(message),
+                Message::Close(_) => return Err(ClientError::closed(operation)),
+                // Pings are answered by `tungstenite`.
+                _ => {}
+            }
+        }
+    }
+
+    /// Closes the connection.
+    async fn close(&self) {
+        let _ = self.sender.lock().await.close(None).await;
+    }
+}
+
+/// Returns the payload of a text or binary message.
+fn into_data(message: Message) -> Vec<u8> {
+    match message {
+        Message::Text(text) => text.as_bytes().to_vec(),
+        message => message.into_data().to_vec(),
+    }
+}
+
+/// Runs a WebSocket operation, failing with `TimedOut` if it takes longer than `timeout`.
+async fn timed<F: Future>(timeout: Option<Duration>, operation: F) -> std::io::Result<F::Output> {
+    match timeout {
+        Some(timeout) => runtime::timeout(timeout, operation).await.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "operation timed out")
+        }),
+        None => Ok(operation.await),
+    }
+}
 
 /// A WebSocket client for interacting with a WRAC server.
 ///
@@ -23,7 +311,7 @@ type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 /// # async fn run() -> Result<(), rac_rs::shared::ClientError> {
 /// let credentials = Credentials {
 ///     username: "test_user".to_string(),
-///     password: Some("password123".to_string()),
+///     password: Some("password123".into()),
 /// };
 ///
 /// let mut client = WClient::new(
@@ -41,6 +329,8 @@ type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 pub struct WClient {
     /// The current size of messages in the client.
     current_messages_size: usize,
+    /// Where the final message of the last fetch starts in the history.
+    last_message_offset: usize,
     /// The address of the RAC server. Can be a full `ws(s)://` URL or just `host:port`.
     address: String,
     /// Whether to use TLS encryption (`wss://`).
@@ -48,9 +338,26 @@ pub struct WClient {
     /// The username for authentication.
     username: String,
     /// The password for authentication, if required.
-    password: Option<String>,
+    password: Option<SecretString>,
+    /// Source of the password, used when no password is set directly.
+    password_provider: Option<Arc<dyn CredentialProvider>>,
+    /// Receiver of request measurements.
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    /// Opener of connections, TCP if not set.
+    connector: Option<Arc<dyn AsyncConnector>>,
+    /// Injector of network faults, used for resilience tests.
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
     /// Holds the WebSocket connection to WRAC.
-    ws_connection: Option<WsStream>,
+    connection: Connection,
+    /// The version of the protocol.
+    version: ProtocolVersion,
+    /// Timeout for connecting and for every WebSocket read or write.
+    timeout: Option<Duration>,
+    /// The encoding of the messages on the server.
+    encoding: TextEncoding,
+    /// What to do with messages that contain control characters.
+    input_policy: InputPolicy,
 }
 
 impl WClient {
@@ -66,14 +373,36 @@ impl WClient {
     pub fn new(address: &str, credentials: Credentials, use_tls: bool) -> Self {
         Self {
             current_messages_size: 0,
+            last_message_offset: 0,
             address: address.to_string(),
             use_tls,
             username: credentials.username,
             password: credentials.password,
-            ws_connection: None,
+            password_provider: None,
+            metrics: None,
+            connector: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            connection: Connection::Disconnected,
+            version: ProtocolVersion::V2,
+            timeout: None,
+            encoding: TextEncoding::default(),
+            input_policy: InputPolicy::default(),
         }
     }
 
+    /// Creates a new client from the settings in `config`.
+    ///
+    /// The `protocol` field of the config is not checked.
+    pub fn from_config(config: &ClientConfig) -> Self {
+        let mut client = Self::new(&config.address, config.credentials.clone(), config.use_tls);
+        client.timeout = config.timeout;
+        client.version = config.version;
+        client.encoding = config.encoding;
+        client.input_policy = config.input_policy;
+        client
+    }
+
     /// Updates the client's credentials.
     ///
     /// This method allows you to change the username and password for the client.
@@ -82,6 +411,35 @@ impl WClient {
         self.password = credentials.password;
     }
 
+    /// Updates the source of the password.
+    ///
+    /// The provider is asked for the password every time the client authenticates,
+    /// unless a password is set directly in the credentials.
+    pub fn update_password_provider(&mut self, provider: Option<Arc<dyn CredentialProvider>>) {
+        self.password_provider = provider;
+    }
+
+    /// Updates the receiver of request counts, latencies, received bytes and errors.
+    pub fn update_metrics(&mut self, metrics: Option<Arc<dyn MetricsRecorder>>) {
+        self.metrics = metrics;
+    }
+
+    /// Updates the opener of connections.
+    ///
+    /// `None` means connecting over TCP, which is the default. The connector gets the host
+    /// and port of the WebSocket URL as the address.
+    pub fn update_connector(&mut self, connector: Option<Arc<dyn AsyncConnector>>) {
+        self.connector = connector;
+    }
+
+    /// Updates the injector of network faults.
+    ///
+    /// Every connection made after this goes through the injector. Only use it in tests.
+    #[cfg(feature = "chaos")]
+    pub fn update_chaos(&mut self, chaos: Option<Arc<Chaos>>) {
+        self.chaos = chaos;
+    }
+
     /// Updates the client's TLS usage.
     ///
     /// This method allows you to enable or disable TLS encryption for the connection.
@@ -89,6 +447,36 @@ impl WClient {
         self.use_tls = use_tls;
     }
 
+    /// Updates the version of the protocol spoken by the server.
+    ///
+    /// With RACv1, messages are sent without authentication and `register_user` fails
+    /// with `ClientError::UnsupportedOperation`.
+    pub fn update_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Updates the timeout for connecting and for every WebSocket read or write.
+    ///
+    /// `None` means waiting forever, which is the default.
+    pub fn update_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Updates the encoding of the messages on the server.
+    ///
+    /// Fetched messages are decoded and sent messages are encoded with it.
+    /// Lossy UTF-8 is the default.
+    pub fn update_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Updates what to do with messages that contain line breaks or other control characters.
+    ///
+    /// Such messages are rejected with `ClientError::InvalidInput` by default.
+    pub fn update_input_policy(&mut self, policy: InputPolicy) {
+        self.input_policy = policy;
+    }
+
     /// Updates the client's address to the server.
     ///
     /// This method allows you to change the address of the RAC server.
@@ -96,6 +484,14 @@ impl WClient {
         self.address = address;
     }
 
+    /// Updates the size of messages known to the client.
+    ///
+    /// The next call to `fetch_new_messages` will fetch messages starting from this size.
+    /// This is used to resume from a size saved earlier, for example in a `HistoryCache`.
+    pub fn update_current_messages_size(&mut self, size: usize) {
+        self.current_messages_size = size;
+    }
+
     /// Turn the user‑supplied `address` into a valid WebSocket URL.
     fn build_url(&self) -> Result<String, ClientError> {
         if self.address.starts_with("ws://") || self.address.starts_with("wss://") {
@@ -106,27 +502,153 @@ impl WClient {
     }
 
     /// Establishes a WebSocket connection to the WRAC server.
-    async fn get_ws(&self) -> Result<WsStream, ClientError> {
-        let url = self.build_url()?;
-        let (ws, _resp) = connect_async(url.into_client_request().unwrap())
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(address = %self.address, tls = self.use_tls))
+    )]
+    async fn get_ws(&self, operation: Operation) -> Result<WsStream, ClientError> {
+        let started = Instant::now();
+        let result = self.get_ws_inner(operation).await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::Connect,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::get_ws`] without reporting it to the metrics recorder.
+    async fn get_ws_inner(&self, operation: Operation) -> Result<WsStream, ClientError> {
+        let (request, host, port) = self.build_request()?;
+
+        let address = format!("{host}:{port}");
+        let connect = match &self.connector {
+            Some(connector) => connector.connect(&address),
+            None => TcpConnector.connect(&address),
+        };
+        let stream = timed(self.timeout, connect)
             .await
-            .map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
+            .and_then(|result| result)
+            .map_err(|e| ClientError::connect(operation, e))?;
+
+        let stream = self.wrap_chaos(stream);
+        let stream = if request.uri().scheme_str() == Some("wss") {
+            self.tls_handshake(operation, transport::tls_domain(&self.address)?, stream)
+                .await?
+        } else {
+            stream
+        };
+        self.websocket_upgrade(operation, request, stream).await
+    }
+
+    /// Passes the stream through the fault injector, if there is one.
+    ///
+    /// Null padding is never injected, because it would break the WebSocket frames.
+    fn wrap_chaos(&self, stream: DynStream) -> DynStream {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            return Box::new(chaos.wrap_framed(stream));
+        }
+        stream
+    }
+
+    /// Builds the WebSocket handshake request and finds the host and port to connect to.
+    fn build_request(&self) -> Result<(Request, String, u16), ClientError> {
+        let invalid = |source: BoxError| ClientError::InvalidAddress {
+            address: self.address.clone(),
+            source,
+        };
+        let request = self
+            .build_url()?
+            .into_client_request()
+            .map_err(|e| invalid(e.into()))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid(tungstenite::Error::Url(UrlError::NoHostName).into()))?
+            .to_string();
+        // The URI has no port if the text after the last colon is not a number up to 65535.
+        let authority = uri.authority().map_or("", |a| a.as_str());
+        let host_port = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let port = match (uri.port_u16(), host_port.rsplit_once(':')) {
+            (Some(port), _) => port,
+            (None, Some((_, port))) if !port.is_empty() && !port.ends_with(']') => {
+                return Err(invalid(format!("invalid port `{port}`").into()));
+            }
+            _ if uri.scheme_str() == Some("wss") => 443,
+            _ => 80,
+        };
+        Ok((request, host, port))
+    }
+
+    /// Performs the TLS handshake over an established connection.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn tls_handshake(
+        &self,
+        operation: Operation,
+        host: &str,
+        stream: DynStream,
+    ) -> Result<DynStream, ClientError> {
+        let connector = TlsConnector::new();
+        let tls_stream = timed(self.timeout, connector.connect(host, stream))
+            .await
+            .map_err(|e| ClientError::tls_handshake(operation, e))?
+            .map_err(|e| ClientError::tls_handshake(operation, e))?;
+        Ok(Box::new(tls_stream))
+    }
+
+    /// Upgrades the connection to WebSocket.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    async fn websocket_upgrade(
+        &self,
+        operation: Operation,
+        request: Request,
+        stream: DynStream,
+    ) -> Result<WsStream, ClientError> {
+        let (ws, _resp) = timed(self.timeout, client_async(request, stream))
+            .await
+            .map_err(|e| ClientError::ws_handshake(operation, e))?
+            .map_err(|e| ClientError::ws_handshake(operation, e))?;
         Ok(ws)
     }
 
     /// Initializes the connection to WRAC server.
+    ///
+    /// If there already is a connection, it is replaced with a new one.
+    /// If connecting fails, the client stays in the state it was in before.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidAddress` if the address is not a valid WebSocket URL.
     pub async fn prepare(&mut self) -> Result<(), ClientError> {
-        self.ws_connection = Some(self.get_ws().await?);
-        Ok(())
+        let previous = std::mem::replace(&mut self.connection, Connection::Connecting);
+        match self.get_ws(Operation::Connect).await {
+            Ok(ws) => {
+                if matches!(previous, Connection::Connected(_) | Connection::Closed)
+                    && let Some(metrics) = &self.metrics
+                {
+                    metrics.reconnect(&self.address);
+                }
+                self.connection = Connection::Connected(Arc::new(Pipeline::new(ws)));
+                Ok(())
+            }
+            Err(e) => {
+                self.connection = previous;
+                Err(e)
+            }
+        }
     }
 
-    /// Checks if connection is established.
-    async fn check_connection(&self) -> Result<(), ClientError> {
-        if self.ws_connection.is_none() {
-            Err(ClientError::NoConnectionWRAC)
-        } else {
-            Ok(())
-        }
+    /// Runs `request` over the open connection.
+    async fn run<R: protocol::Request>(&mut self, request: R) -> Result<R::Output, ClientError> {
+        let pipeline = self.connection.pipeline()?;
+        pipeline
+            .exchange(&self.metrics, &self.address, self.timeout, request)
+            .await
+            .inspect_err(|e| self.connection.close_if_broken(&pipeline, e))
     }
 
     /// Registers a new user on the WRAC server.
@@ -135,83 +657,107 @@ impl WClient {
     ///
     /// Returns `ClientError::NoPassword` if no password specified for the client.
     /// Returns `ClientError::UsernameAlreadyTaken` if the username is already in use.
+    /// Returns `ClientError::UnsupportedOperation` if the client or the server only speaks RACv1.
     /// Returns `ClientError::UnexpectedResponse` if got unexpected response from server.
-    pub async fn register_user(&mut self) -> Result<(), ClientError> {
-        self.check_connection().await?;
-        if self.password.is_some() {
-            let mut ws = self.get_ws().await?;
-            let payload = format!(
-                "\x03{}\n{}",
-                self.username,
-                self.password.as_deref().unwrap()
-            );
-            ws.send(Message::Binary(payload.into()))
-                .await
-                .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-
-            if let Some(Ok(Message::Binary(buf))) = ws.next().await {
-                return match buf.first() {
-                    Some(0x01) => Err(ClientError::UsernameAlreadyTaken),
-                    Some(code) => Err(ClientError::UnexpectedResponse(format!("0x{code:02x}"))),
-                    None => Ok(()),
-                };
-            }
-            return Ok(());
-        }
-        Err(ClientError::NoPassword)
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, username = %self.username),
+            err(level = "warn")
+        )
+    )]
+    pub async fn register_user(&self) -> Result<(), ClientError> {
+        let started = Instant::now();
+        let result = self.register_user_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::RegisterUser,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::register_user`] without reporting it to the metrics recorder.
+    async fn register_user_inner(&self) -> Result<(), ClientError> {
+        let request = Register::for_client(
+            self.version,
+            &self.username,
+            resolve_password_async(self.version, &self.password, &self.password_provider).await?,
+        )?;
+        // Registration runs on its own connection.
+        let ws = self.get_ws(Operation::RegisterUser).await?;
+        Pipeline::new(ws)
+            .exchange(&self.metrics, &self.address, self.timeout, request)
+            .await
     }
 
     /// Fetches the total size of all messages on the server and updates the client's internal state.
     ///
     /// This is useful for determining the amount of data to fetch for all messages.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
     pub async fn fetch_messages_size(&mut self) -> Result<(), ClientError> {
-        self.check_connection().await?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(vec![0x00].into()))
-            .await
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        if let Some(Ok(msg)) = ws.next().await {
-            let txt: String = match msg {
-                Message::Text(t) => t.to_string(),
-                Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-                _ => String::new(),
-            };
-            self.current_messages_size = txt
-                .trim()
-                .parse::<usize>()
-                .map_err(|_| ClientError::ParseError("Failed to parse messages size".into()))?;
-            Ok(())
-        } else {
-            Err(ClientError::UnexpectedResponse("EOF".into()))
-        }
+        let started = Instant::now();
+        let result = self.fetch_messages_size_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchMessagesSize,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_messages_size`] without reporting it to the metrics recorder.
+    async fn fetch_messages_size_inner(&mut self) -> Result<(), ClientError> {
+        self.current_messages_size = self.run(FetchSize::new()).await?;
+        Ok(())
     }
 
     /// Fetches all messages from the WRAC server.
     ///
     /// This method retrieves all messages stored on the server and updates the
     /// client's internal message size tracker.
-    pub async fn fetch_all_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        // Fetching new size explicitly
-        self.fetch_messages_size().await?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(vec![0x01].into()))
-            .await
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        let all_msg = ws
-            .next()
-            .await
-            .ok_or_else(|| ClientError::UnexpectedResponse("EOF".into()))?
-            .map_err(|e| ClientError::WsReadError(e.to_string()))?;
-        let payload = match all_msg {
-            Message::Text(t) => t.to_string(),
-            Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-            _ => String::new(),
-        };
-        Ok(payload
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect())
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_all_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_all_messages_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchAllMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_all_messages`] without reporting it to the metrics recorder.
+    async fn fetch_all_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self
+            .run(FetchAll::new().with_encoding(self.encoding))
+            .await?;
+        self.current_messages_size = size;
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Fetches only new messages that have arrived since the last fetch.
@@ -219,35 +765,45 @@ impl WClient {
     /// This method compares the current message size on the server with the client's
     /// stored size and retrieves only the difference. The client's internal message
     /// size tracker is updated upon successful fetch.
-    pub async fn fetch_new_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        let old_size = self.current_messages_size.clone();
-        // Fetching new size via fetch_current_size because it's WRAC
-        self.fetch_messages_size().await?;
-
-        let new_size = self.current_messages_size;
-        if old_size >= new_size {
-            return Ok(Vec::new());
-        }
-        // Because the first one will be closed after our request.
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(format!("\x00\x02{}", old_size).into()))
-            .await
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        let diff_msg = ws
-            .next()
-            .await
-            .ok_or_else(|| ClientError::UnexpectedResponse("EOF".into()))?
-            .map_err(|e| ClientError::WsReadError(e.to_string()))?;
-        let payload = match diff_msg {
-            Message::Text(t) => t.to_string(),
-            Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-            _ => String::new(),
-        };
-        Ok(payload
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect())
+    ///
+    /// A message that the server hasn't finished writing is left for the next fetch,
+    /// so messages are never cut in half, even in the middle of a multi-byte character.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                address = %self.address,
+                from = self.current_messages_size,
+                bytes_received = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_new_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_new_messages_inner().await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchNewMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_new_messages`] without reporting it to the metrics recorder.
+    async fn fetch_new_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let request =
+            FetchNew::new(Protocol::Wrac, self.current_messages_size).with_encoding(self.encoding);
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(request).await?;
+        self.current_messages_size = size;
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Sends a message to the server.
@@ -258,7 +814,7 @@ impl WClient {
     ///
     /// ```no_run
     /// # use rac_rs::async_wrac::WClient;
-    /// # use rac_rs::shared::{ClientError, Credentials};
+    /// # use rac_rs::shared::ClientError;
     /// # async fn run() -> Result<(), ClientError> {
     /// # let mut client = WClient::new("", Default::default(), false);
     /// client.send_message("<{username}> Hello everyone!").await?;
@@ -270,47 +826,107 @@ impl WClient {
         self.send_custom_message(&msg).await
     }
 
-    /// Sends a raw message to the server without any modifications.
+    /// Sends a raw message to the server without replacing `{username}`.
+    ///
+    /// Line breaks and other control characters are handled according to the input policy,
+    /// see [`Self::update_input_policy`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_message",
+            skip_all,
+            fields(
+                address = %self.address,
+                message_bytes = message.len(),
+                authenticated = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
     pub async fn send_custom_message(&mut self, message: &str) -> Result<(), ClientError> {
-        self.check_connection().await?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        if self.password.is_some() {
-            let payload = format!(
-                "\x02{}\n{}\n{}",
-                self.username,
-                self.password.as_deref().unwrap(),
-                message
-            );
-            ws.send(Message::Binary(payload.into()))
+        let started = Instant::now();
+        let result = self.send_custom_message_inner(message).await;
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::SendMessage,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::send_custom_message`] without reporting it to the metrics recorder.
+    async fn send_custom_message_inner(&mut self, message: &str) -> Result<(), ClientError> {
+        let messages = self.input_policy.apply(message)?;
+        let requests = SendMessage::for_client(
+            self.version,
+            &self.username,
+            resolve_password_async(self.version, &self.password, &self.password_provider).await?,
+            messages.iter().map(|message| self.encoding.encode(message)),
+        )?;
+        let total = requests.len();
+        for (sent, request) in requests.into_iter().enumerate() {
+            self.run(request)
                 .await
-                .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-            if let Some(Ok(Message::Binary(buf))) = ws.next().await {
-                return match buf.first() {
-                    Some(0x01) => Err(ClientError::UserDoesNotExist),
-                    Some(0x02) => Err(ClientError::IncorrectPassword),
-                    Some(code) => Err(ClientError::UnexpectedResponse(format!("0x{code:02x}"))),
-                    None => Ok(()),
-                };
-            }
-            return Ok(());
+                .map_err(|e| ClientError::partial_send(sent, total, e))?;
         }
-        ws.send(Message::Binary(format!("\x01{}", message).into()))
-            .await
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
         Ok(())
     }
 
     /// Resets the client's state to its default values and closes WebSocket connection.
     pub async fn reset(&mut self) {
         self.current_messages_size = 0;
+        self.last_message_offset = 0;
         self.address.clear();
         self.username.clear();
         self.password = None;
+        self.password_provider = None;
         self.use_tls = false;
-        if let Some(ws) = &mut self.ws_connection {
-            let _ = ws.close(None).await;
-            self.ws_connection = None
+        if let Connection::Connected(pipeline) = &self.connection {
+            pipeline.close().await;
         }
+        self.connection = Connection::Disconnected;
+    }
+
+    /// Splits the client into a half that sends messages and a half that fetches them.
+    ///
+    /// Both halves share the connection, so a message can be sent while a fetch is waiting
+    /// for its reply. Every reply still reaches the request it belongs to.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use rac_rs::async_wrac::WClient;
+    ///
+    /// # async fn run() -> Result<(), rac_rs::shared::ClientError> {
+    /// let mut client = WClient::new("127.0.0.1:52666", Default::default(), false);
+    /// client.prepare().await?;
+    ///
+    /// let (sender, mut fetcher) = client.split();
+    /// let (sent, fetched) = futures_util::join!(
+    ///     sender.send_message("<{username}> Hello everyone!"),
+    ///     fetcher.fetch_new_messages(),
+    /// );
+    /// sent?;
+    /// for message in fetched? {
+    ///     println!("{message}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn split(self) -> (WSender, WFetcher) {
+        let handle = WHandle::new(self);
+        (
+            WSender {
+                handle: handle.clone(),
+            },
+            WFetcher { handle },
+        )
+    }
+
+    /// Returns the state of the WebSocket connection.
+    pub fn state(&self) -> ConnectionState {
+        self.connection.state()
     }
 
     /// Returns the current size of messages known to the client.
@@ -320,11 +936,40 @@ impl WClient {
         self.current_messages_size
     }
 
+    /// Returns the offset in the message history where the final message of the last fetch
+    /// starts, counted in the bytes sent by the server.
+    ///
+    /// It is the messages size to resume from to fetch that message again, which
+    /// [`HistoryCache`](crate::cache::HistoryCache) uses to detect a history that was reset.
+    pub fn last_message_offset(&self) -> usize {
+        self.last_message_offset
+    }
+
     /// Returns the current state of TLS usage.
     pub fn tls(&self) -> bool {
         self.use_tls
     }
 
+    /// Returns the version of the protocol.
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the timeout for connecting and for every WebSocket read or write.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns the encoding of the messages on the server.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Returns what is done with messages that contain line breaks or other control characters.
+    pub fn input_policy(&self) -> InputPolicy {
+        self.input_policy
+    }
+
     /// Returns a reference to the server address.
     pub fn address(&self) -> &str {
         &self.address
@@ -335,3 +980,378 @@ impl WClient {
         &self.username
     }
 }
+
+/// A handle to a WRAC connection that can be cloned and used by many tasks at the same time.
+///
+/// All clones share the settings of the [`WClient`] the handle was made from and one WebSocket
+/// connection. Requests of different tasks are pipelined over the connection and every reply
+/// is paired with the request it belongs to, so sending never waits for a fetch to finish.
+/// Fetches run one at a time, so concurrent calls to `fetch_new_messages` never return the
+/// same messages twice.
+///
+/// # Example
+///
+/// ```no_run
+/// use rac_rs::async_wrac::{WClient, WHandle};
+///
+/// # async fn run() -> Result<(), rac_rs::shared::ClientError> {
+/// let mut client = WClient::new("127.0.0.1:52666", Default::default(), false);
+/// client.prepare().await?;
+///
+/// let handle = WHandle::new(client);
+/// let sender = handle.clone();
+/// let (sent, fetched) = futures_util::join!(
+///     sender.send_message("<{username}> Hello everyone!"),
+///     handle.fetch_new_messages(),
+/// );
+/// sent?;
+/// for message in fetched? {
+///     println!("{message}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct WHandle {
+    /// The state shared by all clones.
+    shared: Arc<Shared>,
+}
+
+/// The state of a [`WHandle`].
+#[derive(Debug)]
+struct Shared {
+    /// The settings of the connection. Its own connection is never used.
+    client: WClient,
+    /// The connection shared by all clones.
+    connection: std::sync::Mutex<Connection>,
+    /// The current size of messages known to the handle.
+    current_messages_size: AtomicUsize,
+    /// Held by the running fetch.
+    fetch: Mutex<()>,
+}
+
+impl WHandle {
+    /// Creates a handle that takes over the settings, the connection and the size of messages
+    /// of `client`.
+    pub fn new(mut client: WClient) -> Self {
+        let connection = std::mem::take(&mut client.connection);
+        Self {
+            shared: Arc::new(Shared {
+                current_messages_size: AtomicUsize::new(client.current_messages_size),
+                connection: std::sync::Mutex::new(connection),
+                fetch: Mutex::new(()),
+                client,
+            }),
+        }
+    }
+
+    /// Locks the connection.
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.shared
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Initializes the connection to WRAC server for all clones.
+    ///
+    /// If there already is a connection, it is replaced with a new one once that is open.
+    /// Requests that are still running finish on the old connection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidAddress` if the address is not a valid WebSocket URL.
+    pub async fn prepare(&self) -> Result<(), ClientError> {
+        let client = &self.shared.client;
+        let ws = client.get_ws(Operation::Connect).await?;
+        let connection = Connection::Connected(Arc::new(Pipeline::new(ws)));
+        let previous = std::mem::replace(&mut *self.connection(), connection);
+        if matches!(previous, Connection::Connected(_) | Connection::Closed)
+            && let Some(metrics) = &client.metrics
+        {
+            metrics.reconnect(&client.address);
+        }
+        Ok(())
+    }
+
+    /// Runs `request` over the shared connection.
+    async fn run<R: protocol::Request>(&self, request: R) -> Result<R::Output, ClientError> {
+        let client = &self.shared.client;
+        let pipeline = self.connection().pipeline()?;
+        pipeline
+            .exchange(&client.metrics, &client.address, client.timeout, request)
+            .await
+            .inspect_err(|e| self.connection().close_if_broken(&pipeline, e))
+    }
+
+    /// Registers a new user on the WRAC server over a connection of its own.
+    ///
+    /// See [`WClient::register_user`].
+    pub async fn register_user(&self) -> Result<(), ClientError> {
+        self.shared.client.register_user().await
+    }
+
+    /// Fetches the total size of all messages on the server and updates the handle's internal state.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address(), bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_messages_size(&self) -> Result<(), ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_messages_size_inner().await;
+        let client = &self.shared.client;
+        metrics::record_request(
+            &client.metrics,
+            &client.address,
+            Operation::FetchMessagesSize,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_messages_size`] without reporting it to the metrics recorder.
+    async fn fetch_messages_size_inner(&self) -> Result<(), ClientError> {
+        let _fetch = self.shared.fetch.lock().await;
+        let size = self.run(FetchSize::new()).await?;
+        self.shared
+            .current_messages_size
+            .store(size, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Fetches all messages from the WRAC server.
+    ///
+    /// This method retrieves all messages stored on the server and updates the
+    /// handle's internal message size tracker.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address(), bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_all_messages(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_all_messages_inner().await;
+        let client = &self.shared.client;
+        metrics::record_request(
+            &client.metrics,
+            &client.address,
+            Operation::FetchAllMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_all_messages`] without reporting it to the metrics recorder.
+    async fn fetch_all_messages_inner(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let _fetch = self.shared.fetch.lock().await;
+        let Messages { size, messages, .. } = self
+            .run(FetchAll::new().with_encoding(self.shared.client.encoding))
+            .await?;
+        self.shared
+            .current_messages_size
+            .store(size, Ordering::Relaxed);
+        Ok(messages)
+    }
+
+    /// Fetches only new messages that have arrived since the last fetch of any clone.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                address = %self.address(),
+                from = tracing::field::Empty,
+                bytes_received = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub async fn fetch_new_messages(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_new_messages_inner().await;
+        let client = &self.shared.client;
+        metrics::record_request(
+            &client.metrics,
+            &client.address,
+            Operation::FetchNewMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_new_messages`] without reporting it to the metrics recorder.
+    async fn fetch_new_messages_inner(&self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let _fetch = self.shared.fetch.lock().await;
+        let from = self.current_messages_size();
+        trace_record!("from", from);
+        let request =
+            FetchNew::new(Protocol::Wrac, from).with_encoding(self.shared.client.encoding);
+        let Messages { size, messages, .. } = self.run(request).await?;
+        self.shared
+            .current_messages_size
+            .store(size, Ordering::Relaxed);
+        Ok(messages)
+    }
+
+    /// Sends a message to the server.
+    ///
+    /// The placeholder `{username}` in the message will be replaced with the client's username.
+    pub async fn send_message(&self, message: &str) -> Result<(), ClientError> {
+        let msg = message.replace("{username}", self.username());
+        self.send_custom_message(&msg).await
+    }
+
+    /// Sends a raw message to the server without replacing `{username}`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_message",
+            skip_all,
+            fields(
+                address = %self.address(),
+                message_bytes = message.len(),
+                authenticated = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub async fn send_custom_message(&self, message: &str) -> Result<(), ClientError> {
+        let started = Instant::now();
+        let result = self.send_custom_message_inner(message).await;
+        let client = &self.shared.client;
+        metrics::record_request(
+            &client.metrics,
+            &client.address,
+            Operation::SendMessage,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::send_custom_message`] without reporting it to the metrics recorder.
+    async fn send_custom_message_inner(&self, message: &str) -> Result<(), ClientError> {
+        let client = &self.shared.client;
+        let messages = client.input_policy.apply(message)?;
+        let requests = SendMessage::for_client(
+            client.version,
+            &client.username,
+            resolve_password_async(client.version, &client.password, &client.password_provider)
+                .await?,
+            messages
+                .iter()
+                .map(|message| client.encoding.encode(message)),
+        )?;
+        let total = requests.len();
+        for (sent, request) in requests.into_iter().enumerate() {
+            self.run(request)
+                .await
+                .map_err(|e| ClientError::partial_send(sent, total, e))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the state of the shared WebSocket connection.
+    pub fn state(&self) -> ConnectionState {
+        self.connection().state()
+    }
+
+    /// Returns the current size of messages known to the handle.
+    pub fn current_messages_size(&self) -> usize {
+        self.shared.current_messages_size.load(Ordering::Relaxed)
+    }
+
+    /// Returns a reference to the server address.
+    pub fn address(&self) -> &str {
+        &self.shared.client.address
+    }
+
+    /// Returns a reference to the client's username.
+    pub fn username(&self) -> &str {
+        &self.shared.client.username
+    }
+}
+
+/// The half of a split [`WClient`] that sends messages and registers users.
+///
+/// Made by [`WClient::split`]. Clones send over the same connection.
+#[derive(Debug, Clone)]
+pub struct WSender {
+    /// The handle shared with the fetcher.
+    handle: WHandle,
+}
+
+impl WSender {
+    /// Sends a message to the server.
+    ///
+    /// The placeholder `{username}` in the message will be replaced with the client's username.
+    pub async fn send_message(&self, message: &str) -> Result<(), ClientError> {
+        self.handle.send_message(message).await
+    }
+
+    /// Sends a raw message to the server without replacing `{username}`.
+    pub async fn send_custom_message(&self, message: &str) -> Result<(), ClientError> {
+        self.handle.send_custom_message(message).await
+    }
+
+    /// Registers a new user on the WRAC server over a connection of its own.
+    ///
+    /// See [`WClient::register_user`].
+    pub async fn register_user(&self) -> Result<(), ClientError> {
+        self.handle.register_user().await
+    }
+
+    /// Returns the state of the shared WebSocket connection.
+    pub fn state(&self) -> ConnectionState {
+        self.handle.state()
+    }
+}
+
+/// The half of a split [`WClient`] that fetches messages and keeps the connection open.
+///
+/// Made by [`WClient::split`].
+#[derive(Debug)]
+pub struct WFetcher {
+    /// The handle shared with the sender.
+    handle: WHandle,
+}
+
+impl WFetcher {
+    /// Initializes the connection to WRAC server for both halves.
+    ///
+    /// See [`WHandle::prepare`].
+    pub async fn prepare(&mut self) -> Result<(), ClientError> {
+        self.handle.prepare().await
+    }
+
+    /// Fetches the total size of all messages on the server and updates the fetcher's internal state.
+    pub async fn fetch_messages_size(&mut self) -> Result<(), ClientError> {
+        self.handle.fetch_messages_size().await
+    }
+
+    /// Fetches all messages from the WRAC server.
+    pub async fn fetch_all_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        self.handle.fetch_all_messages().await
+    }
+
+    /// Fetches only new messages that have arrived since the last fetch.
+    pub async fn fetch_new_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        self.handle.fetch_new_messages().await
+    }
+
+    /// Returns the current size of messages known to the fetcher.
+    pub fn current_messages_size(&self) -> usize {
+        self.handle.current_messages_size()
+    }
+
+    /// Returns the state of the shared WebSocket connection.
+    pub fn state(&self) -> ConnectionState {
+        self.handle.state()
+    }
+}
@@ -0,0 +1,252 @@
+use rac_rs::message::ChatMessage;
+use rac_rs::search::{Query, SearchIndex};
+use ratatui::Frame;
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+
+/// Colors used for authors. The color is picked by the hash of the username,
+/// so every author keeps the same color between sessions.
+const AUTHOR_COLORS: [Color; 8] = [
+    Color::Red,
+    Color::Green,
+    Color::Yellow,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightRed,
+    Color::LightGreen,
+];
+
+/// What the UI loop should do after handling a key.
+pub enum Action {
+    None,
+    Send(String),
+    Quit,
+}
+
+/// State of the terminal UI.
+pub struct App {
+    /// Title shown above the history.
+    title: String,
+    /// Parsed and indexed chat history.
+    history: SearchIndex,
+    /// The query and positions of found messages while search results are shown.
+    results: Option<(String, Vec<usize>)>,
+    /// Text typed in the input line.
+    input: String,
+    /// How many lines the history is scrolled up from the bottom.
+    scroll: usize,
+    /// Height of the history area during the last draw, used for paging.
+    page: usize,
+    /// Last status or error to show at the bottom.
+    status: String,
+}
+
+impl App {
+    pub fn new(title: String) -> Self {
+        Self {
+            title,
+            history: SearchIndex::new(),
+            results: None,
+            input: String::new(),
+            scroll: 0,
+            page: 1,
+            status: "Connecting...".to_string(),
+        }
+    }
+
+    /// Replaces the history with the lines fetched right after connecting.
+    pub fn set_history(&mut self, lines: Vec<String>) {
+        self.history.clear();
+        self.history.extend(lines);
+        self.results = None;
+        self.scroll = 0;
+        self.status = format!("{} messages loaded", self.history.len());
+    }
+
+    /// Appends new lines to the history.
+    ///
+    /// If the user scrolled up, the view stays on the same lines instead of jumping to the bottom.
+    pub fn push_messages(&mut self, lines: Vec<String>) {
+        if self.scroll > 0 && self.results.is_none() {
+            self.scroll += lines.len();
+        }
+        self.history.extend(lines);
+    }
+
+    pub fn set_status(&mut self, status: impl Into<String>) {
+        self.status = status.into();
+    }
+
+    /// Handles a key press and tells the caller what to do next.
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Action::Quit,
+            KeyCode::Esc if self.results.is_some() => {
+                self.results = None;
+                self.scroll = 0;
+                Action::None
+            }
+            KeyCode::Esc => Action::Quit,
+            KeyCode::Enter => {
+                let text = std::mem::take(&mut self.input);
+                if text.trim().is_empty() {
+                    return Action::None;
+                }
+                self.scroll = 0;
+                if let Some(query) = text.strip_prefix("/search ") {
+                    self.search(query);
+                    return Action::None;
+                }
+                self.status = "Sending...".to_string();
+                Action::Send(text)
+            }
+            KeyCode::Char(c) => {
+                self.input.push(c);
+                Action::None
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+                Action::None
+            }
+            KeyCode::Up => self.scroll_by(1),
+            KeyCode::Down => self.scroll_back(1),
+            KeyCode::PageUp => self.scroll_by(self.page),
+            KeyCode::PageDown => self.scroll_back(self.page),
+            KeyCode::Home => self.scroll_by(self.visible_len()),
+            KeyCode::End => {
+                self.scroll = 0;
+                Action::None
+            }
+            _ => Action::None,
+        }
+    }
+
+    /// Shows messages matching the query instead of the history.
+    fn search(&mut self, input: &str) {
+        match Query::parse(input) {
+            Ok(query) => {
+                let hits: Vec<usize> = self
+                    .history
+                    .search(&query)
+                    .iter()
+                    .map(|hit| hit.index)
+                    .collect();
+                self.status = format!("{} messages found, press Esc to go back", hits.len());
+                self.results = Some((input.to_string(), hits));
+            }
+            Err(e) => self.status = format!("Error: {e}"),
+        }
+    }
+
+    /// Returns the number of messages in the current view.
+    fn visible_len(&self) -> usize {
+        match &self.results {
+            Some((_, hits)) => hits.len(),
+            None => self.history.len(),
+        }
+    }
+
+    fn scroll_by(&mut self, lines: usize) -> Action {
+        let max = self.visible_len().saturating_sub(self.page);
+        self.scroll = (self.scroll + lines).min(max);
+        Action::None
+    }
+
+    fn scroll_back(&mut self, lines: usize) -> Action {
+        self.scroll = self.scroll.saturating_sub(lines);
+        Action::None
+    }
+
+    /// Draws the history, the input line and the status bar.
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [history_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        self.page = history_area.height.saturating_sub(2).max(1) as usize;
+        let len = self.visible_len();
+        self.scroll = self.scroll.min(len.saturating_sub(self.page));
+        let end = len - self.scroll;
+        let start = end.saturating_sub(self.page);
+        let messages = self.history.messages();
+        let lines: Vec<Line> = match &self.results {
+            Some((_, hits)) => hits[start..end]
+                .iter()
+                .map(|&i| Self::render_message(&messages[i]))
+                .collect(),
+            None => messages[start..end]
+                .iter()
+                .map(Self::render_message)
+                .collect(),
+        };
+
+        let mut title = match &self.results {
+            Some((query, _)) => format!(" Search: {query} "),
+            None => format!(" {} ", self.title),
+        };
+        if self.scroll > 0 {
+            title.push_str(&format!("(scrolled up {} lines) ", self.scroll));
+        }
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title)),
+            history_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(self.input.as_str())
+                .block(Block::default().borders(Borders::ALL).title(" Message ")),
+            input_area,
+        );
+        let cursor_x = input_area.x + 1 + self.input.chars().count() as u16;
+        frame.set_cursor_position((
+            cursor_x.min(input_area.right().saturating_sub(2)),
+            input_area.y + 1,
+        ));
+
+        frame.render_widget(
+            Paragraph::new(self.status.as_str()).style(Style::default().fg(Color::DarkGray)),
+            status_area,
+        );
+    }
+
+    fn render_message(message: &ChatMessage) -> Line<'_> {
+        let mut spans = Vec::new();
+        if let Some(timestamp) = &message.timestamp {
+            spans.push(Span::styled(
+                format!("[{timestamp}] "),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        match &message.author {
+            Some(author) => {
+                spans.push(Span::styled(
+                    format!("<{author}> "),
+                    Style::default()
+                        .fg(Self::author_color(author))
+                        .add_modifier(Modifier::BOLD),
+                ));
+                spans.push(Span::raw(message.body.as_str()));
+            }
+            None => spans.push(Span::styled(
+                message.body.as_str(),
+                Style::default().fg(Color::Gray),
+            )),
+        }
+        Line::from(spans)
+    }
+
+    fn author_color(author: &str) -> Color {
+        // FNV-1a, because it is stable between runs and Rust versions.
+        let hash = author.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        AUTHOR_COLORS[(hash % AUTHOR_COLORS.len() as u64) as usize]
+    }
+}
@@ -0,0 +1,234 @@
+//! `rac-tui` is an interactive terminal client for RAC and WRAC servers.
+//!
+//! It loads the full history on start, polls for new messages and sends
+//! everything typed in the input line in the message format. Typing
+//! `/search <query>` shows matching messages instead of the history.
+
+mod app;
+mod worker;
+
+use app::{Action, App};
+use rac_rs::cache::HistoryCache;
+use rac_rs::credentials::{CommandProvider, CredentialProvider, EnvProvider, FileProvider};
+use rac_rs::message::format_message;
+use rac_rs::search::{Query, SearchIndex};
+use rac_rs::shared::Credentials;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::Duration;
+use worker::{Command, Connection, Update};
+
+const USAGE: &str = "Usage: rac-tui [OPTIONS] <ADDRESS>
+
+Options:
+  -u, --username <NAME>    Username to send messages with
+      --password-env <VAR> Read the password for authenticated sending (RACv2) from
+                           the environment variable VAR
+      --password-file <PATH>
+                           Read the password from PATH, which only its owner may access
+      --password-command <COMMAND>
+                           Run COMMAND, split at spaces and without a shell, and use
+                           the first line of its output as the password
+  -w, --wrac               Use the WRAC protocol instead of RAC
+  -t, --tls                Use TLS for the connection
+  -i, --interval <MS>      Interval between polls in milliseconds [default: 1000]
+  -c, --cache <DIR>        Keep the history in DIR and continue from it on the next start
+  -s, --search <QUERY>     Print messages from the cache matching QUERY and exit
+  -f, --format <FORMAT>    Format of sent messages, `{username}` and `{text}` are replaced
+                           [default: <{username}> {text}]
+  -h, --help               Print this help";
+
+/// Command line options.
+struct Options {
+    address: String,
+    credentials: Credentials,
+    password: Option<Arc<dyn CredentialProvider>>,
+    wrac: bool,
+    tls: bool,
+    interval: Duration,
+    format: String,
+    cache: Option<PathBuf>,
+    search: Option<String>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut address = None;
+        let mut credentials = Credentials {
+            username: std::env::var("USER").unwrap_or_else(|_| "anonymous".to_string()),
+            password: None,
+        };
+        let mut password: Option<Arc<dyn CredentialProvider>> = None;
+        let mut wrac = false;
+        let mut tls = false;
+        let mut interval = Duration::from_millis(1000);
+        let mut format = "<{username}> {text}".to_string();
+        let mut cache = None;
+        let mut search = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {name}"))
+            };
+            match arg.as_str() {
+                "-u" | "--username" => credentials.username = value(&arg)?,
+                "--password-env" => password = Some(Arc::new(EnvProvider::new(value(&arg)?))),
+                "--password-file" => password = Some(Arc::new(FileProvider::new(value(&arg)?))),
+                "--password-command" => {
+                    let command = value(&arg)?;
+                    let mut words = command.split_whitespace();
+                    let program = words
+                        .next()
+                        .ok_or_else(|| "The password command is empty".to_string())?;
+                    password = Some(Arc::new(CommandProvider::new(program, words)));
+                }
+                "-p" | "--password" => {
+                    return Err(format!(
+                        "{arg} is not supported, because other users can see the command line. \
+                         Use --password-env, --password-file or --password-command instead"
+                    ));
+                }
+                "-w" | "--wrac" => wrac = true,
+                "-t" | "--tls" => tls = true,
+                "-i" | "--interval" => {
+                    let ms = value(&arg)?
+                        .parse()
+                        .map_err(|_| "Interval must be a number of milliseconds".to_string())?;
+                    interval = Duration::from_millis(ms);
+                }
+                "-f" | "--format" => format = value(&arg)?,
+                "-c" | "--cache" => cache = Some(PathBuf::from(value(&arg)?)),
+                "-s" | "--search" => search = Some(value(&arg)?),
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option: {arg}")),
+                _ if address.is_none() => address = Some(arg),
+                _ => return Err(format!("Unexpected argument: {arg}")),
+            }
+        }
+
+        Ok(Self {
+            address: address.ok_or_else(|| USAGE.to_string())?,
+            credentials,
+            password,
+            wrac,
+            tls,
+            interval,
+            format,
+            cache,
+            search,
+        })
+    }
+}
+
+fn main() -> ExitCode {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{message}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let cache = match &options.cache {
+        Some(dir) => match HistoryCache::open(dir, &options.address) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                eprintln!("{}", worker::describe(&e));
+                return ExitCode::FAILURE;
+            }
+        },
+        None => None,
+    };
+
+    if let Some(query) = &options.search {
+        let Some(cache) = &cache else {
+            eprintln!("Searching requires the history cache, specify it with --cache");
+            return ExitCode::FAILURE;
+        };
+        return match Query::parse(query) {
+            Ok(query) => {
+                for hit in SearchIndex::from_cache(cache).search(&query) {
+                    println!("{}", hit.message);
+                }
+                ExitCode::SUCCESS
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let (command_tx, command_rx) = mpsc::channel();
+    let (update_tx, update_rx) = mpsc::channel();
+    let connection = Connection::new(
+        &options.address,
+        options.credentials.clone(),
+        options.password.clone(),
+        options.tls,
+        options.wrac,
+    );
+    let interval = options.interval;
+    thread::spawn(move || worker::run(connection, cache, interval, command_rx, update_tx));
+
+    let protocol = if options.wrac { "WRAC" } else { "RAC" };
+    let mut app = App::new(format!("{} ({protocol})", options.address));
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, &options, &command_tx, &update_rx);
+    ratatui::restore();
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the UI loop until the user quits.
+fn run(
+    terminal: &mut ratatui::DefaultTerminal,
+    app: &mut App,
+    options: &Options,
+    commands: &mpsc::Sender<Command>,
+    updates: &mpsc::Receiver<Update>,
+) -> std::io::Result<()> {
+    loop {
+        while let Ok(update) = updates.try_recv() {
+            match update {
+                Update::History(lines) => app.set_history(lines),
+                Update::NewMessages(lines) => app.push_messages(lines),
+                Update::Sent => app.set_status("Message sent"),
+                Update::Error(e) => app.set_status(format!("Error: {e}")),
+            }
+        }
+
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match app.handle_key(key) {
+            Action::None => {}
+            Action::Send(text) => {
+                let message = format_message(&options.format, &options.credentials.username, &text);
+                if commands.send(Command::Send(message)).is_err() {
+                    app.set_status("Error: network thread stopped");
+                }
+            }
+            Action::Quit => return Ok(()),
+        }
+    }
+}
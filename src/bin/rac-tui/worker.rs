@@ -0,0 +1,290 @@
+use rac_rs::cache::HistoryCache;
+use rac_rs::credentials::CredentialProvider;
+use rac_rs::rac::RacClient;
+use rac_rs::shared::{ClientError, ConnectionState, Credentials};
+use rac_rs::wrac::WClient;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// The delay before the first attempt to connect the WRAC client again.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// The longest delay between attempts to connect the WRAC client again.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// Requests sent from the UI to the worker thread.
+pub enum Command {
+    /// Send a formatted message through `send_custom_message`.
+    Send(String),
+}
+
+/// Updates sent from the worker thread to the UI.
+pub enum Update {
+    /// The full history, received right after connecting.
+    History(Vec<String>),
+    /// Messages that arrived since the previous poll.
+    NewMessages(Vec<String>),
+    /// A message was sent successfully.
+    Sent,
+    /// An operation failed.
+    Error(String),
+}
+
+/// The client used by the worker, either RAC or WRAC.
+pub enum Connection {
+    Rac(RacClient),
+    Wrac(Box<WClient>, Backoff),
+}
+
+/// When to try to connect the WRAC client again after a failed attempt.
+pub struct Backoff {
+    /// The delay after the next failed attempt.
+    delay: Duration,
+    /// The earliest time of the next attempt, if the last one failed.
+    next_attempt: Option<Instant>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            delay: RECONNECT_DELAY,
+            next_attempt: None,
+        }
+    }
+}
+
+impl Connection {
+    /// Creates a client for the given transport, which takes the password from `password`.
+    pub fn new(
+        address: &str,
+        credentials: Credentials,
+        password: Option<Arc<dyn CredentialProvider>>,
+        use_tls: bool,
+        wrac: bool,
+    ) -> Self {
+        if wrac {
+            let mut client = WClient::new(address, credentials, use_tls);
+            client.update_password_provider(password);
+            Connection::Wrac(Box::new(client), Backoff::default())
+        } else {
+            let mut client = RacClient::new(address, credentials, use_tls);
+            client.update_password_provider(password);
+            Connection::Rac(client)
+        }
+    }
+
+    fn prepare(&mut self) -> Result<(), ClientError> {
+        match self {
+            Connection::Rac(client) => client.test_connection(),
+            Connection::Wrac(..) => self.reconnect(),
+        }
+    }
+
+    /// Connects the WRAC client if it never connected or its connection was closed.
+    ///
+    /// After a failed attempt, the next one waits for a delay that doubles with every failure,
+    /// and until then `ClientError::NoConnectionWRAC` is returned.
+    fn reconnect(&mut self) -> Result<(), ClientError> {
+        let Connection::Wrac(client, backoff) = self else {
+            return Ok(());
+        };
+        if !matches!(
+            client.state(),
+            ConnectionState::Disconnected | ConnectionState::Closed
+        ) {
+            return Ok(());
+        }
+        if backoff
+            .next_attempt
+            .is_some_and(|next_attempt| Instant::now() < next_attempt)
+        {
+            return Err(ClientError::NoConnectionWRAC);
+        }
+        match client.prepare() {
+            Ok(()) => {
+                *backoff = Backoff::default();
+                Ok(())
+            }
+            Err(e) => {
+                backoff.next_attempt = Some(Instant::now() + backoff.delay);
+                backoff.delay = (backoff.delay * 2).min(MAX_RECONNECT_DELAY);
+                Err(e)
+            }
+        }
+    }
+
+    fn fetch_all_messages(&mut self) -> Result<Vec<String>, ClientError> {
+        self.reconnect()?;
+        let messages = match self {
+            Connection::Rac(client) => client.fetch_all_messages()?,
+            Connection::Wrac(client, _) => client.fetch_all_messages()?,
+        };
+        Ok(messages.into_iter().map(|m| m.into_owned()).collect())
+    }
+
+    fn fetch_new_messages(&mut self) -> Result<Vec<String>, ClientError> {
+        self.reconnect()?;
+        let messages = match self {
+            Connection::Rac(client) => client.fetch_new_messages()?,
+            Connection::Wrac(client, _) => client.fetch_new_messages()?,
+        };
+        Ok(messages.into_iter().map(|m| m.into_owned()).collect())
+    }
+
+    fn current_messages_size(&self) -> usize {
+        match self {
+            Connection::Rac(client) => client.current_messages_size(),
+            Connection::Wrac(client, _) => client.current_messages_size(),
+        }
+    }
+
+    fn last_message_offset(&self) -> usize {
+        match self {
+            Connection::Rac(client) => client.last_message_offset(),
+            Connection::Wrac(client, _) => client.last_message_offset(),
+        }
+    }
+
+    fn update_current_messages_size(&mut self, size: usize) {
+        match self {
+            Connection::Rac(client) => client.update_current_messages_size(size),
+            Connection::Wrac(client, _) => client.update_current_messages_size(size),
+        }
+    }
+
+    fn send_custom_message(&mut self, message: &str) -> Result<(), ClientError> {
+        self.reconnect()?;
+        match self {
+            Connection::Rac(client) => client.send_custom_message(message),
+            Connection::Wrac(client, _) => client.send_custom_message(message),
+        }
+    }
+}
+
+/// Formats an error together with its sources, like `Failed to connect: Connection refused`.
+pub fn describe(error: &ClientError) -> String {
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        message.push_str(&format!(": {cause}"));
+        source = cause.source();
+    }
+    message
+}
+
+/// Runs the network loop until the UI drops its command sender.
+///
+/// Network calls are blocking, so they live on their own thread to keep the UI responsive.
+pub fn run(
+    mut connection: Connection,
+    mut cache: Option<HistoryCache>,
+    interval: Duration,
+    commands: Receiver<Command>,
+    updates: Sender<Update>,
+) {
+    if let Err(e) = connection.prepare() {
+        let _ = updates.send(Update::Error(describe(&e)));
+    }
+
+    // With a cache, show what we already have and continue from there.
+    let mut first = match cache.as_mut() {
+        Some(cache) => {
+            connection.update_current_messages_size(cache.resume_size());
+            if updates
+                .send(Update::History(cache.lines().to_vec()))
+                .is_err()
+            {
+                return;
+            }
+            resume(&mut connection, cache)
+        }
+        None => load_history(&mut connection, None),
+    };
+
+    loop {
+        let update = match first.take() {
+            Some(update) => update,
+            None => match commands.recv_timeout(interval) {
+                Ok(Command::Send(message)) => match connection.send_custom_message(&message) {
+                    Ok(()) => Update::Sent,
+                    Err(e) => Update::Error(describe(&e)),
+                },
+                Err(RecvTimeoutError::Timeout) => match poll(&mut connection, cache.as_mut()) {
+                    Some(update) => update,
+                    None => continue,
+                },
+                Err(RecvTimeoutError::Disconnected) => return,
+            },
+        };
+        if updates.send(update).is_err() {
+            return;
+        }
+    }
+}
+
+/// Fetches the whole history and stores it in the cache.
+fn load_history(connection: &mut Connection, cache: Option<&mut HistoryCache>) -> Option<Update> {
+    let lines = match connection.fetch_all_messages() {
+        Ok(lines) => lines,
+        Err(e) => return Some(Update::Error(describe(&e))),
+    };
+    if let Some(cache) = cache
+        && let Err(e) = cache.replace(
+            &lines,
+            connection.current_messages_size(),
+            connection.last_message_offset(),
+        )
+    {
+        return Some(Update::Error(describe(&e)));
+    }
+    Some(Update::History(lines))
+}
+
+/// Fetches the messages that arrived since the previous run, starting with the final cached
+/// line, and loads the whole history again if that line is not there anymore.
+fn resume(connection: &mut Connection, cache: &mut HistoryCache) -> Option<Update> {
+    let lines = match connection.fetch_new_messages() {
+        Ok(lines) => lines,
+        Err(ClientError::HistoryReset) => return load_history(connection, Some(cache)),
+        Err(e) => return Some(Update::Error(describe(&e))),
+    };
+    let lines = match cache.check_resume(&lines) {
+        Ok(lines) => lines.to_vec(),
+        Err(_) => return load_history(connection, Some(cache)),
+    };
+    if lines.is_empty() {
+        return None;
+    }
+    if let Err(e) = cache.append(
+        &lines,
+        connection.current_messages_size(),
+        connection.last_message_offset(),
+    ) {
+        return Some(Update::Error(describe(&e)));
+    }
+    Some(Update::NewMessages(lines))
+}
+
+/// Fetches new messages and stores them in the cache.
+///
+/// Returns `None` if there is nothing new.
+fn poll(connection: &mut Connection, cache: Option<&mut HistoryCache>) -> Option<Update> {
+    let lines = match connection.fetch_new_messages() {
+        Ok(lines) => lines,
+        Err(ClientError::HistoryReset) => return load_history(connection, cache),
+        Err(e) => return Some(Update::Error(describe(&e))),
+    };
+    if lines.is_empty() {
+        return None;
+    }
+    if let Some(cache) = cache
+        && let Err(e) = cache.append(
+            &lines,
+            connection.current_messages_size(),
+            connection.last_message_offset(),
+        )
+    {
+        return Some(Update::Error(describe(&e)));
+    }
+    Some(Update::NewMessages(lines))
+}
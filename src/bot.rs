@@ -0,0 +1,409 @@
+use crate::message::ChatMessage;
+use crate::shared::ClientError;
+use regex::{Captures, Regex};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// A function that handles a command or a trigger and optionally returns a reply.
+pub type Handler = Box<dyn Fn(&Context<'_>) -> Option<String> + Send + Sync>;
+
+/// Information about the message that invoked a handler.
+pub struct Context<'a> {
+    /// The parsed message.
+    pub message: &'a ChatMessage,
+    /// Everything after the command name, trimmed. Empty for triggers.
+    pub args: &'a str,
+    /// Capture groups of the trigger regex. `None` for commands.
+    pub captures: Option<Captures<'a>>,
+}
+
+/// Limits how many replies the bot sends in a period of time.
+///
+/// Replies over the limit are not dropped, the runner waits until the oldest
+/// reply leaves the window.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Maximum number of replies in the window.
+    pub max_replies: usize,
+    /// Length of the window.
+    pub per: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            max_replies: 5,
+            per: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Stops a running [`Bot`] from another thread or task.
+///
+/// Made by [`Bot::stop_handle`]. The runner checks it before every poll and reply, so it
+/// returns at the latest one poll interval or rate limit wait after [`StopHandle::stop`].
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Asks the bot to stop. It stays stopped, also for later runs.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the bot was asked to stop.
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A chat bot that polls for new messages and dispatches them to handlers.
+///
+/// Commands are matched by a prefix and a name (`!ping`), triggers by a regular
+/// expression. Commands are checked first, then triggers in the order they were added.
+/// Messages sent by the bot itself are always ignored.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::bot::Bot;
+///
+/// let mut bot = Bot::new("!");
+/// bot.add_command("ping", |_| Some("pong".to_string()));
+/// bot.add_command("echo", |ctx| Some(ctx.args.to_string()));
+/// bot.add_trigger(r"(?i)\bhello\b", |ctx| {
+///     Some(format!("Hello, {}!", ctx.message.author.as_deref()?))
+/// })
+/// .unwrap();
+///
+/// assert_eq!(bot.dispatch("<alice> !ping", "bot").as_deref(), Some("pong"));
+/// assert_eq!(bot.dispatch("<alice> !echo a b", "bot").as_deref(), Some("a b"));
+/// assert_eq!(bot.dispatch("<alice> Hello there", "bot").as_deref(), Some("Hello, alice!"));
+/// assert_eq!(bot.dispatch("<bot> !ping", "bot"), None);
+/// ```
+pub struct Bot {
+    /// Prefix that starts every command.
+    prefix: String,
+    /// Registered commands by name.
+    commands: Vec<(String, Handler)>,
+    /// Registered regex triggers.
+    triggers: Vec<(Regex, Handler)>,
+    /// Format of replies. `{text}` is replaced with the reply and `{username}` with the bot's username.
+    reply_format: String,
+    /// Time to wait between polls.
+    poll_interval: Duration,
+    /// Limit of replies.
+    rate_limit: RateLimit,
+    /// Times of the recently sent replies.
+    sent: VecDeque<Instant>,
+    /// Set to stop the runner.
+    stop: StopHandle,
+}
+
+impl Bot {
+    /// Creates a new `Bot` that recognizes commands starting with `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            commands: Vec::new(),
+            triggers: Vec::new(),
+            reply_format: "<{username}> {text}".to_string(),
+            poll_interval: Duration::from_secs(1),
+            rate_limit: RateLimit::default(),
+            sent: VecDeque::new(),
+            stop: StopHandle::default(),
+        }
+    }
+
+    /// Registers a handler for the command `name`.
+    pub fn add_command<F>(&mut self, name: &str, handler: F)
+    where
+        F: Fn(&Context<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.commands.push((name.to_string(), Box::new(handler)));
+    }
+
+    /// Registers a handler for messages which text matches `pattern`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `regex::Error` if the pattern is not a valid regular expression.
+    pub fn add_trigger<F>(&mut self, pattern: &str, handler: F) -> Result<(), regex::Error>
+    where
+        F: Fn(&Context<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.triggers
+            .push((Regex::new(pattern)?, Box::new(handler)));
+        Ok(())
+    }
+
+    /// Updates the format of replies.
+    ///
+    /// `{text}` is replaced with the reply and `{username}` with the bot's username.
+    pub fn update_reply_format(&mut self, format: &str) {
+        self.reply_format = format.to_string();
+    }
+
+    /// Updates the time to wait between polls.
+    pub fn update_poll_interval(&mut self, interval: Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Updates the limit of replies.
+    pub fn update_rate_limit(&mut self, rate_limit: RateLimit) {
+        self.rate_limit = rate_limit;
+        self.sent.clear();
+    }
+
+    /// Returns a handle that stops the running bot.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Finds a handler for the line and returns its reply.
+    ///
+    /// Returns `None` if the line was sent by `own_username`, nothing matched or the handler
+    /// decided not to reply.
+    pub fn dispatch(&self, line: &str, own_username: &str) -> Option<String> {
+        let message = ChatMessage::parse(line);
+        if message.author.as_deref() == Some(own_username) {
+            return None;
+        }
+
+        if let Some(command) = message.body.strip_prefix(&self.prefix) {
+            let (name, args) = command
+                .split_once(char::is_whitespace)
+                .unwrap_or((command, ""));
+            if let Some((_, handler)) = self.commands.iter().find(|(n, _)| n == name) {
+                return handler(&Context {
+                    message: &message,
+                    args: args.trim(),
+                    captures: None,
+                });
+            }
+        }
+
+        self.triggers.iter().find_map(|(regex, handler)| {
+            let captures = regex.captures(&message.body)?;
+            handler(&Context {
+                message: &message,
+                args: "",
+                captures: Some(captures),
+            })
+        })
+    }
+
+    /// Puts the reply text and the bot's username into the reply format.
+    ///
+    /// Both are filled in at once, so placeholders in the text, which may quote other users,
+    /// are sent as they are.
+    fn format_reply(&self, text: &str, username: &str) -> String {
+        crate::message::format_message(&self.reply_format, username, text)
+    }
+
+    /// Returns how long to wait before the next reply is allowed and records it.
+    fn reserve_reply(&mut self) -> Duration {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= self.rate_limit.per)
+        {
+            self.sent.pop_front();
+        }
+
+        let wait = if self.sent.len() >= self.rate_limit.max_replies.max(1) {
+            (self.sent[0] + self.rate_limit.per).saturating_duration_since(now)
+        } else {
+            Duration::ZERO
+        };
+        self.sent.push_back(now + wait);
+        wait
+    }
+
+    /// Runs the bot with a synchronous client until an error occurs or it is stopped
+    /// with a [`StopHandle`].
+    ///
+    /// Messages that were on the server before the start are skipped.
+    pub fn run<C: BotClient>(&mut self, client: &mut C) -> Result<(), ClientError> {
+        client.start()?;
+        while !self.stop.is_stopped() {
+            for line in client.poll()? {
+                let Some(reply) = self.dispatch(&line, client.username()) else {
+                    continue;
+                };
+                std::thread::sleep(self.reserve_reply());
+                if self.stop.is_stopped() {
+                    break;
+                }
+                client.reply(&self.format_reply(&reply, client.username()))?;
+            }
+            std::thread::sleep(self.poll_interval);
+        }
+        Ok(())
+    }
+
+    /// Runs the bot with an asynchronous client until an error occurs or it is stopped
+    /// with a [`StopHandle`].
+    ///
+    /// Messages that were on the server before the start are skipped.
+    #[cfg(any(feature = "async_client", feature = "async_wrac"))]
+    pub async fn run_async<C: AsyncBotClient>(
+        &mut self,
+        client: &mut C,
+    ) -> Result<(), ClientError> {
+        client.start().await?;
+        while !self.stop.is_stopped() {
+            for line in client.poll().await? {
+                let Some(reply) = self.dispatch(&line, client.username()) else {
+                    continue;
+                };
+                crate::runtime::sleep(self.reserve_reply()).await;
+                if self.stop.is_stopped() {
+                    break;
+                }
+                let reply = self.format_reply(&reply, client.username());
+                client.reply(&reply).await?;
+            }
+            crate::runtime::sleep(self.poll_interval).await;
+        }
+        Ok(())
+    }
+}
+
+/// A synchronous client that can run a [`Bot`].
+pub trait BotClient {
+    /// Returns the username the bot sends messages with.
+    fn username(&self) -> &str;
+    /// Connects if required and skips the messages that are already on the server.
+    fn start(&mut self) -> Result<(), ClientError>;
+    /// Fetches messages that arrived since the previous poll.
+    fn poll(&mut self) -> Result<Vec<String>, ClientError>;
+    /// Sends a formatted reply as it is, without replacing placeholders.
+    fn reply(&mut self, message: &str) -> Result<(), ClientError>;
+}
+
+/// An asynchronous client that can run a [`Bot`].
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+pub trait AsyncBotClient {
+    /// Returns the username the bot sends messages with.
+    fn username(&self) -> &str;
+    /// Connects if required and skips the messages that are already on the server.
+    fn start(&mut self) -> impl Future<Output = Result<(), ClientError>> + Send;
+    /// Fetches messages that arrived since the previous poll.
+    fn poll(&mut self) -> impl Future<Output = Result<Vec<String>, ClientError>> + Send;
+    /// Sends a formatted reply as it is, without replacing placeholders.
+    fn reply(&mut self, message: &str) -> impl Future<Output = Result<(), ClientError>> + Send;
+}
+
+#[cfg(feature = "client")]
+impl BotClient for crate::rac::RacClient {
+    fn username(&self) -> &str {
+        crate::rac::RacClient::username(self)
+    }
+
+    fn start(&mut self) -> Result<(), ClientError> {
+        self.fetch_messages_size()
+    }
+
+    fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        match self.fetch_new_messages() {
+            Ok(messages) => Ok(messages.into_iter().map(|m| m.into_owned()).collect()),
+            // Start over from the new history without replying to old messages.
+            Err(ClientError::HistoryReset) => {
+                self.fetch_messages_size()?;
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.send_custom_message(message)
+    }
+}
+
+#[cfg(feature = "wrac")]
+impl BotClient for crate::wrac::WClient {
+    fn username(&self) -> &str {
+        crate::wrac::WClient::username(self)
+    }
+
+    fn start(&mut self) -> Result<(), ClientError> {
+        self.prepare()?;
+        self.fetch_messages_size()
+    }
+
+    fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        match self.fetch_new_messages() {
+            Ok(messages) => Ok(messages.into_iter().map(|m| m.into_owned()).collect()),
+            // Start over from the new history without replying to old messages.
+            Err(ClientError::HistoryReset) => {
+                self.fetch_messages_size()?;
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.send_custom_message(message)
+    }
+}
+
+#[cfg(feature = "async_client")]
+impl AsyncBotClient for crate::async_rac::RacClient {
+    fn username(&self) -> &str {
+        crate::async_rac::RacClient::username(self)
+    }
+
+    async fn start(&mut self) -> Result<(), ClientError> {
+        self.fetch_messages_size().await
+    }
+
+    async fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        match self.fetch_new_messages().await {
+            Ok(messages) => Ok(messages.into_iter().map(|m| m.into_owned()).collect()),
+            // Start over from the new history without replying to old messages.
+            Err(ClientError::HistoryReset) => {
+                self.fetch_messages_size().await?;
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.send_custom_message(message).await
+    }
+}
+
+#[cfg(feature = "async_wrac")]
+impl AsyncBotClient for crate::async_wrac::WClient {
+    fn username(&self) -> &str {
+        crate::async_wrac::WClient::username(self)
+    }
+
+    async fn start(&mut self) -> Result<(), ClientError> {
+        self.prepare().await?;
+        self.fetch_messages_size().await
+    }
+
+    async fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        match self.fetch_new_messages().await {
+            Ok(messages) => Ok(messages.into_iter().map(|m| m.into_owned()).collect()),
+            // Start over from the new history without replying to old messages.
+            Err(ClientError::HistoryReset) => {
+                self.fetch_messages_size().await?;
+                Ok(Vec::new())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.send_custom_message(message).await
+    }
+}
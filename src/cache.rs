@@ -0,0 +1,283 @@
+use crate::shared::ClientError;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A file-based cache of the message history of a single server.
+///
+/// The cache stores fetched lines and the messages size they correspond to, so after a
+/// restart the client can continue with `fetch_new_messages` instead of downloading
+/// the whole history again. Every server gets its own pair of files in the cache
+/// directory: `<key>.log` with the lines and `<key>.meta` with the sizes.
+///
+/// A history that was cleared and grew past the cached size again can't be told apart by
+/// its size, so the final cached line serves as a fingerprint: the client resumes from the
+/// start of that line with [`Self::resume_size`], and [`Self::check_resume`] compares it with
+/// the first fetched line. Where the line starts is taken from the client when it is cached,
+/// because the server can separate lines with `\r\n`, empty lines or null bytes that are
+/// not cached.
+///
+/// # Example
+///
+/// ```no_run
+/// use rac_rs::cache::HistoryCache;
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::{ClientError, Credentials};
+///
+/// # fn run() -> Result<(), ClientError> {
+/// let mut client = RacClient::new("127.0.0.1:42666", Credentials::default(), false);
+/// let mut cache = HistoryCache::open("history", client.address())?;
+///
+/// // Continue from where the previous run stopped.
+/// client.update_current_messages_size(cache.resume_size());
+/// let resumed = client
+///     .fetch_new_messages()
+///     .and_then(|lines| cache.check_resume(&lines).map(<[_]>::to_vec));
+/// match resumed {
+///     Ok(lines) => cache.append(
+///         &lines,
+///         client.current_messages_size(),
+///         client.last_message_offset(),
+///     )?,
+///     Err(ClientError::HistoryReset) => {
+///         let lines = client.fetch_all_messages()?;
+///         cache.replace(
+///             &lines,
+///             client.current_messages_size(),
+///             client.last_message_offset(),
+///         )?;
+///     }
+///     Err(e) => return Err(e),
+/// }
+///
+/// for line in cache.lines() {
+///     println!("{line}");
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct HistoryCache {
+    /// Path to the file with cached lines.
+    log_path: PathBuf,
+    /// Path to the file with the messages size and the length of the log.
+    meta_path: PathBuf,
+    /// Cached lines.
+    lines: Vec<String>,
+    /// Messages size on the server that the cached lines correspond to.
+    messages_size: usize,
+    /// Where the final cached line starts on the server.
+    resume_size: usize,
+    /// Length of the log file confirmed by the meta file.
+    log_len: u64,
+}
+
+impl HistoryCache {
+    /// Opens the cache of the server at `address` in `dir`, creating the directory if needed.
+    ///
+    /// Lines that were written to the log but not confirmed in the meta file
+    /// (for example, because the process was killed in between) are discarded.
+    pub fn open(dir: impl AsRef<Path>, address: &str) -> Result<Self, ClientError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(ClientError::CacheError)?;
+
+        let key = Self::key(address);
+        let mut cache = Self {
+            log_path: dir.join(format!("{key}.log")),
+            meta_path: dir.join(format!("{key}.meta")),
+            lines: Vec::new(),
+            messages_size: 0,
+            resume_size: 0,
+            log_len: 0,
+        };
+
+        match fs::read_to_string(&cache.meta_path) {
+            Ok(meta) => {
+                (cache.messages_size, cache.resume_size, cache.log_len) = Self::parse_meta(&meta)?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(ClientError::CacheError(e)),
+        }
+
+        let mut log = match fs::read(&cache.log_path) {
+            Ok(log) => log,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(ClientError::CacheError(e)),
+        };
+        if log.len() as u64 != cache.log_len {
+            // Drop everything that wasn't confirmed by the meta file.
+            log.truncate(cache.log_len.min(log.len() as u64) as usize);
+            fs::write(&cache.log_path, &log).map_err(ClientError::CacheError)?;
+            cache.log_len = log.len() as u64;
+        }
+
+        cache.lines = String::from_utf8_lossy(&log)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        Ok(cache)
+    }
+
+    /// Returns the cached lines.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Returns the messages size that the cached lines correspond to.
+    ///
+    /// Pass it to `update_current_messages_size` to resume fetching new messages.
+    pub fn messages_size(&self) -> usize {
+        self.messages_size
+    }
+
+    /// Returns the messages size to resume fetching from, which is the start of the final
+    /// cached line on the server, or the messages size if nothing is cached.
+    ///
+    /// Pass it to `update_current_messages_size` and the lines fetched with `fetch_new_messages`
+    /// to [`Self::check_resume`].
+    pub fn resume_size(&self) -> usize {
+        self.resume_size
+    }
+
+    /// Checks the lines fetched from [`Self::resume_size`] against the final cached line
+    /// and returns the lines that are new.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::HistoryReset` if the first line is not the final cached line,
+    /// which means that the history on the server was replaced.
+    pub fn check_resume<'a, S: AsRef<str>>(&self, lines: &'a [S]) -> Result<&'a [S], ClientError> {
+        let Some(last) = self.lines.last() else {
+            return Ok(lines);
+        };
+        match lines.split_first() {
+            Some((first, new)) if first.as_ref() == last => Ok(new),
+            _ => Err(ClientError::HistoryReset),
+        }
+    }
+
+    /// Appends lines fetched with `fetch_new_messages` and stores the new messages size.
+    ///
+    /// `last_message_offset` is where the final line starts on the server, as returned by
+    /// `last_message_offset` of the client after the fetch.
+    pub fn append<S: AsRef<str>>(
+        &mut self,
+        lines: &[S],
+        messages_size: usize,
+        last_message_offset: usize,
+    ) -> Result<(), ClientError> {
+        let mut data = String::new();
+        for line in lines {
+            data.push_str(line.as_ref());
+            data.push('\n');
+        }
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_path)
+            .map_err(ClientError::CacheError)?;
+        log.write_all(data.as_bytes())
+            .and_then(|_| log.sync_data())
+            .map_err(ClientError::CacheError)?;
+
+        self.lines
+            .extend(lines.iter().map(|l| l.as_ref().to_string()));
+        self.log_len += data.len() as u64;
+        self.messages_size = messages_size;
+        if !lines.is_empty() {
+            self.resume_size = last_message_offset;
+        } else if self.lines.is_empty() {
+            self.resume_size = messages_size;
+        }
+        self.write_meta()
+    }
+
+    /// Replaces all cached lines, for example after `fetch_all_messages`.
+    ///
+    /// The arguments are the same as for [`Self::append`].
+    pub fn replace<S: AsRef<str>>(
+        &mut self,
+        lines: &[S],
+        messages_size: usize,
+        last_message_offset: usize,
+    ) -> Result<(), ClientError> {
+        self.clear()?;
+        self.append(lines, messages_size, last_message_offset)
+    }
+
+    /// Removes all cached lines and resets the messages size.
+    pub fn clear(&mut self) -> Result<(), ClientError> {
+        // Meta goes first, so a crash in between leaves an empty cache instead of a broken one.
+        self.lines.clear();
+        self.messages_size = 0;
+        self.resume_size = 0;
+        self.log_len = 0;
+        self.write_meta()?;
+        File::create(&self.log_path).map_err(ClientError::CacheError)?;
+        Ok(())
+    }
+
+    /// Writes the meta file atomically.
+    fn write_meta(&self) -> Result<(), ClientError> {
+        let tmp_path = self.meta_path.with_extension("meta.tmp");
+        fs::write(
+            &tmp_path,
+            format!(
+                "size={}\nresume={}\nlog_len={}\n",
+                self.messages_size, self.resume_size, self.log_len
+            ),
+        )
+        .and_then(|_| fs::rename(&tmp_path, &self.meta_path))
+        .map_err(ClientError::CacheError)
+    }
+
+    /// Parses the meta file into the messages size, the size to resume from and the length
+    /// of the log.
+    ///
+    /// Meta files without the size to resume from resume from the start of the history,
+    /// which `check_resume` reports as a reset, so the history is fetched again.
+    fn parse_meta(meta: &str) -> Result<(usize, usize, u64), ClientError> {
+        let mut size = None;
+        let mut resume = Some(0);
+        let mut log_len = None;
+        for line in meta.lines() {
+            match line.split_once('=') {
+                Some(("size", value)) => size = value.parse().ok(),
+                Some(("resume", value)) => resume = value.parse().ok(),
+                Some(("log_len", value)) => log_len = value.parse().ok(),
+                _ => {}
+            }
+        }
+        match (size, resume, log_len) {
+            (Some(size), Some(resume), Some(log_len)) => Ok((size, resume, log_len)),
+            _ => Err(ClientError::CacheError(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "corrupted cache meta file",
+            ))),
+        }
+    }
+
+    /// Builds a file name for the server address.
+    ///
+    /// The readable part is only for humans looking into the directory,
+    /// the hash keeps addresses that sanitize to the same string apart.
+    fn key(address: &str) -> String {
+        let readable: String = address
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+
+        // FNV-1a, because it is stable between runs and Rust versions.
+        let hash = address.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        format!("{readable}-{hash:016x}")
+    }
+}
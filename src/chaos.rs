@@ -0,0 +1,419 @@
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The faults that a [`Chaos`] injects into connections.
+///
+/// Rates are probabilities between `0.0` and `1.0`. The default injects nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Seed of the random faults. The same seed gives the same faults for the same requests.
+    pub seed: u64,
+    /// Longest delay before every read or write. The actual delay is random, up to this long.
+    pub latency: Duration,
+    /// Probability that a read returns only a random part of the data that is available.
+    pub fragment_rate: f64,
+    /// Probability that the connection is reset before a read or write.
+    pub disconnect_rate: f64,
+    /// Probability that null bytes follow the reply to the first request on a connection,
+    /// like servers written in C send them after the messages size or a status.
+    ///
+    /// Other replies, such as the messages, are never padded, because the padding would
+    /// shift the offsets that clients count. Clients don't pad TLS and WebSocket connections,
+    /// whose framing has no room for it.
+    pub null_padding_rate: f64,
+    /// Probability that the connection ends in the middle of the data received from the server.
+    pub truncate_rate: f64,
+}
+
+/// Injects faults into client connections, for testing how the code around a client
+/// behaves on a bad network.
+///
+/// Faults are injected below TLS, in the stream that the client reads and writes.
+/// Every connection gets its own sequence of faults, derived from the seed and the number
+/// of connections made before it, so a test that makes the same requests sees the same faults.
+///
+/// Writes are never fragmented, because the servers expect every command in a single piece.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::chaos::{Chaos, ChaosConfig};
+/// use std::io::Read;
+///
+/// let chaos = Chaos::new(ChaosConfig {
+///     seed: 42,
+///     fragment_rate: 1.0,
+///     ..Default::default()
+/// });
+///
+/// // Every read returns a random part of what is available.
+/// let mut stream = chaos.wrap(&b"12345"[..]);
+/// let mut received = Vec::new();
+/// let mut buf = [0u8; 16];
+/// let mut reads = 0;
+/// loop {
+///     let n = stream.read(&mut buf).unwrap();
+///     if n == 0 {
+///         break;
+///     }
+///     received.extend_from_slice(&buf[..n]);
+///     reads += 1;
+/// }
+/// assert_eq!(received, b"12345");
+/// assert!(reads > 1);
+/// ```
+///
+/// Clients use it when it is set with `update_chaos`:
+///
+/// ```
+/// use rac_rs::chaos::{Chaos, ChaosConfig};
+/// use rac_rs::rac::RacClient;
+/// use std::io::{Read, Write};
+/// use std::net::TcpListener;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let address = listener.local_addr().unwrap().to_string();
+/// let server = thread::spawn(move || {
+///     let (mut stream, _) = listener.accept().unwrap();
+///     let mut command = [0u8; 1];
+///     stream.read_exact(&mut command).unwrap();
+///     stream.write_all(b"22").unwrap();
+///     stream.read_exact(&mut command).unwrap();
+///     stream.write_all(b"Hello\nfrom a bad link\n").unwrap();
+/// });
+///
+/// let mut client = RacClient::new(&address, Default::default(), false);
+/// client.update_chaos(Some(Arc::new(Chaos::new(ChaosConfig {
+///     seed: 7,
+///     fragment_rate: 1.0,
+///     ..Default::default()
+/// }))));
+/// let messages = client.fetch_all_messages().unwrap();
+/// assert_eq!(messages, vec!["Hello", "from a bad link"]);
+/// server.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Chaos {
+    /// The faults to inject.
+    config: ChaosConfig,
+    /// Number of connections wrapped so far.
+    connections: AtomicU64,
+}
+
+impl Chaos {
+    /// Creates an injector of the faults in `config`.
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            config,
+            connections: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the faults that are injected.
+    pub fn config(&self) -> &ChaosConfig {
+        &self.config
+    }
+
+    /// Wraps a connection, so faults are injected into it.
+    pub fn wrap<S>(&self, stream: S) -> ChaosStream<S> {
+        self.wrap_with_padding(stream, true)
+    }
+
+    /// Wraps a connection without null padding, for protocols that frame their data.
+    #[cfg(any(
+        feature = "client",
+        feature = "async_client",
+        feature = "wrac",
+        feature = "async_wrac"
+    ))]
+    pub(crate) fn wrap_framed<S>(&self, stream: S) -> ChaosStream<S> {
+        self.wrap_with_padding(stream, false)
+    }
+
+    fn wrap_with_padding<S>(&self, stream: S, padding: bool) -> ChaosStream<S> {
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed);
+        ChaosStream {
+            inner: stream,
+            faults: Faults {
+                config: self.config.clone(),
+                rng: Rng(self.config.seed ^ connection.wrapping_mul(0xA076_1D64_78BD_642F)),
+                pending: Vec::new(),
+                position: 0,
+                reset: false,
+                ended: false,
+                padding,
+                padded: false,
+                requests: 0,
+            },
+            #[cfg(any(feature = "async_client", feature = "async_wrac"))]
+            delay: None,
+        }
+    }
+}
+
+/// A stream that faults are injected into, made by [`Chaos::wrap`].
+#[derive(Debug)]
+pub struct ChaosStream<S> {
+    /// The wrapped stream.
+    inner: S,
+    /// The state of the injected faults.
+    faults: Faults,
+    /// The delay of the read or write in progress.
+    #[cfg(any(feature = "async_client", feature = "async_wrac"))]
+    delay: Option<crate::runtime::Sleep>,
+}
+
+impl<S> ChaosStream<S> {
+    /// Returns a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the wrapped stream, dropping the data that wasn't read yet.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Read> Read for ChaosStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        std::thread::sleep(self.faults.start());
+        self.faults.check()?;
+        if !self.faults.has_pending() {
+            if self.faults.ended {
+                return Ok(0);
+            }
+            let n = self.inner.read(buf)?;
+            self.faults.receive(&buf[..n]);
+        }
+        Ok(self.faults.deliver(buf))
+    }
+}
+
+impl<S: Write> Write for ChaosStream<S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::thread::sleep(self.faults.start());
+        self.faults.check()?;
+        let n = self.inner.write(buf)?;
+        self.faults.requests += 1;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.faults.check()?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+mod async_stream {
+    use super::ChaosStream;
+    use crate::runtime;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::pin::Pin;
+    use std::task::{Context, Poll, ready};
+
+    impl<S> ChaosStream<S> {
+        /// Waits for the delay of the read or write in progress, starting it if needed.
+        fn poll_delay(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            let faults = &mut self.faults;
+            let delay = self
+                .delay
+                .get_or_insert_with(|| runtime::sleep(faults.start()));
+            Pin::new(delay).poll(cx)
+        }
+
+        /// Finishes the read or write in progress, so the next one gets a new delay.
+        fn finish<T>(&mut self, result: Poll<T>) -> Poll<T> {
+            if result.is_ready() {
+                self.delay = None;
+            }
+            result
+        }
+    }
+
+    impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            ready!(this.poll_delay(cx));
+            let result = this.poll_read_delayed(cx, buf);
+            this.finish(result)
+        }
+    }
+
+    impl<S: AsyncRead + Unpin> ChaosStream<S> {
+        /// Reads after the delay has passed.
+        fn poll_read_delayed(
+            &mut self,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            self.faults.check()?;
+            if !self.faults.has_pending() {
+                if self.faults.ended {
+                    return Poll::Ready(Ok(0));
+                }
+                let n = ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+                self.faults.receive(&buf[..n]);
+            }
+            Poll::Ready(Ok(self.faults.deliver(buf)))
+        }
+    }
+
+    impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_delay(cx));
+            let result = match this.faults.check() {
+                Ok(()) => Pin::new(&mut this.inner).poll_write(cx, buf),
+                Err(e) => Poll::Ready(Err(e)),
+            };
+            if let Poll::Ready(Ok(_)) = result {
+                this.faults.requests += 1;
+            }
+            this.finish(result)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            this.faults.check()?;
+            Pin::new(&mut this.inner).poll_flush(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_close(cx)
+        }
+    }
+}
+
+/// The state of the faults injected into one connection.
+#[derive(Debug)]
+struct Faults {
+    /// The faults to inject.
+    config: ChaosConfig,
+    /// Source of the random faults.
+    rng: Rng,
+    /// Data received from the wrapped stream that wasn't read yet.
+    pending: Vec<u8>,
+    /// Position of the first byte in `pending` that wasn't read yet.
+    position: usize,
+    /// Whether the connection was reset.
+    reset: bool,
+    /// Whether the connection ends after the pending data.
+    ended: bool,
+    /// Whether null padding may be added to the reply to the first request.
+    padding: bool,
+    /// Whether the pending data was padded, so it is delivered in one piece like servers
+    /// send it. Otherwise, the padding could be left over for the next reply.
+    padded: bool,
+    /// Number of writes so far, each of which is a request.
+    requests: u64,
+}
+
+impl Faults {
+    /// Starts a read or write, possibly resetting the connection,
+    /// and returns how long to wait before it.
+    fn start(&mut self) -> Duration {
+        if self.rng.chance(self.config.disconnect_rate) {
+            self.reset = true;
+        }
+        let max = self.config.latency.as_nanos().min(u64::MAX as u128) as u64;
+        Duration::from_nanos(self.rng.below(max.saturating_add(1)))
+    }
+
+    /// Fails if the connection was reset.
+    fn check(&self) -> std::io::Result<()> {
+        if self.reset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionReset,
+                "connection reset by chaos",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if there is received data that wasn't read yet.
+    fn has_pending(&self) -> bool {
+        self.position < self.pending.len()
+    }
+
+    /// Takes data received from the wrapped stream, possibly truncating or padding it.
+    fn receive(&mut self, data: &[u8]) {
+        self.pending.clear();
+        self.position = 0;
+        self.padded = false;
+        if data.is_empty() {
+            return;
+        }
+        if self.rng.chance(self.config.truncate_rate) {
+            let len = self.rng.below(data.len() as u64) as usize;
+            self.pending.extend_from_slice(&data[..len]);
+            self.ended = true;
+            return;
+        }
+        self.pending.extend_from_slice(data);
+        // Only the first piece of the first reply is padded, which is where servers
+        // written in C put the padding after the size or the status.
+        if self.padding && self.requests == 1 && self.rng.chance(self.config.null_padding_rate) {
+            let nulls = 1 + self.rng.below(8) as usize;
+            self.pending.resize(data.len() + nulls, 0);
+            self.padding = false;
+            self.padded = true;
+        }
+    }
+
+    /// Copies pending data into `buf`, possibly only a part of it, and returns its length.
+    fn deliver(&mut self, buf: &mut [u8]) -> usize {
+        let available = (self.pending.len() - self.position).min(buf.len());
+        let n = if available > 1 && !self.padded && self.rng.chance(self.config.fragment_rate) {
+            1 + self.rng.below(available as u64 - 1) as usize
+        } else {
+            available
+        };
+        buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+        self.position += n;
+        n
+    }
+}
+
+/// A small seeded random number generator (SplitMix64).
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+    /// Returns the next random number.
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a random number below `n`, or `0` if `n` is `0`.
+    fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next_u64() % n }
+    }
+
+    /// Returns `true` with the probability `rate`.
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0.0 && ((self.next_u64() >> 11) as f64) < rate * (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,291 @@
+use crate::shared::{ClientError, SecretString};
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use thiserror::Error;
+use zeroize::Zeroize;
+
+/// A source of the password that is resolved only when the client needs to authenticate.
+///
+/// Clients call the provider every time they register or send an authenticated message,
+/// so a rotated password is picked up without recreating the client. Async clients call
+/// it on the thread pool of the runtime for blocking work, so it can block.
+///
+/// # Example
+///
+/// ```no_run
+/// use rac_rs::credentials::CommandProvider;
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::Credentials;
+/// use std::sync::Arc;
+///
+/// let credentials = Credentials {
+///     username: "bot".to_string(),
+///     password: None,
+/// };
+/// let mut client = RacClient::new("127.0.0.1:42666", credentials, false);
+/// let provider = CommandProvider::new("pass", ["show", "rac/bot"]);
+/// client.update_password_provider(Some(Arc::new(provider)));
+///
+/// // The password manager is asked for the password here.
+/// client.send_message("<{username}> Hello everyone!").unwrap();
+/// // And its answer is reused here.
+/// client.send_message("<{username}> Anyone here?").unwrap();
+/// ```
+pub trait CredentialProvider: Debug + Send + Sync {
+    /// Returns the current password.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::CredentialError` if the password can't be obtained.
+    fn password(&self) -> Result<SecretString, ClientError>;
+
+    /// Forgets the cached password, if the provider caches it, so the next call
+    /// to [`Self::password`] obtains it again.
+    fn refresh(&self) {}
+}
+
+/// Reads the password from an environment variable.
+#[derive(Debug, Clone)]
+pub struct EnvProvider {
+    /// Name of the environment variable.
+    var: String,
+}
+
+impl EnvProvider {
+    /// Creates a provider that reads the environment variable `var`.
+    pub fn new(var: impl Into<String>) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+impl CredentialProvider for EnvProvider {
+    fn password(&self) -> Result<SecretString, ClientError> {
+        let password = std::env::var(&self.var).map_err(|_| {
+            ClientError::CredentialError(
+                format!("environment variable {} is not set", self.var).into(),
+            )
+        })?;
+        non_empty(password.into(), || {
+            format!("environment variable {}", self.var)
+        })
+    }
+}
+
+/// Reads the password from a file.
+///
+/// Trailing line breaks are removed. On Unix, the file is rejected if it can be read
+/// or written by anyone but its owner, like `ssh` does for private keys.
+#[derive(Debug, Clone)]
+pub struct FileProvider {
+    /// Path to the file with the password.
+    path: PathBuf,
+}
+
+impl FileProvider {
+    /// Creates a provider that reads the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Checks that only the owner has access to the file.
+    #[cfg(unix)]
+    fn check_permissions(&self) -> Result<(), ClientError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = std::fs::metadata(&self.path)
+            .map_err(|e| {
+                ClientError::CredentialError(Box::new(Failed::new(
+                    format!("failed to read {}", self.path.display()),
+                    e,
+                )))
+            })?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(ClientError::CredentialError(
+                format!(
+                    "{} is accessible by other users (mode {:o}), restrict it with `chmod 600`",
+                    self.path.display(),
+                    mode & 0o777
+                )
+                .into(),
+            ));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn check_permissions(&self) -> Result<(), ClientError> {
+        Ok(())
+    }
+}
+
+impl CredentialProvider for FileProvider {
+    fn password(&self) -> Result<SecretString, ClientError> {
+        self.check_permissions()?;
+        let content = SecretString::from(std::fs::read_to_string(&self.path).map_err(|e| {
+            ClientError::CredentialError(Box::new(Failed::new(
+                format!("failed to read {}", self.path.display()),
+                e,
+            )))
+        })?);
+        let password = content.expose().trim_end_matches(['\r', '\n']);
+        non_empty(password.into(), || self.path.display().to_string())
+    }
+}
+
+/// Runs an external command, such as a password manager, and takes the first line
+/// of its output as the password.
+///
+/// The command is run directly, without a shell. It is only run the first time the password
+/// is needed, because password managers can be slow or ask the user to unlock them, and the
+/// password is reused until [`CredentialProvider::refresh`] is called, for example after
+/// the server reports `ClientError::IncorrectPassword`.
+#[derive(Debug)]
+pub struct CommandProvider {
+    /// The program to run.
+    program: String,
+    /// Arguments for the program.
+    args: Vec<String>,
+    /// The password from the last successful run.
+    cached: Mutex<Option<SecretString>>,
+}
+
+impl CommandProvider {
+    /// Creates a provider that runs `program` with `args`.
+    pub fn new<I, S>(program: impl Into<String>, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Runs the command and reads the password from its output.
+    fn run(&self) -> Result<SecretString, ClientError> {
+        let mut output = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| {
+                ClientError::CredentialError(Box::new(Failed::new(
+                    format!("failed to run {}", self.program),
+                    e,
+                )))
+            })?;
+        let stdout = SecretString::from(String::from_utf8_lossy(&output.stdout).into_owned());
+        output.stdout.zeroize();
+
+        if !output.status.success() {
+            let mut message = format!("{} exited with {}", self.program, output.status);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                message.push_str(&format!(": {}", stderr.trim()));
+            }
+            return Err(ClientError::CredentialError(message.into()));
+        }
+        let password = stdout.expose().lines().next().unwrap_or_default();
+        non_empty(password.into(), || format!("output of {}", self.program))
+    }
+}
+
+impl Clone for CommandProvider {
+    /// Clones the command, but not the cached password.
+    fn clone(&self) -> Self {
+        Self::new(&self.program, &self.args)
+    }
+}
+
+impl CredentialProvider for CommandProvider {
+    fn password(&self) -> Result<SecretString, ClientError> {
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(password) = &*cached {
+            return Ok(password.clone());
+        }
+        let password = self.run()?;
+        *cached = Some(password.clone());
+        Ok(password)
+    }
+
+    fn refresh(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+/// An I/O error with a description of the step that failed.
+#[derive(Error, Debug)]
+#[error("{context}")]
+struct Failed {
+    /// What the provider was doing.
+    context: String,
+    /// The error that stopped it.
+    #[source]
+    source: std::io::Error,
+}
+
+impl Failed {
+    fn new(context: String, source: std::io::Error) -> Self {
+        Self { context, source }
+    }
+}
+
+/// Rejects empty passwords, naming the source in the error.
+fn non_empty(
+    password: SecretString,
+    source: impl FnOnce() -> String,
+) -> Result<SecretString, ClientError> {
+    if password.is_empty() {
+        return Err(ClientError::CredentialError(
+            format!("{} contains an empty password", source()).into(),
+        ));
+    }
+    Ok(password)
+}
+
+/// Returns the password a client should authenticate with.
+///
+/// The password set directly takes precedence over the provider. RACv1 servers have
+/// no user accounts, so there is never a password for them.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub(crate) fn resolve_password(
+    version: crate::shared::ProtocolVersion,
+    password: &Option<SecretString>,
+    provider: &Option<std::sync::Arc<dyn CredentialProvider>>,
+) -> Result<Option<SecretString>, ClientError> {
+    match (version, password, provider) {
+        (crate::shared::ProtocolVersion::V1, _, _) => Ok(None),
+        (_, Some(password), _) => Ok(Some(password.clone())),
+        (_, None, Some(provider)) => provider.password().map(Some),
+        (_, None, None) => Ok(None),
+    }
+}
+
+/// Returns the password an async client should authenticate with, calling the provider
+/// on the thread pool of the runtime for blocking work.
+///
+/// See [`resolve_password`].
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+pub(crate) async fn resolve_password_async(
+    version: crate::shared::ProtocolVersion,
+    password: &Option<SecretString>,
+    provider: &Option<std::sync::Arc<dyn CredentialProvider>>,
+) -> Result<Option<SecretString>, ClientError> {
+    match (version, password, provider) {
+        (crate::shared::ProtocolVersion::V2, None, Some(provider)) => {
+            let provider = provider.clone();
+            crate::runtime::spawn_blocking(move || provider.password().map(Some)).await
+        }
+        _ => resolve_password(version, password, &None),
+    }
+}
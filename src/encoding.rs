@@ -0,0 +1,135 @@
+use crate::shared::ClientError;
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+/// The text encoding of the messages on a server.
+///
+/// Fetched messages are decoded with it and sent messages are encoded with it.
+/// Usernames and passwords are always sent as UTF-8.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::encoding::TextEncoding;
+///
+/// let encoding: TextEncoding = "utf-8-strict".parse().unwrap();
+/// assert_eq!(encoding, TextEncoding::Utf8Strict);
+/// assert_eq!(encoding.decode("Привет 🦀".as_bytes()).unwrap(), "Привет 🦀");
+/// assert!(encoding.decode(&[0xcf, 0xf0, 0xe8]).is_none());
+/// assert_eq!(TextEncoding::Utf8Lossy.decode(&[b'a', 0xff]).unwrap(), "a\u{fffd}");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", try_from = "String"))]
+pub enum TextEncoding {
+    /// UTF-8. Fetching fails with `ClientError::ParseError` if the messages are not valid UTF-8.
+    Utf8Strict,
+    /// UTF-8, with invalid bytes replaced by `U+FFFD`.
+    #[default]
+    Utf8Lossy,
+    /// A legacy encoding from the Encoding Standard, such as `windows-1251` or `KOI8-R`.
+    ///
+    /// The encoding has to be compatible with ASCII, because messages are split into lines
+    /// by the byte of the line break. Characters missing from the encoding are sent as HTML
+    /// character references, such as `&#128512;`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rac_rs::encoding::TextEncoding;
+    ///
+    /// let encoding = TextEncoding::for_label("cp1251").unwrap();
+    /// assert_eq!(encoding.to_string(), "windows-1251");
+    /// assert_eq!(encoding.decode(&[0xcf, 0xf0, 0xe8]).unwrap(), "При");
+    /// assert_eq!(encoding.encode("При 🦀"), &b"\xcf\xf0\xe8 &#129408;"[..]);
+    /// ```
+    #[cfg(feature = "encoding")]
+    Legacy(&'static encoding_rs::Encoding),
+}
+
+impl TextEncoding {
+    /// Returns the encoding with the given label, if it is known and compatible with ASCII.
+    ///
+    /// `utf-8` is lossy UTF-8 and `utf-8-strict` is strict UTF-8. With the `encoding` feature,
+    /// all labels of the Encoding Standard are known, such as `cp1251` or `koi8-r`.
+    pub fn for_label(label: &str) -> Option<Self> {
+        let label = label.trim();
+        if label.eq_ignore_ascii_case("utf-8-strict") {
+            return Some(TextEncoding::Utf8Strict);
+        }
+        if label.eq_ignore_ascii_case("utf-8") || label.eq_ignore_ascii_case("utf8") {
+            return Some(TextEncoding::Utf8Lossy);
+        }
+        #[cfg(feature = "encoding")]
+        if let Some(encoding) = encoding_rs::Encoding::for_label(label.as_bytes())
+            && encoding.is_ascii_compatible()
+        {
+            if encoding == encoding_rs::UTF_8 {
+                return Some(TextEncoding::Utf8Lossy);
+            }
+            return Some(TextEncoding::Legacy(encoding));
+        }
+        None
+    }
+
+    /// Returns the name of the encoding, which is also one of its labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8Strict => "utf-8-strict",
+            TextEncoding::Utf8Lossy => "utf-8",
+            #[cfg(feature = "encoding")]
+            TextEncoding::Legacy(encoding) => encoding.name(),
+        }
+    }
+
+    /// Decodes `bytes`, or returns `None` if they are not valid UTF-8 for strict UTF-8.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, str>> {
+        match self {
+            TextEncoding::Utf8Strict => std::str::from_utf8(bytes).ok().map(Cow::Borrowed),
+            TextEncoding::Utf8Lossy => Some(String::from_utf8_lossy(bytes)),
+            #[cfg(feature = "encoding")]
+            TextEncoding::Legacy(encoding) => Some(encoding.decode_without_bom_handling(bytes).0),
+        }
+    }
+
+    /// Encodes `text` to the bytes sent to the server.
+    pub fn encode<'a>(&self, text: &'a str) -> Cow<'a, [u8]> {
+        match self {
+            TextEncoding::Utf8Strict | TextEncoding::Utf8Lossy => Cow::Borrowed(text.as_bytes()),
+            #[cfg(feature = "encoding")]
+            TextEncoding::Legacy(encoding) => encoding.encode(text).0,
+        }
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for TextEncoding {
+    type Err = ClientError;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        Self::for_label(label)
+            .ok_or_else(|| ClientError::InvalidConfig(format!("unknown text encoding `{label}`")))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<TextEncoding> for String {
+    fn from(encoding: TextEncoding) -> Self {
+        encoding.name().to_string()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<String> for TextEncoding {
+    type Error = ClientError;
+
+    fn try_from(label: String) -> Result<Self, Self::Error> {
+        label.parse()
+    }
+}
@@ -0,0 +1,266 @@
+use crate::cache::HistoryCache;
+use crate::encoding::TextEncoding;
+use crate::message::{ChatMessage, ClientKind};
+use crate::shared::ClientError;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Format of exported history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON object per line.
+    JsonLines,
+    /// Comma-separated values with a header row.
+    Csv,
+    /// Raw lines exactly as they were received from the server.
+    PlainText,
+}
+
+impl ExportFormat {
+    /// Picks the format by a file extension (`jsonl`, `csv` or `txt`).
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Some(ExportFormat::JsonLines),
+            "csv" => Some(ExportFormat::Csv),
+            "txt" | "log" => Some(ExportFormat::PlainText),
+            _ => None,
+        }
+    }
+}
+
+/// A single exported message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportRecord {
+    /// Estimated position of the line in the server history, in bytes.
+    ///
+    /// See [`ExportRecord::from_lines`] for when it is exact.
+    pub offset: usize,
+    /// The timestamp added by the server, if any.
+    pub timestamp: Option<String>,
+    /// The author of the message, if it could be detected.
+    pub author: Option<String>,
+    /// The client that sent the message, if it could be detected.
+    pub client: Option<ClientKind>,
+    /// The text of the message.
+    pub body: String,
+    /// The line exactly as it was received from the server.
+    pub raw: String,
+}
+
+impl ExportRecord {
+    /// Builds records from fetched lines.
+    ///
+    /// `start_offset` is the messages size the lines were fetched from: `0` for
+    /// `fetch_all_messages` and the previous `current_messages_size` for `fetch_new_messages`.
+    /// Offsets of the following lines count every line in `encoding`, the encoding of the
+    /// server, and assume that it ends with `\n`.
+    ///
+    /// The offsets are estimates made from the decoded lines. They are exact for a history of
+    /// lines that end with `\n` and were decoded without loss. Bytes that the client replaced
+    /// while decoding, `\r\n` line ends and empty lines or null bytes that the client removed
+    /// are counted differently than on the server. `current_messages_size` and
+    /// `last_message_offset` of the client are exact.
+    pub fn from_lines<S: AsRef<str>>(
+        lines: &[S],
+        start_offset: usize,
+        encoding: TextEncoding,
+    ) -> Vec<Self> {
+        let mut offset = start_offset;
+        lines
+            .iter()
+            .map(|line| {
+                let line = line.as_ref();
+                let message = ChatMessage::parse(line);
+                let record = Self {
+                    offset,
+                    timestamp: message.timestamp,
+                    author: message.author,
+                    client: message.client,
+                    body: message.body,
+                    raw: message.raw,
+                };
+                offset = record.end_offset(encoding);
+                record
+            })
+            .collect()
+    }
+
+    /// Builds records from all lines of the history cache, as they are in `encoding` on the server.
+    pub fn from_cache(cache: &HistoryCache, encoding: TextEncoding) -> Vec<Self> {
+        Self::from_lines(cache.lines(), 0, encoding)
+    }
+
+    /// Returns the offset right after the line of the record, counted in `encoding`.
+    pub fn end_offset(&self, encoding: TextEncoding) -> usize {
+        self.offset + encoding.encode(&self.raw).len() + 1
+    }
+}
+
+/// Writes records in the given format.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::encoding::TextEncoding;
+/// use rac_rs::export::{ExportFormat, ExportRecord, export, import};
+///
+/// let lines = ["<alice> Hello", "<bob> Hi, \"alice\""];
+/// let records = ExportRecord::from_lines(&lines, 0, TextEncoding::Utf8Lossy);
+///
+/// let mut csv = Vec::new();
+/// export(&records, ExportFormat::Csv, &mut csv).unwrap();
+///
+/// let imported = import(ExportFormat::Csv, csv.as_slice()).unwrap();
+/// assert_eq!(imported, records);
+/// assert_eq!(imported[1].offset, 14);
+/// ```
+pub fn export<W: Write>(
+    records: &[ExportRecord],
+    format: ExportFormat,
+    mut writer: W,
+) -> Result<(), ClientError> {
+    match format {
+        ExportFormat::JsonLines => {
+            for record in records {
+                serde_json::to_writer(&mut writer, record)
+                    .map_err(|e| ClientError::ExportError(e.into()))?;
+                writer
+                    .write_all(b"\n")
+                    .map_err(|e| ClientError::ExportError(e.into()))?;
+            }
+        }
+        ExportFormat::Csv => {
+            let mut csv = csv::Writer::from_writer(&mut writer);
+            for record in records {
+                csv.serialize(record)
+                    .map_err(|e| ClientError::ExportError(e.into()))?;
+            }
+            csv.flush()
+                .map_err(|e| ClientError::ExportError(e.into()))?;
+        }
+        ExportFormat::PlainText => {
+            for record in records {
+                writeln!(writer, "{}", record.raw)
+                    .map_err(|e| ClientError::ExportError(e.into()))?;
+            }
+        }
+    }
+    writer
+        .flush()
+        .map_err(|e| ClientError::ExportError(e.into()))
+}
+
+/// Reads records in the given format.
+///
+/// Plain text has nothing but raw lines, so messages are parsed again and offsets
+/// are counted from zero in UTF-8.
+pub fn import<R: Read>(format: ExportFormat, reader: R) -> Result<Vec<ExportRecord>, ClientError> {
+    match format {
+        ExportFormat::JsonLines => BufReader::new(reader)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+            .map(|line| {
+                let line = line.map_err(|e| ClientError::ExportError(e.into()))?;
+                serde_json::from_str(&line).map_err(|e| ClientError::ExportError(e.into()))
+            })
+            .collect(),
+        ExportFormat::Csv => csv::Reader::from_reader(reader)
+            .deserialize()
+            .map(|record| record.map_err(|e| ClientError::ExportError(e.into())))
+            .collect(),
+        ExportFormat::PlainText => {
+            let lines = BufReader::new(reader)
+                .lines()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| ClientError::ExportError(e.into()))?;
+            let lines: Vec<_> = lines.into_iter().filter(|l| !l.is_empty()).collect();
+            Ok(ExportRecord::from_lines(&lines, 0, TextEncoding::default()))
+        }
+    }
+}
+
+/// Seeds the history cache with imported records, replacing its contents.
+///
+/// `messages_size` is where the client continues fetching new messages: the messages size
+/// of the cache or the client that the records were exported from, or
+/// [`ExportRecord::end_offset`] of the last record if it isn't known.
+///
+/// The offsets of the records are estimates, so they are not used to find where the final
+/// line starts on the server. The cache resumes from `messages_size` instead, and the first
+/// [`HistoryCache::check_resume`] fails with `ClientError::HistoryReset`, after which the
+/// whole history is fetched once and replaces the seeded lines. Until then, the seeded lines
+/// can be read and searched offline.
+pub fn seed_cache(
+    cache: &mut HistoryCache,
+    records: &[ExportRecord],
+    messages_size: usize,
+) -> Result<(), ClientError> {
+    let lines: Vec<&str> = records.iter().map(|r| r.raw.as_str()).collect();
+    cache.replace(&lines, messages_size, messages_size)
+}
+
+/// A record that [`send_records`] couldn't send.
+#[derive(Debug)]
+pub struct FailedRecord {
+    /// The position of the record in the records that were sent.
+    pub index: usize,
+    /// Why the record couldn't be sent.
+    pub error: ClientError,
+}
+
+/// Seeds a server with records, sending their raw lines in order with `send`, and returns
+/// the records that failed.
+///
+/// A failed record doesn't stop the import. With the default `InputPolicy::Reject`,
+/// lines with control characters fail with `ClientError::InvalidInput`. Set
+/// `InputPolicy::Escape` on the client to send them escaped instead.
+///
+/// Every record is still tried when the server can't be reached, so check the first
+/// failure before importing again.
+///
+/// # Example
+///
+#[cfg_attr(feature = "client", doc = "```no_run")]
+#[cfg_attr(not(feature = "client"), doc = "```ignore")]
+/// use rac_rs::export::{ExportFormat, import, send_records};
+/// use rac_rs::rac::RacClient;
+///
+/// # fn run() -> Result<(), rac_rs::shared::ClientError> {
+/// let client = RacClient::new("127.0.0.1:42666", Default::default(), false);
+/// let file = std::fs::File::open("history.jsonl").unwrap();
+/// let records = import(ExportFormat::JsonLines, file)?;
+/// for failed in send_records(&records, |line| client.send_custom_message(line)) {
+///     eprintln!("{}: {}", records[failed.index].raw, failed.error);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn send_records<F>(records: &[ExportRecord], mut send: F) -> Vec<FailedRecord>
+where
+    F: FnMut(&str) -> Result<(), ClientError>,
+{
+    records
+        .iter()
+        .enumerate()
+        .filter_map(|(index, record)| {
+            send(&record.raw)
+                .err()
+                .map(|error| FailedRecord { index, error })
+        })
+        .collect()
+}
+
+/// Works like [`send_records`], but with an asynchronous `send` that takes the line by value.
+pub async fn send_records_async<F, Fut>(records: &[ExportRecord], mut send: F) -> Vec<FailedRecord>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<(), ClientError>>,
+{
+    let mut failed = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        if let Err(error) = send(record.raw.clone()).await {
+            failed.push(FailedRecord { index, error });
+        }
+    }
+    failed
+}
@@ -0,0 +1,282 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long RAC clients wait for more digits of the messages size by default.
+///
+/// The size has no terminator and the protocol doesn't say how long it is, so when it arrives
+/// without a null byte or whitespace after it, the client waits this long for the rest before
+/// it considers the size complete. Every such size read takes at least this long.
+///
+/// The wait can't tell a pause in the middle of the size from its end. If the server sends
+/// the digits further apart than this, the size is cut short without an error: the client
+/// takes `35` for `350`, fetches too few messages and reads the digits that arrive late as
+/// the start of the messages. Clients on slow links should wait longer, see
+/// `RacClient::update_size_settle_time`.
+pub const SIZE_SETTLE_TIME: Duration = Duration::from_millis(20);
+
+/// The longest messages size that is accepted, in digits. Enough for `u64::MAX`.
+const MAX_SIZE_DIGITS: usize = 20;
+
+/// An error in the framing of a response.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FrameError {
+    /// The connection ended before the response was complete.
+    #[error("Response ended before it was complete")]
+    Incomplete,
+
+    /// The messages size is not a number.
+    #[error("Invalid messages size: {0}")]
+    InvalidSize(String),
+
+    /// The reply to an authenticated send or a registration is neither empty nor a status.
+    #[error("Invalid status: {0}")]
+    InvalidStatus(String),
+}
+
+/// Decodes the size of the message history, sent by RAC servers as ASCII digits.
+///
+/// The size can arrive in several pieces, and servers written in C pad it with null bytes.
+/// Null bytes and whitespace before the digits are skipped, and the first one after them
+/// ends the size. Without it, the size is complete when the server closes the connection
+/// or stops sending, which the caller reports with [`SizeDecoder::finish`].
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::framing::SizeDecoder;
+///
+/// let response = b"\0\x0012345\0\0\0";
+///
+/// // However the response is split, the same size is decoded.
+/// for split in 0..=response.len() {
+///     let (first, second) = response.split_at(split);
+///     let mut decoder = SizeDecoder::new();
+///     let size = match decoder.feed(first).unwrap() {
+///         Some(size) => size,
+///         None => decoder.feed(second).unwrap().unwrap(),
+///     };
+///     assert_eq!(size, 12345);
+/// }
+///
+/// // Without padding, the size is complete when the server stops sending.
+/// let mut decoder = SizeDecoder::new();
+/// for byte in b"12345" {
+///     assert_eq!(decoder.feed(&[*byte]).unwrap(), None);
+/// }
+/// assert_eq!(decoder.finish().unwrap(), 12345);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SizeDecoder {
+    /// Digits received so far.
+    digits: String,
+    /// The size, once it is complete.
+    size: Option<usize>,
+}
+
+impl SizeDecoder {
+    /// Creates a decoder that hasn't received anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if some digits were received, so the size may already be complete.
+    pub fn has_digits(&self) -> bool {
+        !self.digits.is_empty()
+    }
+
+    /// Feeds bytes received from the server.
+    ///
+    /// Returns the size once a byte that ends it was received. Bytes after it are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FrameError::InvalidSize` if a byte can't be a part of the size.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Option<usize>, FrameError> {
+        if self.size.is_some() {
+            return Ok(self.size);
+        }
+        for (i, &byte) in bytes.iter().enumerate() {
+            match byte {
+                b'0'..=b'9' if self.digits.len() < MAX_SIZE_DIGITS => {
+                    self.digits.push(byte as char)
+                }
+                0 | b'\t' | b'\n' | b'\r' | b' ' if self.digits.is_empty() => {}
+                0 | b'\t' | b'\n' | b'\r' | b' ' => return self.finish().map(Some),
+                _ => {
+                    return Err(FrameError::InvalidSize(format!(
+                        "{}{}",
+                        self.digits,
+                        String::from_utf8_lossy(&bytes[i..])
+                    )));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Decodes a size that arrived whole, such as in a WebSocket message.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FrameError::Incomplete` if there are no digits and
+    /// `FrameError::InvalidSize` if the size is not a number.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rac_rs::framing::SizeDecoder;
+    ///
+    /// assert_eq!(SizeDecoder::decode(b"1024\n").unwrap(), 1024);
+    /// assert!(SizeDecoder::decode(b"").is_err());
+    /// ```
+    pub fn decode(bytes: &[u8]) -> Result<usize, FrameError> {
+        let mut decoder = Self::new();
+        match decoder.feed(bytes)? {
+            Some(size) => Ok(size),
+            None => decoder.finish(),
+        }
+    }
+
+    /// Completes the size after the server closed the connection or stopped sending.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FrameError::Incomplete` if no digits were received and
+    /// `FrameError::InvalidSize` if the size doesn't fit into `usize`.
+    pub fn finish(&mut self) -> Result<usize, FrameError> {
+        if let Some(size) = self.size {
+            return Ok(size);
+        }
+        if self.digits.is_empty() {
+            return Err(FrameError::Incomplete);
+        }
+        let size = self
+            .digits
+            .parse()
+            .map_err(|_| FrameError::InvalidSize(self.digits.clone()))?;
+        self.size = Some(size);
+        Ok(size)
+    }
+}
+
+/// Decodes the reply to an authenticated send or a registration.
+///
+/// RACv2 servers reply with a single status byte if the request failed, and close the
+/// connection without a reply if it succeeded. Null bytes before the status are skipped.
+/// WRAC servers reply with a WebSocket message either way, which [`StatusDecoder::decode`]
+/// decodes.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::framing::StatusDecoder;
+///
+/// let mut decoder = StatusDecoder::new();
+/// assert_eq!(decoder.feed(b"\0"), None);
+/// assert_eq!(decoder.feed(b"\x02\0"), Some(0x02));
+///
+/// // The server closed the connection without a status.
+/// let decoder = StatusDecoder::new();
+/// assert_eq!(decoder.finish(), None);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StatusDecoder {
+    /// The status, once it was received.
+    status: Option<u8>,
+}
+
+impl StatusDecoder {
+    /// Creates a decoder that hasn't received anything yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds bytes received from the server.
+    ///
+    /// Returns the status once it was received. Bytes after it are ignored.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<u8> {
+        if self.status.is_none() {
+            self.status = bytes.iter().copied().find(|&byte| byte != 0);
+        }
+        self.status
+    }
+
+    /// Returns the status after the server closed the connection,
+    /// or `None` if it didn't send one, which means success.
+    pub fn finish(&self) -> Option<u8> {
+        self.status
+    }
+
+    /// Decodes a reply that arrived whole, such as in a WebSocket message.
+    ///
+    /// An empty reply means success and is decoded as `None`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `FrameError::InvalidStatus` if the reply is not empty but has no status,
+    /// for example if it only has null bytes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rac_rs::framing::StatusDecoder;
+    ///
+    /// assert_eq!(StatusDecoder::decode(b"").unwrap(), None);
+    /// assert_eq!(StatusDecoder::decode(b"\x01").unwrap(), Some(0x01));
+    /// assert!(StatusDecoder::decode(b"\0").is_err());
+    /// ```
+    pub fn decode(bytes: &[u8]) -> Result<Option<u8>, FrameError> {
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        match Self::new().feed(bytes) {
+            Some(status) => Ok(Some(status)),
+            None => Err(FrameError::InvalidStatus(format!("{bytes:?}"))),
+        }
+    }
+}
+
+/// Collects a payload of a known length, such as the messages that follow the size.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::framing::PayloadDecoder;
+///
+/// let mut decoder = PayloadDecoder::new(11);
+/// assert_eq!(decoder.feed(b"Hello"), None);
+/// assert_eq!(decoder.remaining(), 6);
+/// assert_eq!(decoder.feed(b", world"), Some(b"Hello, worl".to_vec()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct PayloadDecoder {
+    /// Bytes received so far.
+    data: Vec<u8>,
+    /// Bytes that are still missing.
+    remaining: usize,
+}
+
+impl PayloadDecoder {
+    /// Creates a decoder for a payload of `len` bytes.
+    pub fn new(len: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            remaining: len,
+        }
+    }
+
+    /// Returns the number of bytes that are still missing.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Feeds bytes received from the server.
+    ///
+    /// Returns the payload once all of it was received. Bytes after it are ignored.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let take = bytes.len().min(self.remaining);
+        self.data.extend_from_slice(&bytes[..take]);
+        self.remaining -= take;
+        (self.remaining == 0).then(|| std::mem::take(&mut self.data))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::shared::ClientError;
+use std::borrow::Cow;
+
+/// What clients do with messages that contain line breaks or other control characters.
+///
+/// Every line of the history is a message, so a line break in a message would add lines
+/// that look like messages of other users. Tabs are allowed.
+///
+/// Usernames and passwords are always rejected if they contain line breaks or null bytes,
+/// because these separate the fields of a request.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::input::InputPolicy;
+/// use rac_rs::shared::ClientError;
+///
+/// let error = InputPolicy::Reject.apply("<bob> hi\n<admin> bye").unwrap_err();
+/// assert!(matches!(error, ClientError::InvalidInput(_)));
+///
+/// let escaped = InputPolicy::Escape.apply("Привет\n🦀\u{1b}[31m").unwrap();
+/// assert_eq!(escaped, vec![r"Привет\n🦀\u{1b}[31m"]);
+///
+/// let lines = InputPolicy::Split.apply("first\r\n\nsecond\n").unwrap();
+/// assert_eq!(lines, vec!["first", "second"]);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum InputPolicy {
+    /// Fail with `ClientError::InvalidInput`.
+    #[default]
+    Reject,
+    /// Replace control characters with escapes, such as `\n` or `\u{1b}`.
+    Escape,
+    /// Send every line as a message of its own, skipping empty lines,
+    /// and escape the other control characters.
+    ///
+    /// All lines are checked and encoded before the first one is sent, but the send is not
+    /// atomic: if sending a line fails, the lines before it stay on the server and the error
+    /// is `ClientError::PartialSend`.
+    Split,
+}
+
+impl InputPolicy {
+    /// Returns the messages to send instead of `message`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidInput` for `Reject` if the message has a control character.
+    pub fn apply<'a>(&self, message: &'a str) -> Result<Vec<Cow<'a, str>>, ClientError> {
+        match self {
+            InputPolicy::Reject => match message.chars().find(|&c| is_forbidden(c)) {
+                Some(c) => Err(ClientError::InvalidInput(format!("message contains {c:?}"))),
+                None => Ok(vec![Cow::Borrowed(message)]),
+            },
+            InputPolicy::Escape => Ok(vec![escape(message)]),
+            InputPolicy::Split => Ok(message
+                .split(['\r', '\n'])
+                .filter(|line| !line.is_empty())
+                .map(escape)
+                .collect()),
+        }
+    }
+}
+
+/// Checks whether `c` can't be a part of a message.
+fn is_forbidden(c: char) -> bool {
+    c.is_control() && c != '\t'
+}
+
+/// Replaces the control characters in `message` with escapes.
+fn escape(message: &str) -> Cow<'_, str> {
+    if !message.contains(is_forbidden) {
+        return Cow::Borrowed(message);
+    }
+    let mut escaped = String::with_capacity(message.len() + 8);
+    for c in message.chars() {
+        if is_forbidden(c) {
+            escaped.extend(c.escape_debug());
+        } else {
+            escaped.push(c);
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Checks that `value` can be sent as the `field` of a request.
+///
+/// # Errors
+///
+/// Returns `ClientError::InvalidInput` if it contains a line break or a null byte.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub(crate) fn check_field(field: &str, value: &str) -> Result<(), ClientError> {
+    if value.contains(['\n', '\r', '\0']) {
+        return Err(ClientError::InvalidInput(format!(
+            "{field} contains a line break or a null byte"
+        )));
+    }
+    Ok(())
+}
@@ -16,6 +16,18 @@
 //!
 //! By default, all of these features are enabled.
 //!
+//! Optional features:
+//!
+//! - `bot` - Framework for chat bots with prefix commands and regex triggers.
+//! - `encoding` - Legacy text encodings, such as CP1251 and KOI8-R, for messages on old servers.
+//! - `export` - Export and import of the message history in JSON Lines, CSV and plain text.
+//! - `profiles` - Named server profiles loaded from a TOML file.
+//! - `serde` - `Serialize`/`Deserialize` for credentials, client configuration and parsed messages.
+//! - `search` - Indexed search over the message history by text, regex, author and client.
+//! - `tracing` - Spans for connecting, TLS and WebSocket handshakes and every request, with byte
+//!   counts, and events for stripped null bytes, parse errors and unexpected responses.
+//! - `tui` - The `rac-tui` binary, an interactive terminal chat client.
+//!
 //! # Example
 //!
 //! ```no_run
@@ -25,11 +37,11 @@
 //! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let credentials = Credentials {
 //!         username: "test_user".to_string(),
-//!         password: Some("password123".to_string()),
+//!         password: Some("password123".into()),
 //!     };
 //!
 //!     let mut client = RacClient::new(
-//!         "127.0.0.1:42666".to_string(),
+//!         "127.0.0.1:42666",
 //!         credentials,
 //!         false
 //!     );
@@ -61,9 +73,65 @@ pub mod rac;
 #[cfg(feature = "async_client")]
 pub mod async_rac;
 
+/// Contains decoders that reassemble RAC responses from arbitrarily split reads.
+pub mod framing;
+
+/// Contains the requests of the protocol, shared by all clients and written without any I/O.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub mod protocol;
+
+/// Contains the fault injector for testing clients on a bad network.
+#[cfg(feature = "chaos")]
+pub mod chaos;
+
+/// Contains the streams that clients run the protocol over and the connectors that open them.
+pub mod transport;
+
 /// Contains shared type and utilities that's used across the library.
 pub mod shared;
 
+/// Contains helpers for the optional `tracing` instrumentation.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+mod trace;
+
+/// Contains the timers and sockets of the runtime that async clients run on.
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+mod runtime;
+
+#[cfg(all(
+    any(feature = "async_client", feature = "async_wrac"),
+    not(any(feature = "tokio", feature = "smol"))
+))]
+compile_error!("async clients need a runtime, enable the `tokio` or `smol` feature");
+
+/// Contains the parser that splits chat lines into author, text and the client that sent them.
+pub mod message;
+
+/// Contains the text encodings that messages are stored in on servers.
+pub mod encoding;
+
+/// Contains the checks of usernames, passwords and messages before they are sent.
+pub mod input;
+
+/// Contains providers that obtain the password from the environment, a file or a command.
+pub mod credentials;
+
+/// Contains the hook for collecting request metrics from clients.
+pub mod metrics;
+
+/// Contains the file-based cache of the message history that survives restarts.
+pub mod cache;
+
 /// Contains the implementation of the WRAC protocol, which is a WebSocket-based version of the RAC protocol.
 #[cfg(feature = "wrac")]
 pub mod wrac;
@@ -71,3 +139,23 @@ pub mod wrac;
 /// Contains the async implementation of the WRAC protocol.
 #[cfg(feature = "async_wrac")]
 pub mod async_wrac;
+
+/// Contains the detection of the protocol and capabilities of a server.
+#[cfg(all(feature = "client", feature = "wrac"))]
+pub mod probe;
+
+/// Contains a framework for chat bots with command routing on top of the clients.
+#[cfg(feature = "bot")]
+pub mod bot;
+
+/// Contains the full-text search over the message history.
+#[cfg(feature = "search")]
+pub mod search;
+
+/// Contains named server profiles loaded from a TOML file.
+#[cfg(feature = "profiles")]
+pub mod profile;
+
+/// Contains exporters and importers of the message history in JSON Lines, CSV and plain text.
+#[cfg(feature = "export")]
+pub mod export;
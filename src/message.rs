@@ -0,0 +1,201 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A RAC client that can be recognized by the signature it puts in front of the author.
+///
+/// RAC has no structured message format, so popular clients mark their messages
+/// with a few unique characters before the `<username>` part. This is the only
+/// way to tell which client sent the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClientKind {
+    /// Messages sent by bRAC (`리㹰<username> text`).
+    #[cfg_attr(feature = "serde", serde(rename = "bRAC"))]
+    Brac,
+    /// Messages sent by CRAB (`═══<username> text`).
+    #[cfg_attr(feature = "serde", serde(rename = "CRAB"))]
+    Crab,
+    /// Messages sent by Mefidroniy (`°ʘ<username> text`).
+    #[cfg_attr(feature = "serde", serde(rename = "Mefidroniy"))]
+    Mefidroniy,
+    /// Messages in the plain `<username> text` format without any client signature.
+    #[cfg_attr(feature = "serde", serde(rename = "unsigned"))]
+    Unsigned,
+}
+
+impl ClientKind {
+    /// All known signatures paired with their clients.
+    const SIGNATURES: [(&'static str, ClientKind); 3] = [
+        ("\u{B9AC}\u{3E70}", ClientKind::Brac),
+        ("\u{2550}\u{2550}\u{2550}", ClientKind::Crab),
+        ("\u{00B0}\u{0298}", ClientKind::Mefidroniy),
+    ];
+
+    /// Returns the signature that the client puts before the author, if any.
+    pub fn signature(&self) -> &'static str {
+        Self::SIGNATURES
+            .iter()
+            .find(|(_, kind)| kind == self)
+            .map(|(signature, _)| *signature)
+            .unwrap_or("")
+    }
+
+    /// Returns a human-readable name of the client.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientKind::Brac => "bRAC",
+            ClientKind::Crab => "CRAB",
+            ClientKind::Mefidroniy => "Mefidroniy",
+            ClientKind::Unsigned => "unsigned",
+        }
+    }
+}
+
+impl FromStr for ClientKind {
+    type Err = String;
+
+    /// Parses the name of the client, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            ClientKind::Brac,
+            ClientKind::Crab,
+            ClientKind::Mefidroniy,
+            ClientKind::Unsigned,
+        ]
+        .into_iter()
+        .find(|kind| kind.name().eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("Unknown client: {s}"))
+    }
+}
+
+impl fmt::Display for ClientKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A single line of the chat history split into its parts.
+///
+/// Parsing never fails: lines that don't follow any known convention are kept
+/// as a message without an author, with the whole line as its body.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::message::{ChatMessage, ClientKind};
+///
+/// let message = ChatMessage::parse("[01.06.2025 12:00] \u{B9AC}\u{3E70}<alice> Hello!");
+/// assert_eq!(message.timestamp.as_deref(), Some("01.06.2025 12:00"));
+/// assert_eq!(message.author.as_deref(), Some("alice"));
+/// assert_eq!(message.body, "Hello!");
+/// assert_eq!(message.client, Some(ClientKind::Brac));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChatMessage {
+    /// The line exactly as it was received from the server.
+    pub raw: String,
+    /// The timestamp added by the server in front of the message, if any.
+    pub timestamp: Option<String>,
+    /// The author of the message, if it could be detected.
+    pub author: Option<String>,
+    /// The text of the message without the timestamp and the author.
+    pub body: String,
+    /// The client that sent the message, if it could be detected.
+    pub client: Option<ClientKind>,
+}
+
+impl ChatMessage {
+    /// Parses a line received from the server.
+    pub fn parse(line: &str) -> Self {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+
+        // Servers may put the date of the message in square brackets at the beginning.
+        let mut timestamp = None;
+        if let Some(stripped) = rest.strip_prefix('[')
+            && let Some((date, tail)) = stripped.split_once("] ")
+        {
+            timestamp = Some(date.to_string());
+            rest = tail;
+        }
+
+        // Some servers also add the address of the sender in curly brackets.
+        if rest.starts_with('{')
+            && let Some((_, tail)) = rest.split_once("} ")
+        {
+            rest = tail;
+        }
+
+        let (client, signed) = ClientKind::SIGNATURES
+            .iter()
+            .find_map(|(signature, kind)| rest.strip_prefix(signature).map(|tail| (*kind, tail)))
+            .unwrap_or((ClientKind::Unsigned, rest));
+
+        if let Some((author, body)) = Self::split_author(signed) {
+            return Self {
+                raw: line.to_string(),
+                timestamp,
+                author: Some(author.to_string()),
+                body: body.to_string(),
+                client: Some(client),
+            };
+        }
+
+        Self {
+            raw: line.to_string(),
+            timestamp,
+            author: None,
+            body: rest.to_string(),
+            client: None,
+        }
+    }
+
+    /// Splits `<username> text` into the username and the text.
+    fn split_author(text: &str) -> Option<(&str, &str)> {
+        let (author, body) = text.strip_prefix('<')?.split_once('>')?;
+        if author.is_empty() || author.chars().any(char::is_whitespace) {
+            return None;
+        }
+        Some((author, body.strip_prefix(' ').unwrap_or(body)))
+    }
+}
+
+impl fmt::Display for ChatMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+/// Fills in a message format, replacing `{username}` with `username` and `{text}` with `text`.
+///
+/// Both placeholders are replaced in one pass, so placeholders in `text` are kept as they are.
+/// Send the result with `send_custom_message`, which doesn't replace `{username}` again.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::message::format_message;
+///
+/// let message = format_message("<{username}> {text}", "alice", "I typed {username}");
+/// assert_eq!(message, "<alice> I typed {username}");
+/// ```
+pub fn format_message(format: &str, username: &str, text: &str) -> String {
+    let mut message = String::with_capacity(format.len() + username.len() + text.len());
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        message.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(tail) = rest.strip_prefix("{username}") {
+            message.push_str(username);
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("{text}") {
+            message.push_str(text);
+            rest = tail;
+        } else {
+            message.push('{');
+            rest = &rest[1..];
+        }
+    }
+    message.push_str(rest);
+    message
+}
@@ -0,0 +1,140 @@
+use crate::shared::ClientError;
+use std::fmt::Debug;
+use std::time::Duration;
+
+pub use crate::shared::Operation;
+
+/// Receives measurements from clients, to be forwarded to a metrics system.
+///
+/// Every method gets the address of the server the client talks to, so one recorder
+/// can be shared between clients of different servers. Methods are called on the hot
+/// path and should return quickly.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::metrics::{MetricsRecorder, Operation};
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::ClientError;
+/// use std::sync::Arc;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+///
+/// #[derive(Debug, Default)]
+/// struct Counters {
+///     requests: AtomicUsize,
+///     errors: AtomicUsize,
+/// }
+///
+/// impl MetricsRecorder for Counters {
+///     fn request(
+///         &self,
+///         server: &str,
+///         operation: Operation,
+///         latency: Duration,
+///         error: Option<&ClientError>,
+///     ) {
+///         self.requests.fetch_add(1, Ordering::Relaxed);
+///         if let Some(error) = error {
+///             self.errors.fetch_add(1, Ordering::Relaxed);
+///             let variant = error.variant_name();
+///             println!("{server} {operation} failed after {latency:?}: {variant}");
+///         }
+///     }
+/// }
+///
+/// let counters = Arc::new(Counters::default());
+/// let mut client = RacClient::new("127.0.0.1:1", Default::default(), false);
+/// client.update_metrics(Some(counters.clone()));
+///
+/// // Nothing listens on port 1, so the request fails while connecting,
+/// // which is reported once, as `Operation::Connect`.
+/// assert!(client.fetch_messages_size().is_err());
+/// assert_eq!(counters.requests.load(Ordering::Relaxed), 1);
+/// assert_eq!(counters.errors.load(Ordering::Relaxed), 1);
+/// ```
+pub trait MetricsRecorder: Debug + Send + Sync {
+    /// Called when an operation finishes, with its duration and the error if it failed.
+    ///
+    /// Requests open a connection first, which is reported as a separate
+    /// [`Operation::Connect`] before the request itself. If the connection can't be opened,
+    /// only the failed [`Operation::Connect`] is reported, so every failure is counted once.
+    fn request(
+        &self,
+        server: &str,
+        operation: Operation,
+        latency: Duration,
+        error: Option<&ClientError>,
+    );
+
+    /// Called when a response is received, with its size in bytes.
+    fn bytes_received(&self, server: &str, operation: Operation, bytes: usize) {
+        let _ = (server, operation, bytes);
+    }
+
+    /// Called when a WebSocket client replaces an established connection with a new one.
+    ///
+    /// RAC clients open a new connection for every request, so they never reconnect.
+    fn reconnect(&self, server: &str) {
+        let _ = server;
+    }
+}
+
+/// Reports a finished operation to the recorder, if there is one, and passes the result through.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub(crate) fn record_request<T>(
+    metrics: &Option<std::sync::Arc<dyn MetricsRecorder>>,
+    server: &str,
+    operation: Operation,
+    started: std::time::Instant,
+    result: Result<T, ClientError>,
+) -> Result<T, ClientError> {
+    if let Some(metrics) = metrics
+        && (operation == Operation::Connect || !result.as_ref().is_err_and(failed_to_connect))
+    {
+        metrics.request(server, operation, started.elapsed(), result.as_ref().err());
+    }
+    result
+}
+
+/// Checks whether the error happened while opening a connection, which was already
+/// reported as [`Operation::Connect`].
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+fn failed_to_connect(error: &ClientError) -> bool {
+    matches!(
+        error,
+        ClientError::ConnectionError { .. }
+            | ClientError::TlsHandshakeError { .. }
+            | ClientError::WsHandshakeError { .. }
+            | ClientError::TlsInitializationError(_)
+            | ClientError::InvalidAddress { .. }
+    )
+}
+
+/// Reports the size of a received response to the recorder, if there is one.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub(crate) fn record_bytes(
+    metrics: &Option<std::sync::Arc<dyn MetricsRecorder>>,
+    server: &str,
+    operation: Operation,
+    bytes: usize,
+) {
+    if let Some(metrics) = metrics {
+        metrics.bytes_received(server, operation, bytes);
+    }
+}
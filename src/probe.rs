@@ -0,0 +1,254 @@
+use crate::rac::RacClient;
+use crate::shared::{ClientConfig, ClientError, Credentials, ErrorKind, Protocol, ProtocolVersion};
+use crate::trace::trace_event;
+use crate::wrac::WClient;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long [`probe`] waits for every connection, read or write.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a server supports, as found out by [`probe`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The address that was probed.
+    pub address: String,
+    /// The protocol the server speaks.
+    pub protocol: Protocol,
+    /// Whether the server is behind TLS.
+    pub use_tls: bool,
+    /// The version of the protocol, RACv2 if authenticated sends are supported.
+    pub version: ProtocolVersion,
+    /// Whether the server accepts messages from registered users (RACv2).
+    pub authenticated_send: bool,
+    /// Whether users can register on the server (RACv2).
+    ///
+    /// Registration is part of RACv2, so it is assumed to be available together with
+    /// authenticated sends. The probe never registers a user to check it.
+    pub registration: bool,
+    /// The time it took to connect and get the size of the message history.
+    pub latency: Duration,
+    /// The size of the message history on the server, in bytes.
+    pub messages_size: usize,
+}
+
+impl Capabilities {
+    /// Builds the settings for a client of the probed server.
+    pub fn to_config(&self, credentials: Credentials) -> ClientConfig {
+        ClientConfig {
+            address: self.address.clone(),
+            protocol: self.protocol,
+            version: self.version,
+            use_tls: self.use_tls,
+            credentials,
+            timeout: None,
+            ..Default::default()
+        }
+    }
+}
+
+/// Detects the protocol of the server at `address`, whether it uses TLS and whether
+/// it supports RACv2 authentication, waiting up to [`DEFAULT_TIMEOUT`] for every step.
+///
+/// Plain RAC, RAC over TLS, WRAC and WRAC over TLS are tried in this order, and the
+/// first one that returns the size of the message history wins. If `address` is a
+/// `ws://` or `wss://` URL, only WRAC with the given scheme is tried.
+///
+/// Authentication is detected by sending an empty message as a user that doesn't exist,
+/// which RACv2 servers reject without adding anything to the history. A server that
+/// accepts the message doesn't check users, and one that answers with a status sends don't
+/// have doesn't know the request, so both are reported as RACv1.
+///
+/// The probe can't know how the server handles the authenticated send before it is made.
+/// RACv1 servers don't know the request, and a server that handles it like an unauthenticated
+/// send adds the username of the probe, `rac-rs-probe-` followed by a number, and the password
+/// `probe` to the history, each as a line of its own. Where that is not acceptable, build
+/// a [`ClientConfig`] with the version you already know instead of probing.
+///
+/// # Errors
+///
+/// Returns the error of the first combination that got past connecting, or of the first
+/// combination if the server can't be reached at all. Once the size of the message history
+/// was fetched, any answer to the authenticated send other than a missing user or a success,
+/// such as a timeout, is returned as the error of that combination.
+///
+/// # Example
+///
+/// ```no_run
+/// use rac_rs::probe::probe;
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::{Credentials, Protocol};
+/// use rac_rs::wrac::WClient;
+///
+/// # fn run() -> Result<(), rac_rs::shared::ClientError> {
+/// let capabilities = probe("127.0.0.1:42666")?;
+/// println!(
+///     "{:?}, TLS: {}, {}, latency: {:?}",
+///     capabilities.protocol,
+///     capabilities.use_tls,
+///     capabilities.version,
+///     capabilities.latency,
+/// );
+///
+/// let config = capabilities.to_config(Credentials {
+///     username: "test_user".to_string(),
+///     password: None,
+/// });
+/// match config.protocol {
+///     Protocol::Rac => {
+///         let mut client = RacClient::from_config(&config);
+///         client.fetch_all_messages()?;
+///     }
+///     Protocol::Wrac => {
+///         let mut client = WClient::from_config(&config);
+///         client.prepare()?;
+///         client.fetch_all_messages()?;
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn probe(address: &str) -> Result<Capabilities, ClientError> {
+    probe_with_timeout(address, DEFAULT_TIMEOUT)
+}
+
+/// Works like [`probe`], but waits up to `timeout` for every connection, read or write.
+///
+/// A server that doesn't answer a protocol it doesn't speak makes the probe wait for
+/// the whole timeout before the next combination is tried.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::probe::probe_with_timeout;
+/// use rac_rs::shared::ErrorKind;
+/// use std::time::Duration;
+///
+/// // Nothing listens on port 1.
+/// let error = probe_with_timeout("127.0.0.1:1", Duration::from_secs(1)).unwrap_err();
+/// assert_eq!(error.kind(), ErrorKind::Connect);
+/// ```
+pub fn probe_with_timeout(address: &str, timeout: Duration) -> Result<Capabilities, ClientError> {
+    let candidates: &[(Protocol, bool)] = if address.starts_with("ws://") {
+        &[(Protocol::Wrac, false)]
+    } else if address.starts_with("wss://") {
+        &[(Protocol::Wrac, true)]
+    } else {
+        &[
+            (Protocol::Rac, false),
+            (Protocol::Rac, true),
+            (Protocol::Wrac, false),
+            (Protocol::Wrac, true),
+        ]
+    };
+
+    let mut error: Option<ClientError> = None;
+    for &(protocol, use_tls) in candidates {
+        let config = ClientConfig {
+            address: address.to_string(),
+            protocol,
+            use_tls,
+            timeout: Some(timeout),
+            ..Default::default()
+        };
+        let result = match protocol {
+            Protocol::Rac => probe_rac(&config),
+            Protocol::Wrac => probe_wrac(&config),
+        };
+        match result {
+            Ok(capabilities) => return Ok(capabilities),
+            Err(e) => {
+                trace_event!(debug, ?protocol, use_tls, error = %e, "probe failed");
+                let better = error.as_ref().is_none_or(|first| {
+                    first.kind() == ErrorKind::Connect && e.kind() != ErrorKind::Connect
+                });
+                if better {
+                    error = Some(e);
+                }
+            }
+        }
+    }
+    Err(error.expect("at least one combination is tried"))
+}
+
+/// Probes the server with a RAC client.
+fn probe_rac(config: &ClientConfig) -> Result<Capabilities, ClientError> {
+    let mut client = RacClient::from_config(config);
+    let started = Instant::now();
+    client.fetch_messages_size()?;
+    let latency = started.elapsed();
+
+    client.update_credentials(probe_credentials());
+    let authenticated_send = detect_authentication(client.send_custom_message(""))?;
+
+    Ok(Capabilities {
+        address: config.address.clone(),
+        protocol: Protocol::Rac,
+        use_tls: config.use_tls,
+        version: version(authenticated_send),
+        authenticated_send,
+        registration: authenticated_send,
+        latency,
+        messages_size: client.current_messages_size(),
+    })
+}
+
+/// Probes the server with a WRAC client.
+fn probe_wrac(config: &ClientConfig) -> Result<Capabilities, ClientError> {
+    let mut client = WClient::from_config(config);
+    let started = Instant::now();
+    client.prepare()?;
+    client.fetch_messages_size()?;
+    let latency = started.elapsed();
+
+    client.update_credentials(probe_credentials());
+    let result = client.send_custom_message("");
+    let messages_size = client.current_messages_size();
+    client.reset();
+    let authenticated_send = detect_authentication(result)?;
+
+    Ok(Capabilities {
+        address: config.address.clone(),
+        protocol: Protocol::Wrac,
+        use_tls: config.use_tls,
+        version: version(authenticated_send),
+        authenticated_send,
+        registration: authenticated_send,
+        latency,
+        messages_size,
+    })
+}
+
+/// Checks the result of an authenticated send as a user that doesn't exist, returning
+/// whether the server supports authentication.
+///
+/// # Errors
+///
+/// Returns the error of the send if it doesn't tell the versions apart.
+fn detect_authentication(result: Result<(), ClientError>) -> Result<bool, ClientError> {
+    match result {
+        Err(ClientError::UserDoesNotExist) => Ok(true),
+        Ok(()) | Err(ClientError::UnsupportedOperation { .. }) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the protocol version that matches the support of authenticated sends.
+fn version(authenticated_send: bool) -> ProtocolVersion {
+    if authenticated_send {
+        ProtocolVersion::V2
+    } else {
+        ProtocolVersion::V1
+    }
+}
+
+/// Returns credentials of a user that is very unlikely to exist on the server.
+fn probe_credentials() -> Credentials {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    Credentials {
+        username: format!("rac-rs-probe-{nanos}"),
+        password: Some("probe".into()),
+    }
+}
@@ -0,0 +1,356 @@
+use crate::credentials::{CommandProvider, CredentialProvider, EnvProvider, FileProvider};
+use crate::encoding::TextEncoding;
+use crate::input::InputPolicy;
+use crate::shared::{
+    ClientConfig, ClientError, Credentials, Protocol, ProtocolVersion, SecretString,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A named server profile as written in the profiles file.
+///
+/// The password can be written directly with `password` or obtained when the client
+/// authenticates from an environment variable (`password_env`), a file readable only
+/// by its owner (`password_file`) or the output of a command (`password_command`).
+/// Only one of these can be set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// The address of the server, `host:port` or a `ws(s)://` URL for WRAC.
+    pub address: String,
+    /// The protocol of the server, `rac` (default) or `wrac`.
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// The version of the protocol, `v2` (default) or `v1`.
+    #[serde(default)]
+    pub version: ProtocolVersion,
+    /// Whether to use TLS encryption.
+    #[serde(default)]
+    pub tls: bool,
+    /// The username for authentication.
+    pub username: String,
+    /// The password for authentication.
+    pub password: Option<SecretString>,
+    /// Name of the environment variable with the password.
+    pub password_env: Option<String>,
+    /// Path to the file with the password.
+    pub password_file: Option<PathBuf>,
+    /// The command that prints the password, the program followed by its arguments.
+    pub password_command: Option<Vec<String>>,
+    /// Timeout in seconds for connecting and for every read or write.
+    pub timeout: Option<f64>,
+    /// The encoding of the messages on the server, `utf-8` (default), `utf-8-strict`
+    /// or, with the `encoding` feature, a legacy one such as `cp1251` or `koi8-r`.
+    #[serde(default)]
+    pub encoding: TextEncoding,
+    /// What to do with messages that contain control characters,
+    /// `reject` (default), `escape` or `split`.
+    #[serde(default)]
+    pub input_policy: InputPolicy,
+}
+
+impl Profile {
+    /// Checks that the profile can be used to create a client.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidConfig` describing the first problem found.
+    pub fn validate(&self) -> Result<(), ClientError> {
+        let invalid = |message: &str| Err(ClientError::InvalidConfig(message.to_string()));
+
+        if self.username.is_empty() {
+            return invalid("username is empty");
+        }
+        let sources = [
+            self.password.is_some(),
+            self.password_env.is_some(),
+            self.password_file.is_some(),
+            self.password_command.is_some(),
+        ];
+        if sources.into_iter().filter(|&set| set).count() > 1 {
+            return invalid(
+                "only one of `password`, `password_env`, `password_file` and `password_command` can be set",
+            );
+        }
+        if self.version == ProtocolVersion::V1 && sources.contains(&true) {
+            return invalid("passwords can't be used with RACv1");
+        }
+        if self.password_command.as_ref().is_some_and(Vec::is_empty) {
+            return invalid("`password_command` is empty");
+        }
+        // `try_from_secs_f64` also rejects values too large for a `Duration`, which
+        // `to_config` would otherwise panic on.
+        if let Some(timeout) = self.timeout
+            && (timeout <= 0.0 || Duration::try_from_secs_f64(timeout).is_err())
+        {
+            return invalid("timeout must be a positive number of seconds");
+        }
+
+        let is_url = self.address.starts_with("ws://") || self.address.starts_with("wss://");
+        match self.protocol {
+            Protocol::Rac if is_url => invalid("WebSocket URLs can only be used with WRAC"),
+            _ if is_url => Ok(()),
+            _ => match self.address.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(()),
+                _ => invalid("address must be in the `host:port` format"),
+            },
+        }
+    }
+
+    /// Builds the settings for a client.
+    ///
+    /// Only the password written directly in the profile is included. Other sources
+    /// are resolved lazily by the provider from [`Profile::password_provider`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidConfig` if the profile is invalid.
+    pub fn to_config(&self) -> Result<ClientConfig, ClientError> {
+        self.validate()?;
+
+        Ok(ClientConfig {
+            address: self.address.clone(),
+            protocol: self.protocol,
+            version: self.version,
+            use_tls: self.tls,
+            credentials: Credentials {
+                username: self.username.clone(),
+                password: self.password.clone(),
+            },
+            timeout: self.timeout.map(Duration::from_secs_f64),
+            encoding: self.encoding,
+            input_policy: self.input_policy,
+        })
+    }
+
+    /// Returns the provider for the password source of the profile, if it has one.
+    pub fn password_provider(&self) -> Option<Arc<dyn CredentialProvider>> {
+        if let Some(var) = &self.password_env {
+            return Some(Arc::new(EnvProvider::new(var)));
+        }
+        if let Some(path) = &self.password_file {
+            return Some(Arc::new(FileProvider::new(path)));
+        }
+        match self.password_command.as_deref() {
+            Some([program, args @ ..]) => Some(Arc::new(CommandProvider::new(program, args))),
+            _ => None,
+        }
+    }
+}
+
+/// A synchronous client created from a profile.
+#[derive(Debug)]
+pub enum ProfileClient {
+    /// A client for a RAC server.
+    #[cfg(feature = "client")]
+    Rac(crate::rac::RacClient),
+    /// A client for a WRAC server.
+    #[cfg(feature = "wrac")]
+    Wrac(Box<crate::wrac::WClient>),
+}
+
+/// An asynchronous client created from a profile.
+#[derive(Debug)]
+pub enum AsyncProfileClient {
+    /// A client for a RAC server.
+    #[cfg(feature = "async_client")]
+    Rac(crate::async_rac::RacClient),
+    /// A client for a WRAC server.
+    #[cfg(feature = "async_wrac")]
+    Wrac(Box<crate::async_wrac::WClient>),
+}
+
+/// The contents of the profiles file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    /// Name of the profile to use when none is specified.
+    default: Option<String>,
+    /// Profiles by name.
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+}
+
+/// A set of named server profiles loaded from a TOML file.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::profile::{ProfileClient, Profiles};
+/// use rac_rs::shared::ProtocolVersion;
+///
+/// let profiles = Profiles::from_toml(r#"
+///     default = "local"
+///
+///     [profiles.local]
+///     address = "127.0.0.1:42666"
+///     username = "alice"
+///     version = "v1"
+///     timeout = 5
+///
+///     [profiles.public]
+///     address = "wss://chat.example.com/"
+///     protocol = "wrac"
+///     username = "alice"
+///     password_env = "RAC_PASSWORD"
+///
+///     [profiles.bot]
+///     address = "127.0.0.1:42666"
+///     username = "bot"
+///     password_command = ["pass", "show", "rac/bot"]
+/// "#).unwrap();
+///
+/// assert_eq!(profiles.names().collect::<Vec<_>>(), ["bot", "local", "public"]);
+/// match profiles.client(profiles.default_name().unwrap()).unwrap() {
+///     ProfileClient::Rac(client) => {
+///         assert_eq!(client.username(), "alice");
+///         assert_eq!(client.version(), ProtocolVersion::V1);
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Profiles {
+    /// Name of the profile to use when none is specified.
+    default: Option<String>,
+    /// Profiles by name.
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    /// Loads and validates profiles from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let content = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            ClientError::InvalidConfig(format!("failed to read {}: {e}", path.as_ref().display()))
+        })?;
+        Self::from_toml(&content)
+    }
+
+    /// Parses and validates profiles from a TOML string.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidConfig` if the TOML is malformed, a profile is invalid
+    /// or the default profile doesn't exist.
+    pub fn from_toml(content: &str) -> Result<Self, ClientError> {
+        let file: ProfilesFile =
+            toml::from_str(content).map_err(|e| ClientError::InvalidConfig(e.to_string()))?;
+
+        for (name, profile) in &file.profiles {
+            profile.validate().map_err(|e| match e {
+                ClientError::InvalidConfig(message) => {
+                    ClientError::InvalidConfig(format!("profile `{name}`: {message}"))
+                }
+                e => e,
+            })?;
+        }
+        if let Some(default) = &file.default
+            && !file.profiles.contains_key(default)
+        {
+            return Err(ClientError::InvalidConfig(format!(
+                "default profile `{default}` does not exist"
+            )));
+        }
+
+        Ok(Self {
+            default: file.default,
+            profiles: file.profiles,
+        })
+    }
+
+    /// Returns the names of all profiles in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    /// Returns the name of the default profile, if set.
+    pub fn default_name(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    /// Returns the profile with the given name.
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.get(name)
+    }
+
+    /// Builds the settings for a client from the profile with the given name.
+    pub fn config(&self, name: &str) -> Result<ClientConfig, ClientError> {
+        self.existing(name)?.to_config()
+    }
+
+    /// Returns the profile with the given name or an error if it doesn't exist.
+    fn existing(&self, name: &str) -> Result<&Profile, ClientError> {
+        self.get(name)
+            .ok_or_else(|| ClientError::InvalidConfig(format!("profile `{name}` does not exist")))
+    }
+
+    /// Creates a synchronous client for the protocol of the profile with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidConfig` if the profile doesn't exist or the client
+    /// for its protocol is disabled by crate features.
+    pub fn client(&self, name: &str) -> Result<ProfileClient, ClientError> {
+        let profile = self.existing(name)?;
+        let config = profile.to_config()?;
+        match config.protocol {
+            #[cfg(feature = "client")]
+            Protocol::Rac => {
+                let mut client = crate::rac::RacClient::from_config(&config);
+                client.update_password_provider(profile.password_provider());
+                Ok(ProfileClient::Rac(client))
+            }
+            #[cfg(feature = "wrac")]
+            Protocol::Wrac => {
+                let mut client = crate::wrac::WClient::from_config(&config);
+                client.update_password_provider(profile.password_provider());
+                Ok(ProfileClient::Wrac(Box::new(client)))
+            }
+            #[cfg(not(all(feature = "client", feature = "wrac")))]
+            protocol => Err(Self::disabled(protocol)),
+        }
+    }
+
+    /// Creates an asynchronous client for the protocol of the profile with the given name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidConfig` if the profile doesn't exist or the client
+    /// for its protocol is disabled by crate features.
+    pub fn async_client(&self, name: &str) -> Result<AsyncProfileClient, ClientError> {
+        let profile = self.existing(name)?;
+        let config = profile.to_config()?;
+        match config.protocol {
+            #[cfg(feature = "async_client")]
+            Protocol::Rac => {
+                let mut client = crate::async_rac::RacClient::from_config(&config);
+                client.update_password_provider(profile.password_provider());
+                Ok(AsyncProfileClient::Rac(client))
+            }
+            #[cfg(feature = "async_wrac")]
+            Protocol::Wrac => {
+                let mut client = crate::async_wrac::WClient::from_config(&config);
+                client.update_password_provider(profile.password_provider());
+                Ok(AsyncProfileClient::Wrac(Box::new(client)))
+            }
+            #[cfg(not(all(feature = "async_client", feature = "async_wrac")))]
+            protocol => Err(Self::disabled(protocol)),
+        }
+    }
+
+    /// Builds the error for a protocol which client is disabled by crate features.
+    #[cfg(not(all(
+        feature = "client",
+        feature = "wrac",
+        feature = "async_client",
+        feature = "async_wrac"
+    )))]
+    fn disabled(protocol: Protocol) -> ClientError {
+        ClientError::InvalidConfig(format!(
+            "support for {protocol:?} is not enabled in crate features"
+        ))
+    }
+}
@@ -0,0 +1,624 @@
+use crate::encoding::TextEncoding;
+use crate::input;
+use crate::shared::{ClientError, Operation, Protocol, ProtocolVersion, SecretString};
+use crate::trace::{self, trace_event, trace_record};
+use std::borrow::Cow;
+use zeroize::Zeroizing;
+
+/// The response that a client reads after sending a request to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expect {
+    /// Nothing, the server doesn't reply.
+    Nothing,
+    /// The size of the message history.
+    Size,
+    /// A payload of the given length in bytes.
+    ///
+    /// WRAC clients read one WebSocket message, whatever its length.
+    Payload(usize),
+    /// The status of an authenticated send or a registration.
+    Status,
+}
+
+/// A response read from the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// The size of the message history.
+    Size(usize),
+    /// The payload, such as the messages that follow the size.
+    Payload(Vec<u8>),
+    /// The status byte, or `None` if the server didn't send one, which means success.
+    Status(Option<u8>),
+}
+
+/// What a client does next to complete a request.
+#[derive(Debug)]
+pub enum Step<T> {
+    /// Send `data` to the server and read the response described by `expect`.
+    ///
+    /// For RAC, every request runs on its own connection, so all of its data goes over
+    /// the same stream. For WRAC, every `data` is sent as one binary WebSocket message.
+    Send {
+        /// Bytes to send. They are wiped from memory on drop, as they may contain a password.
+        data: Zeroizing<Vec<u8>>,
+        /// The response to read after sending.
+        expect: Expect,
+    },
+    /// The request is complete.
+    Done(T),
+}
+
+impl<T> Step<T> {
+    /// Builds a step that sends `data`.
+    fn send(data: impl Into<Vec<u8>>, expect: Expect) -> Self {
+        Step::Send {
+            data: Zeroizing::new(data.into()),
+            expect,
+        }
+    }
+}
+
+/// A request to the server, written without any I/O.
+///
+/// All clients run requests the same way: they call [`Request::resume`] with `None`,
+/// then send the data of every [`Step::Send`] and pass what they read back to `resume`,
+/// until they get [`Step::Done`]. So the protocol is implemented once for the synchronous,
+/// async, RAC and WRAC clients, which only move bytes.
+///
+/// # Example
+///
+/// Fetching new messages, with responses that a client would read from the server:
+///
+/// ```
+/// use rac_rs::protocol::{Expect, FetchNew, Request, Response, Step};
+/// use rac_rs::shared::Protocol;
+///
+/// let mut request = FetchNew::new(Protocol::Rac, 6);
+///
+/// let Step::Send { data, expect } = request.resume(None).unwrap() else { panic!() };
+/// assert_eq!((data.as_slice(), expect), (&b"\x00"[..], Expect::Size));
+///
+/// let step = request.resume(Some(Response::Size(13))).unwrap();
+/// let Step::Send { data, expect } = step else { panic!() };
+/// assert_eq!((data.as_slice(), expect), (&b"\x026"[..], Expect::Payload(7)));
+///
+/// let step = request.resume(Some(Response::Payload(b"Hello!\n".to_vec()))).unwrap();
+/// let Step::Done(messages) = step else { panic!() };
+/// assert_eq!(messages.size, 13);
+/// assert_eq!(messages.messages, vec!["Hello!"]);
+/// ```
+pub trait Request {
+    /// The result of the request.
+    type Output;
+
+    /// Returns the operation that the request performs.
+    fn operation(&self) -> Operation;
+
+    /// Advances the request with the response to the previous step.
+    ///
+    /// The response is `None` for the first step and after steps that expect nothing.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error that the server reported, `ClientError::HistoryReset` if the history
+    /// shrank, or `ClientError::UnexpectedResponse` if the response is not the expected one.
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<Self::Output>, ClientError>;
+}
+
+/// Messages fetched from the server.
+///
+/// Only complete messages, the ones that end with a line break, are returned. The bytes after
+/// the last line break are part of a message that the server is still writing, and may end in
+/// the middle of a character, so `size` stops before them and the next fetch reads them again.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::protocol::{FetchAll, FetchNew, Request, Response, Step};
+/// use rac_rs::shared::Protocol;
+///
+/// // The history ends in the middle of the emoji, which takes 4 bytes.
+/// let history = "Привет\n🦀 Rust\n".as_bytes();
+/// let mut request = FetchAll::new();
+/// request.resume(None).unwrap();
+/// request.resume(Some(Response::Size(20))).unwrap();
+/// let step = request.resume(Some(Response::Payload(history[..20].to_vec()))).unwrap();
+/// let Step::Done(messages) = step else { panic!() };
+/// assert_eq!(messages.messages, vec!["Привет"]);
+/// assert_eq!(messages.size, "Привет\n".len());
+/// assert_eq!(messages.last_message, 0);
+///
+/// // Once the message is complete, the next fetch returns it whole.
+/// let mut request = FetchNew::new(Protocol::Rac, messages.size);
+/// request.resume(None).unwrap();
+/// request.resume(Some(Response::Size(history.len()))).unwrap();
+/// let rest = history[messages.size..].to_vec();
+/// let Step::Done(messages) = request.resume(Some(Response::Payload(rest))).unwrap() else {
+///     panic!()
+/// };
+/// assert_eq!(messages.messages, vec!["🦀 Rust"]);
+/// assert_eq!(messages.size, history.len());
+/// assert_eq!(messages.last_message, "Привет\n".len());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Messages {
+    /// The size of the message history up to the end of the last complete message,
+    /// which is where the next fetch continues from.
+    pub size: usize,
+    /// The fetched messages, without empty lines.
+    pub messages: Vec<Cow<'static, str>>,
+    /// The offset in the message history where the final fetched message starts,
+    /// or `size` if no messages were fetched.
+    ///
+    /// It is counted in the bytes sent by the server, including line breaks, empty lines
+    /// and null bytes that are not part of the messages.
+    pub last_message: usize,
+}
+
+/// Builds the error for a response that the request didn't expect.
+fn unexpected(operation: Operation, response: Option<Response>) -> ClientError {
+    trace::unexpected_response(operation, format!("{response:?}"))
+}
+
+/// Splits a payload into complete messages, with the size and the offsets counted from the
+/// start of the payload.
+///
+/// Null bytes are removed, because some servers that are written in C
+/// pad the response with them.
+///
+/// # Errors
+///
+/// Returns `ClientError::ParseError` if the messages can't be decoded with `encoding`.
+fn parse_messages(
+    operation: Operation,
+    mut payload: Vec<u8>,
+    encoding: TextEncoding,
+) -> Result<Messages, ClientError> {
+    // A line break can't be a part of a multi-byte character, so no character is cut here.
+    let complete = payload
+        .iter()
+        .rposition(|&x| x == b'\n')
+        .map_or(0, |i| i + 1);
+    if complete < payload.len() {
+        trace_event!(
+            debug,
+            carried = payload.len() - complete,
+            "left an incomplete message for the next fetch"
+        );
+        payload.truncate(complete);
+    }
+    // Find the final line that is still a message after null bytes and `\r` are stripped.
+    let mut last = None;
+    let mut start = 0;
+    for line in payload.split_inclusive(|&x| x == b'\n') {
+        let text: Vec<u8> = line.iter().copied().filter(|&x| x != 0).collect();
+        let text = text.strip_suffix(b"\n").unwrap_or(&text);
+        if !text.strip_suffix(b"\r").unwrap_or(text).is_empty() {
+            last = Some(start);
+        }
+        start += line.len();
+    }
+    let len = payload.len();
+    payload.retain(|&x| x != 0);
+    if payload.len() < len {
+        trace_event!(
+            debug,
+            removed = len - payload.len(),
+            "stripped null bytes from the response"
+        );
+    }
+    let text = encoding
+        .decode(&payload)
+        .ok_or_else(|| ClientError::ParseError {
+            operation,
+            message: "messages are not valid UTF-8".to_string(),
+        })?;
+    let messages = text
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|s| Cow::Owned(s.to_string()))
+        .collect();
+    Ok(Messages {
+        size: complete,
+        messages,
+        last_message: last.unwrap_or(complete),
+    })
+}
+
+/// Fetches the size of the message history.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::protocol::{FetchSize, Request, Response, Step};
+///
+/// let mut request = FetchSize::new();
+/// assert!(matches!(request.resume(None).unwrap(), Step::Send { .. }));
+/// let step = request.resume(Some(Response::Size(42))).unwrap();
+/// assert!(matches!(step, Step::Done(42)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FetchSize;
+
+impl FetchSize {
+    /// Creates the request.
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Request for FetchSize {
+    type Output = usize;
+
+    fn operation(&self) -> Operation {
+        Operation::FetchMessagesSize
+    }
+
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<usize>, ClientError> {
+        match response {
+            None => Ok(Step::send([0x00], Expect::Size)),
+            Some(Response::Size(size)) => Ok(Step::Done(size)),
+            response => Err(unexpected(self.operation(), response)),
+        }
+    }
+}
+
+/// Fetches all messages, together with the size of the history.
+#[derive(Debug, Clone, Default)]
+pub struct FetchAll {
+    /// The encoding that the messages are decoded with.
+    encoding: TextEncoding,
+}
+
+impl FetchAll {
+    /// Creates the request, which decodes the messages as lossy UTF-8.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the messages with `encoding`.
+    pub fn with_encoding(self, encoding: TextEncoding) -> Self {
+        Self { encoding }
+    }
+}
+
+impl Request for FetchAll {
+    type Output = Messages;
+
+    fn operation(&self) -> Operation {
+        Operation::FetchAllMessages
+    }
+
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<Messages>, ClientError> {
+        match response {
+            None => Ok(Step::send([0x00], Expect::Size)),
+            Some(Response::Size(size)) => Ok(Step::send([0x01], Expect::Payload(size))),
+            Some(Response::Payload(payload)) => {
+                let messages = parse_messages(self.operation(), payload, self.encoding)?;
+                trace_event!(
+                    debug,
+                    messages = messages.messages.len(),
+                    "fetched all messages"
+                );
+                Ok(Step::Done(messages))
+            }
+            response => Err(unexpected(self.operation(), response)),
+        }
+    }
+}
+
+/// Fetches the messages that were added after the history had the size `from`.
+///
+/// If the history hasn't grown, no messages are requested.
+///
+/// # Errors
+///
+/// Fails with `ClientError::HistoryReset` if the history is smaller than `from`,
+/// which only happens if it was cleared on the server.
+///
+/// # Example
+///
+/// The synchronous and async clients run the same request, so they behave the same.
+/// The example runs the async client on `tokio`:
+///
+#[cfg_attr(
+    all(feature = "client", feature = "async_client", feature = "tokio"),
+    doc = "```"
+)]
+#[cfg_attr(
+    not(all(feature = "client", feature = "async_client", feature = "tokio")),
+    doc = "```ignore"
+)]
+/// use rac_rs::shared::ClientError;
+/// use rac_rs::{async_rac, rac};
+/// use std::io::{Read, Write};
+/// use std::net::TcpListener;
+/// use std::thread;
+///
+/// // A server with 11 bytes of history, that accepts two connections.
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let address = listener.local_addr().unwrap().to_string();
+/// let server = thread::spawn(move || {
+///     for _ in 0..2 {
+///         let (mut stream, _) = listener.accept().unwrap();
+///         let mut command = [0u8; 1];
+///         stream.read_exact(&mut command).unwrap();
+///         stream.write_all(b"11\0").unwrap();
+///         // The clients close the connection without asking for messages.
+///         assert_eq!(stream.read(&mut command).unwrap(), 0);
+///     }
+/// });
+///
+/// let mut client = rac::RacClient::new(&address, Default::default(), false);
+/// client.update_current_messages_size(20);
+/// let sync_error = client.fetch_new_messages().unwrap_err();
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// let mut client = async_rac::RacClient::new(&address, Default::default(), false);
+/// client.update_current_messages_size(20);
+/// let async_error = runtime.block_on(client.fetch_new_messages()).unwrap_err();
+///
+/// assert!(matches!(sync_error, ClientError::HistoryReset));
+/// assert!(matches!(async_error, ClientError::HistoryReset));
+/// server.join().unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FetchNew {
+    /// The protocol, which changes how the messages are requested.
+    protocol: Protocol,
+    /// The size of the history known to the client.
+    from: usize,
+    /// The encoding that the messages are decoded with.
+    encoding: TextEncoding,
+}
+
+impl FetchNew {
+    /// Creates the request for the messages after `from`, which decodes them as lossy UTF-8.
+    pub fn new(protocol: Protocol, from: usize) -> Self {
+        Self {
+            protocol,
+            from,
+            encoding: TextEncoding::default(),
+        }
+    }
+
+    /// Decodes the messages with `encoding`.
+    pub fn with_encoding(self, encoding: TextEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+}
+
+impl Request for FetchNew {
+    type Output = Messages;
+
+    fn operation(&self) -> Operation {
+        Operation::FetchNewMessages
+    }
+
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<Messages>, ClientError> {
+        match response {
+            None => Ok(Step::send([0x00], Expect::Size)),
+            Some(Response::Size(size)) if size < self.from => Err(ClientError::HistoryReset),
+            Some(Response::Size(size)) if size == self.from => Ok(Step::Done(Messages {
+                size,
+                messages: Vec::new(),
+                last_message: size,
+            })),
+            Some(Response::Size(size)) => {
+                let data = match self.protocol {
+                    Protocol::Rac => format!("\x02{}", self.from),
+                    // WRAC servers treat every WebSocket message as a new connection.
+                    Protocol::Wrac => format!("\x00\x02{}", self.from),
+                };
+                Ok(Step::send(data, Expect::Payload(size - self.from)))
+            }
+            Some(Response::Payload(payload)) => {
+                let messages = parse_messages(self.operation(), payload, self.encoding)?;
+                trace_event!(
+                    debug,
+                    messages = messages.messages.len(),
+                    "fetched new messages"
+                );
+                Ok(Step::Done(Messages {
+                    size: self.from + messages.size,
+                    last_message: self.from + messages.last_message,
+                    ..messages
+                }))
+            }
+            response => Err(unexpected(self.operation(), response)),
+        }
+    }
+}
+
+/// Sends a message, with authentication if there are credentials.
+///
+/// A status other than a missing user or an incorrect password means that the server only
+/// speaks RACv1, which fails with `ClientError::UnsupportedOperation`.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::protocol::{Request, Response, SendMessage, Step};
+/// use rac_rs::shared::ClientError;
+///
+/// let mut request = SendMessage::authenticated("alice", &"secret".into(), "Hello!");
+/// let Step::Send { data, .. } = request.resume(None).unwrap() else { panic!() };
+/// assert_eq!(data.as_slice(), b"\x02alice\nsecret\nHello!");
+///
+/// let error = request.resume(Some(Response::Status(Some(0x02)))).unwrap_err();
+/// assert!(matches!(error, ClientError::IncorrectPassword));
+/// ```
+#[derive(Debug)]
+pub struct SendMessage {
+    /// The request, until it was sent.
+    data: Option<Zeroizing<Vec<u8>>>,
+    /// Whether the request is authenticated, so the server replies with a status.
+    authenticated: bool,
+}
+
+impl SendMessage {
+    /// Creates a request that sends `message` without authentication.
+    ///
+    /// The message is sent as it is, so text in an encoding other than UTF-8 has to be
+    /// encoded first, for example with [`TextEncoding::encode`].
+    pub fn unauthenticated(message: impl AsRef<[u8]>) -> Self {
+        Self {
+            data: Some(Zeroizing::new([b"\x01", message.as_ref()].concat())),
+            authenticated: false,
+        }
+    }
+
+    /// Creates a request that sends `message` as `username`.
+    ///
+    /// The message is sent as it is, like in [`SendMessage::unauthenticated`].
+    pub fn authenticated(
+        username: &str,
+        password: &SecretString,
+        message: impl AsRef<[u8]>,
+    ) -> Self {
+        let credentials = Zeroizing::new(format!("\x02{username}\n{}\n", password.expose()));
+        Self {
+            data: Some(Zeroizing::new(
+                [credentials.as_bytes(), message.as_ref()].concat(),
+            )),
+            authenticated: true,
+        }
+    }
+
+    /// Creates the requests that a client with these settings sends, one for every message.
+    ///
+    /// `password` is the one resolved for the client, which is never set for RACv1.
+    /// The username is checked for both versions, because `send_message` puts it into
+    /// the message for RACv1.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidInput` if the username or the password can't be sent.
+    pub(crate) fn for_client<M: AsRef<[u8]>>(
+        version: ProtocolVersion,
+        username: &str,
+        password: Option<SecretString>,
+        messages: impl IntoIterator<Item = M>,
+    ) -> Result<Vec<Self>, ClientError> {
+        input::check_field("username", username)?;
+        if version == ProtocolVersion::V2
+            && let Some(password) = password
+        {
+            input::check_field("password", password.expose())?;
+            trace_record!("authenticated", true);
+            return Ok(messages
+                .into_iter()
+                .map(|message| Self::authenticated(username, &password, message))
+                .collect());
+        }
+        trace_record!("authenticated", false);
+        Ok(messages.into_iter().map(Self::unauthenticated).collect())
+    }
+}
+
+impl Request for SendMessage {
+    type Output = ();
+
+    fn operation(&self) -> Operation {
+        Operation::SendMessage
+    }
+
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<()>, ClientError> {
+        match response {
+            None => match self.data.take() {
+                Some(data) if self.authenticated => Ok(Step::Send {
+                    data,
+                    expect: Expect::Status,
+                }),
+                Some(data) => Ok(Step::Send {
+                    data,
+                    expect: Expect::Nothing,
+                }),
+                None => Ok(Step::Done(())),
+            },
+            Some(Response::Status(None)) => Ok(Step::Done(())),
+            Some(Response::Status(Some(0x01))) => Err(ClientError::UserDoesNotExist),
+            Some(Response::Status(Some(0x02))) => Err(ClientError::IncorrectPassword),
+            Some(Response::Status(Some(code))) => Err(trace::unsupported_by_server(
+                self.operation(),
+                &format!("0x{code:02x}"),
+            )),
+            response => Err(unexpected(self.operation(), response)),
+        }
+    }
+}
+
+/// Registers a user.
+///
+/// A status other than a taken username means that the server only speaks RACv1,
+/// which fails with `ClientError::UnsupportedOperation`.
+#[derive(Debug)]
+pub struct Register {
+    /// The request, until it was sent.
+    data: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Register {
+    /// Creates a request that registers `username` with `password`.
+    pub fn new(username: &str, password: &SecretString) -> Self {
+        Self {
+            data: Some(Zeroizing::new(
+                format!("\x03{username}\n{}", password.expose()).into_bytes(),
+            )),
+        }
+    }
+
+    /// Creates the request that a client with these settings sends.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::UnsupportedOperation` for RACv1, `ClientError::NoPassword` if there
+    /// is no password and `ClientError::InvalidInput` if the username or the password can't be sent.
+    pub(crate) fn for_client(
+        version: ProtocolVersion,
+        username: &str,
+        password: Option<SecretString>,
+    ) -> Result<Self, ClientError> {
+        if version == ProtocolVersion::V1 {
+            return Err(ClientError::UnsupportedOperation {
+                operation: Operation::RegisterUser,
+                version,
+            });
+        }
+        match password {
+            Some(password) => {
+                input::check_field("username", username)?;
+                input::check_field("password", password.expose())?;
+                Ok(Self::new(username, &password))
+            }
+            None => Err(ClientError::NoPassword),
+        }
+    }
+}
+
+impl Request for Register {
+    type Output = ();
+
+    fn operation(&self) -> Operation {
+        Operation::RegisterUser
+    }
+
+    fn resume(&mut self, response: Option<Response>) -> Result<Step<()>, ClientError> {
+        match response {
+            None => match self.data.take() {
+                Some(data) => Ok(Step::Send {
+                    data,
+                    expect: Expect::Status,
+                }),
+                None => Err(unexpected(self.operation(), None)),
+            },
+            Some(Response::Status(None)) => Ok(Step::Done(())),
+            Some(Response::Status(Some(0x01))) => Err(ClientError::UsernameAlreadyTaken),
+            Some(Response::Status(Some(code))) => Err(trace::unsupported_by_server(
+                self.operation(),
+                &format!("0x{code:02x}"),
+            )),
+            response => Err(unexpected(self.operation(), response)),
+        }
+    }
+}
@@ -1,13 +1,23 @@
-﻿use crate::shared::{ClientError, Credentials};
-use native_tls::TlsConnector;
+﻿#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::credentials::{CredentialProvider, resolve_password};
+use crate::encoding::TextEncoding;
+use crate::framing::{PayloadDecoder, SIZE_SETTLE_TIME, SizeDecoder, StatusDecoder};
+use crate::input::InputPolicy;
+use crate::metrics::{self, MetricsRecorder, Operation};
+use crate::protocol::{
+    Expect, FetchAll, FetchNew, FetchSize, Messages, Register, Request, Response, SendMessage, Step,
+};
+use crate::shared::{
+    BoxError, ClientConfig, ClientError, Credentials, Protocol, ProtocolVersion, SecretString,
+};
+use crate::trace::{self, trace_record};
+use crate::transport::{self, Connector, TcpConnector, Transport};
+use native_tls::{HandshakeError, TlsConnector};
 use std::borrow::Cow;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-
-trait Io: Read + Write {}
-impl<T: Read + Write + ?Sized> Io for T {}
-
-type DynStream = Box<dyn Io + Send>;
+use std::io::{ErrorKind, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// A client for interacting with a RAC server.
 ///
@@ -17,16 +27,16 @@ type DynStream = Box<dyn Io + Send>;
 /// # Example
 ///
 /// ```no_run
-/// use rac_rs::client::RacClient;
+/// use rac_rs::rac::RacClient;
 /// use rac_rs::shared::Credentials;
 ///
 /// let credentials = Credentials {
 ///     username: "test_user".to_string(),
-///     password: Some("password123".to_string()),
+///     password: Some("password123".into()),
 /// };
 ///
-/// let mut client = Client::new(
-///     "127.0.0.1:1234",
+/// let mut client = RacClient::new(
+///     "127.0.0.1:42666",
 ///     credentials,
 ///     false
 /// );
@@ -35,14 +45,35 @@ type DynStream = Box<dyn Io + Send>;
 pub struct RacClient {
     /// The current size of messages in the client.
     current_messages_size: usize,
+    /// Where the final message of the last fetch starts in the history.
+    last_message_offset: usize,
     /// The address of the RAC server.
     address: String,
     /// The username for authentication.
     username: String,
     /// The password for authentication, if required.
-    password: Option<String>,
+    password: Option<SecretString>,
+    /// Source of the password, used when no password is set directly.
+    password_provider: Option<Arc<dyn CredentialProvider>>,
+    /// Receiver of request measurements.
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    /// Opener of connections, TCP if not set.
+    connector: Option<Arc<dyn Connector>>,
+    /// Injector of network faults, used for resilience tests.
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
     /// Whether to use TLS encryption.
     use_tls: bool,
+    /// The version of the protocol.
+    version: ProtocolVersion,
+    /// Timeout for connecting and for every read or write.
+    timeout: Option<Duration>,
+    /// How long to wait for more digits of the messages size.
+    size_settle_time: Duration,
+    /// The encoding of the messages on the server.
+    encoding: TextEncoding,
+    /// What to do with messages that contain control characters.
+    input_policy: InputPolicy,
 }
 
 impl RacClient {
@@ -56,13 +87,36 @@ impl RacClient {
     pub fn new(address: &str, credentials: Credentials, use_tls: bool) -> Self {
         Self {
             current_messages_size: 0,
+            last_message_offset: 0,
             address: address.to_string(),
             username: credentials.username,
             password: credentials.password,
+            password_provider: None,
+            metrics: None,
+            connector: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
             use_tls,
+            version: ProtocolVersion::V2,
+            timeout: None,
+            size_settle_time: SIZE_SETTLE_TIME,
+            encoding: TextEncoding::default(),
+            input_policy: InputPolicy::default(),
         }
     }
 
+    /// Creates a new client from the settings in `config`.
+    ///
+    /// The `protocol` field of the config is not checked.
+    pub fn from_config(config: &ClientConfig) -> Self {
+        let mut client = Self::new(&config.address, config.credentials.clone(), config.use_tls);
+        client.timeout = config.timeout;
+        client.version = config.version;
+        client.encoding = config.encoding;
+        client.input_policy = config.input_policy;
+        client
+    }
+
     /// Updates the client's credentials.
     ///
     /// This method allows you to change the username and password for the client.
@@ -71,6 +125,34 @@ impl RacClient {
         self.password = credentials.password;
     }
 
+    /// Updates the source of the password.
+    ///
+    /// The provider is asked for the password every time the client authenticates,
+    /// unless a password is set directly in the credentials.
+    pub fn update_password_provider(&mut self, provider: Option<Arc<dyn CredentialProvider>>) {
+        self.password_provider = provider;
+    }
+
+    /// Updates the receiver of request counts, latencies, received bytes and errors.
+    pub fn update_metrics(&mut self, metrics: Option<Arc<dyn MetricsRecorder>>) {
+        self.metrics = metrics;
+    }
+
+    /// Updates the opener of connections.
+    ///
+    /// `None` means connecting over TCP, which is the default.
+    pub fn update_connector(&mut self, connector: Option<Arc<dyn Connector>>) {
+        self.connector = connector;
+    }
+
+    /// Updates the injector of network faults.
+    ///
+    /// Every connection made after this goes through the injector. Only use it in tests.
+    #[cfg(feature = "chaos")]
+    pub fn update_chaos(&mut self, chaos: Option<Arc<Chaos>>) {
+        self.chaos = chaos;
+    }
+
     /// Updates the client's TLS usage.
     ///
     /// This method allows you to enable or disable TLS encryption for the connection.
@@ -78,6 +160,51 @@ impl RacClient {
         self.use_tls = use_tls;
     }
 
+    /// Updates the version of the protocol spoken by the server.
+    ///
+    /// With RACv1, messages are sent without authentication and `register_user` fails
+    /// with `ClientError::UnsupportedOperation`.
+    pub fn update_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Updates the timeout for connecting and for every read or write.
+    ///
+    /// `None` means waiting forever, which is the default.
+    pub fn update_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Updates how long the client waits for more digits of the messages size.
+    ///
+    /// The size has no terminator, so when a server sends it without a null byte or whitespace
+    /// after it, the client can only tell it is complete when no more digits arrive for this
+    /// long. Every such size read takes at least this long, and a size whose digits arrive
+    /// further apart is cut short. Raise it for slow links and set it to zero for servers
+    /// that are known to send the size in one piece.
+    ///
+    /// [`SIZE_SETTLE_TIME`] is the default.
+    /// Connections made by a [`Connector`] whose streams don't support read timeouts
+    /// can't wait, so the size is taken from the first read that has digits.
+    pub fn update_size_settle_time(&mut self, settle_time: Duration) {
+        self.size_settle_time = settle_time;
+    }
+
+    /// Updates the encoding of the messages on the server.
+    ///
+    /// Fetched messages are decoded and sent messages are encoded with it.
+    /// Lossy UTF-8 is the default.
+    pub fn update_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Updates what to do with messages that contain line breaks or other control characters.
+    ///
+    /// Such messages are rejected with `ClientError::InvalidInput` by default.
+    pub fn update_input_policy(&mut self, policy: InputPolicy) {
+        self.input_policy = policy;
+    }
+
     /// Updates the client's address to the server.
     ///
     /// This method allows you to change the address of the RAC server.
@@ -85,39 +212,231 @@ impl RacClient {
         self.address = address;
     }
 
+    /// Updates the size of messages known to the client.
+    ///
+    /// The next call to `fetch_new_messages` will fetch messages starting from this size.
+    /// This is used to resume from a size saved earlier, for example in a `HistoryCache`.
+    pub fn update_current_messages_size(&mut self, size: usize) {
+        self.current_messages_size = size;
+    }
+
     /// Attempts to establish a TCP connection to the RAC server.
-    fn get_stream(&self) -> Result<DynStream, ClientError> {
-        let stream = TcpStream::connect(&self.address).map_err(ClientError::ConnectionError)?;
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(address = %self.address, tls = self.use_tls))
+    )]
+    fn get_stream(&self, operation: Operation) -> Result<Box<dyn Transport>, ClientError> {
+        let started = Instant::now();
+        let result = self.get_stream_inner(operation);
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::Connect,
+            started,
+            result,
+        )
+    }
 
-        if !self.use_tls {
-            return Ok(Box::new(stream));
+    /// Runs [`Self::get_stream`] without reporting it to the metrics recorder.
+    fn get_stream_inner(&self, operation: Operation) -> Result<Box<dyn Transport>, ClientError> {
+        let stream = match &self.connector {
+            Some(connector) => connector.connect(&self.address, self.timeout),
+            None => TcpConnector.connect(&self.address, self.timeout),
         }
+        .map_err(|e| ClientError::connect(operation, e))?;
+
+        let stream = self.wrap_chaos(stream);
+        if self.use_tls {
+            self.tls_handshake(operation, stream)
+        } else {
+            Ok(stream)
+        }
+    }
 
-        let domain = self.address.split(':').next().unwrap_or("localhost");
+    /// Passes the stream through the fault injector, if there is one.
+    ///
+    /// Null padding is only injected into plain connections; below TLS it would break
+    /// the records.
+    fn wrap_chaos(&self, stream: Box<dyn Transport>) -> Box<dyn Transport> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            if self.use_tls {
+                return Box::new(chaos.wrap_framed(stream));
+            }
+            return Box::new(chaos.wrap(stream));
+        }
+        stream
+    }
 
+    /// Performs the TLS handshake over an established connection.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    fn tls_handshake(
+        &self,
+        operation: Operation,
+        stream: Box<dyn Transport>,
+    ) -> Result<Box<dyn Transport>, ClientError> {
+        let domain = transport::tls_domain(&self.address)?;
         let connector =
             TlsConnector::new().map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
         let tls_stream = connector
             .connect(domain, stream)
-            .map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
+            .map_err(|e| ClientError::tls_handshake(operation, Self::handshake_cause(e)))?;
 
         Ok(Box::new(tls_stream))
     }
 
-    /// Removes null bytes from the data vector.
+    /// Extracts the cause of a failed TLS handshake.
+    ///
+    /// A handshake is only interrupted when a blocking read or write times out,
+    /// so this is reported as `TimedOut`.
+    fn handshake_cause(error: HandshakeError<Box<dyn Transport>>) -> BoxError {
+        match error {
+            HandshakeError::Failure(e) => e.into(),
+            HandshakeError::WouldBlock(_) => {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timed out").into()
+            }
+        }
+    }
+
+    /// Runs `request` over a new connection.
+    fn run<R: Request>(&self, mut request: R) -> Result<R::Output, ClientError> {
+        let op = request.operation();
+        let mut stream = self.get_stream(op)?;
+        let mut received = 0;
+        let mut response = None;
+        loop {
+            let (data, expect) = match request.resume(response.take())? {
+                Step::Send { data, expect } => (data, expect),
+                Step::Done(output) => {
+                    if received > 0 {
+                        trace_record!("bytes_received", received);
+                        metrics::record_bytes(&self.metrics, &self.address, op, received);
+                    }
+                    return Ok(output);
+                }
+            };
+            stream
+                .write_all(&data)
+                .map_err(|e| ClientError::write(op, e))?;
+            response = match expect {
+                Expect::Nothing => None,
+                Expect::Size => {
+                    let (size, n) = self.read_size(op, &mut stream)?;
+                    received += n;
+                    Some(Response::Size(size))
+                }
+                Expect::Payload(len) => {
+                    let payload = Self::read_payload(op, &mut stream, len)?;
+                    received += payload.len();
+                    Some(Response::Payload(payload))
+                }
+                Expect::Status => Some(Response::Status(Self::read_status(op, &mut stream)?)),
+            };
+        }
+    }
+
+    /// Reads the size of the message history.
+    ///
+    /// The size has no terminator, so once some digits arrived, the client waits up to
+    /// the size settle time for more of them before it considers the size complete,
+    /// see [`Self::update_size_settle_time`]. Returns the size and the number of bytes read.
+    fn read_size(
+        &self,
+        operation: Operation,
+        stream: &mut dyn Transport,
+    ) -> Result<(usize, usize), ClientError> {
+        let mut decoder = SizeDecoder::new();
+        let mut buf = [0u8; 64];
+        let mut received = 0;
+        loop {
+            let settling = decoder.has_digits();
+            let result = if !settling {
+                stream.read(&mut buf)
+            } else if self.size_settle_time.is_zero() {
+                Ok(0)
+            } else if stream.set_read_timeout(Some(self.size_settle_time)).is_ok() {
+                let result = stream.read(&mut buf);
+                stream
+                    .set_read_timeout(self.timeout)
+                    .map_err(|e| ClientError::read(operation, e))?;
+                result
+            } else {
+                // Without a read timeout, waiting for more digits could block forever.
+                Ok(0)
+            };
+            let frame = match result {
+                Ok(0) => decoder.finish(),
+                Ok(n) => {
+                    received += n;
+                    match decoder.feed(&buf[..n]) {
+                        Ok(Some(size)) => Ok(size),
+                        Ok(None) => continue,
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e)
+                    if settling
+                        && matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    decoder.finish()
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ClientError::read(operation, e)),
+            };
+            return frame
+                .map(|size| (size, received))
+                .map_err(|e| trace::frame_error(operation, e));
+        }
+    }
+
+    /// Reads the reply to an authenticated send or a registration.
     ///
-    /// This is required because some servers that are written in C
-    /// may send null bytes in the response, which can cause issues
-    /// when parsing the response.
-    fn remove_nulls(data: &mut Vec<u8>) {
-        data.retain(|&x| x != 0);
+    /// Returns `None` if the server closed the connection without a status, which means success.
+    fn read_status(
+        operation: Operation,
+        stream: &mut dyn Transport,
+    ) -> Result<Option<u8>, ClientError> {
+        let mut decoder = StatusDecoder::new();
+        let mut buf = [0u8; 16];
+        loop {
+            match stream.read(&mut buf) {
+                Ok(0) => return Ok(decoder.finish()),
+                Ok(n) => {
+                    if let Some(status) = decoder.feed(&buf[..n]) {
+                        return Ok(Some(status));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(ClientError::read(operation, e)),
+            }
+        }
+    }
+
+    /// Reads a payload of `len` bytes, such as the messages that follow the size.
+    fn read_payload(
+        operation: Operation,
+        stream: &mut dyn Transport,
+        len: usize,
+    ) -> Result<Vec<u8>, ClientError> {
+        let mut decoder = PayloadDecoder::new(len);
+        let mut buf = vec![0u8; len.min(8192)];
+        let mut payload = decoder.feed(&[]);
+        while payload.is_none() {
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(ClientError::closed(operation)),
+                Ok(n) => payload = decoder.feed(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(ClientError::read(operation, e)),
+            }
+        }
+        Ok(payload.unwrap_or_default())
     }
 
     /// Tests the connection to the RAC server.
     ///
     /// This method attempts to establish a TCP connection and returns `Ok(())` if successful.
     pub fn test_connection(&self) -> Result<(), ClientError> {
-        self.get_stream()?;
+        self.get_stream(Operation::Connect)?;
         Ok(())
     }
 
@@ -127,125 +446,136 @@ impl RacClient {
     ///
     /// Returns `ClientError::NoPassword` if no password specified for the client.
     /// Returns `ClientError::UsernameAlreadyTaken` if the username is already in use.
+    /// Returns `ClientError::UnsupportedOperation` if the client or the server only speaks RACv1.
     /// Returns `ClientError::UnexpectedResponse` if got unexpected response from server.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, username = %self.username),
+            err(level = "warn")
+        )
+    )]
     pub fn register_user(&mut self) -> Result<(), ClientError> {
-        // Getting the TCP stream to the RAC server.
-        let mut stream = self.get_stream()?;
+        let started = Instant::now();
+        let result = self.register_user_inner();
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::RegisterUser,
+            started,
+            result,
+        )
+    }
 
-        // Sending the username and password to the RAC server.
-        if self.password.is_some() {
-            stream
-                .write_all(
-                    format!(
-                        "\x03{}\n{}",
-                        self.username,
-                        self.password.as_deref().unwrap()
-                    )
-                    .as_bytes(),
-                )
-                .map_err(ClientError::StreamWriteError)?;
-            let mut buf = [0u8; 2];
-            let n = stream
-                .read(&mut buf)
-                .map_err(ClientError::StreamReadError)?;
-            if n == 0 {
-                return Ok(());
-            }
-            match buf[0] {
-                0x01 => Err(ClientError::UsernameAlreadyTaken),
-                _ => Err(ClientError::UnexpectedResponse(
-                    String::from_utf8_lossy(&buf[..n]).to_string(),
-                )),
-            }
-        } else {
-            Err(ClientError::NoPassword)
-        }
+    /// Runs [`Self::register_user`] without reporting it to the metrics recorder.
+    fn register_user_inner(&mut self) -> Result<(), ClientError> {
+        let request = Register::for_client(
+            self.version,
+            &self.username,
+            resolve_password(self.version, &self.password, &self.password_provider)?,
+        )?;
+        self.run(request)
     }
 
     /// Fetches the total size of all messages on the server and updates the client's internal state.
     ///
     /// This is useful for determining the amount of data if you want to know current size.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
     pub fn fetch_messages_size(&mut self) -> Result<(), ClientError> {
-        // Getting the TCP stream to the RAC server.
-        let mut stream = self.get_stream()?;
-
-        // Trying to send 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buf = vec![0u8; 1024];
-        let n = stream
-            .read(&mut buf)
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
-
-        Self::remove_nulls(&mut buf);
+        let started = Instant::now();
+        let result = self.fetch_messages_size_inner();
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchMessagesSize,
+            started,
+            result,
+        )
+    }
 
-        // Then, converting it to utf8 and parsing the size to usize.
-        let response = String::from_utf8_lossy(&buf[..n]);
-        if let Ok(size) = response.parse::<usize>() {
-            self.current_messages_size = size;
-            Ok(())
-        } else {
-            Err(ClientError::ParseError(
-                "Failed to parse messages size".to_string(),
-            ))
-        }
+    /// Runs [`Self::fetch_messages_size`] without reporting it to the metrics recorder.
+    fn fetch_messages_size_inner(&mut self) -> Result<(), ClientError> {
+        self.current_messages_size = self.run(FetchSize::new())?;
+        Ok(())
     }
 
     /// Fetches all messages from the RAC server.
     ///
     /// This method retrieves all messages stored on the server and updates the
     /// client's internal message size tracker.
-    pub fn fetch_all_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        let mut stream = self.get_stream()?;
-
-        // Sending 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .map_err(ClientError::StreamWriteError)?;
-        let mut head = vec![0u8; 1024];
-        let n = stream
-            .read(&mut head)
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
-
-        Self::remove_nulls(&mut head);
+    ///
+    /// # Example
+    ///
+    /// Responses are reassembled, however the network splits them:
+    ///
+    /// ```
+    /// use rac_rs::rac::RacClient;
+    /// use std::io::{Read, Write};
+    /// use std::net::{TcpListener, TcpStream};
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// // A server that sends its responses one byte at a time.
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let address = listener.local_addr().unwrap().to_string();
+    /// let server = thread::spawn(move || {
+    ///     let (mut stream, _) = listener.accept().unwrap();
+    ///     stream.set_nodelay(true).unwrap();
+    ///     let mut send = |stream: &mut TcpStream, bytes: &[u8]| {
+    ///         let mut command = [0u8; 1];
+    ///         stream.read_exact(&mut command).unwrap();
+    ///         for byte in bytes {
+    ///             stream.write_all(&[*byte]).unwrap();
+    ///             thread::sleep(Duration::from_millis(1));
+    ///         }
+    ///     };
+    ///     send(&mut stream, b"11");
+    ///     send(&mut stream, b"Hello, RAC\n");
+    /// });
+    ///
+    /// let mut client = RacClient::new(&address, Default::default(), false);
+    /// assert_eq!(client.fetch_all_messages().unwrap(), vec!["Hello, RAC"]);
+    /// assert_eq!(client.current_messages_size(), 11);
+    /// server.join().unwrap();
+    /// ```
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub fn fetch_all_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_all_messages_inner();
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchAllMessages,
+            started,
+            result,
+        )
+    }
 
-        let response = String::from_utf8_lossy(&head[..n]);
-        let size = response
-            .parse::<usize>()
-            .map_err(|_| ClientError::ParseError("Failed to parse messages size".to_string()))?;
+    /// Runs [`Self::fetch_all_messages`] without reporting it to the metrics recorder.
+    fn fetch_all_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(FetchAll::new().with_encoding(self.encoding))?;
         self.current_messages_size = size;
-
-        // Sending 0x01 byte to get all messages.
-        stream
-            .write_all(&[0x01])
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buffer = vec![0u8; self.current_messages_size];
-        stream
-            .read_exact(&mut buffer)
-            .map_err(ClientError::StreamReadError)?;
-
-        Self::remove_nulls(&mut buffer);
-
-        let response = String::from_utf8_lossy(&buffer).into_owned();
-
-        let vec_messages = response
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect();
-
-        Ok(vec_messages)
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Fetches only new messages that have arrived since the last fetch.
@@ -253,58 +583,46 @@ impl RacClient {
     /// This method compares the current message size on the server with the client's
     /// stored size and retrieves only the difference. The client's internal message
     /// size tracker is updated upon successful fetch.
-    pub fn fetch_new_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        // For this approach, we will not use fetch_messages_size function,
-        // because it is necessary to fetch messages size AND THEN get new messages
-        // IN THE SAME STREAM. Welcome to the Sugoma's bullshit protocol.
-
-        let mut stream = self.get_stream()?;
-
-        // Sending 0x00 byte to get the size of messages.
-        stream
-            .write_all(&[0x00])
-            .map_err(ClientError::StreamWriteError)?;
-        let mut head = vec![0u8; 1024];
-        let n = stream
-            .read(&mut head)
-            .map_err(ClientError::StreamReadError)?;
-
-        if n == 0 {
-            return Err(ClientError::ServerClosedConnection);
-        }
-
-        Self::remove_nulls(&mut head);
-
-        // Then, converting it to utf8 and parsing the size to usize.
-        let response = String::from_utf8_lossy(&head[..n]);
-        let size = response
-            .parse::<usize>()
-            .map_err(|_| ClientError::ParseError("Failed to parse messages size".to_string()))?;
-
-        // Now, we can get new messages.
-        stream
-            .write_all(format!("\x02{}", self.current_messages_size).as_bytes())
-            .map_err(ClientError::StreamWriteError)?;
-
-        let mut buffer = vec![0u8; size - self.current_messages_size];
-        stream
-            .read_exact(&mut buffer)
-            .map_err(ClientError::StreamReadError)?;
-
-        Self::remove_nulls(&mut buffer);
-
-        let response = String::from_utf8_lossy(&buffer).into_owned();
-
-        let vec_messages = response
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect();
+    ///
+    /// A message that the server hasn't finished writing is left for the next fetch,
+    /// so messages are never cut in half, even in the middle of a multi-byte character.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                address = %self.address,
+                from = self.current_messages_size,
+                bytes_received = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub fn fetch_new_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self.fetch_new_messages_inner();
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchNewMessages,
+            started,
+            result,
+        )
+    }
 
-        // Setting the new messages size.
+    /// Runs [`Self::fetch_new_messages`] without reporting it to the metrics recorder.
+    fn fetch_new_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        // The size and the new messages have to be fetched over the same connection.
+        let request =
+            FetchNew::new(Protocol::Rac, self.current_messages_size).with_encoding(self.encoding);
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(request)?;
         self.current_messages_size = size;
-
-        Ok(vec_messages)
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Sends a message to the server.
@@ -314,9 +632,9 @@ impl RacClient {
     /// # Example
     ///
     /// ```no_run
-    /// # use rac_rs::client::Client;
+    /// # use rac_rs::rac::RacClient;
     /// # use rac_rs::shared::ClientError;
-    /// # let mut client = Client::new("", Default::default(), false);
+    /// # let mut client = RacClient::new("", Default::default(), false);
     /// client.send_message("<{username}> Hello everyone!")?;
     /// # Ok::<(), ClientError>(())
     /// ```
@@ -326,45 +644,49 @@ impl RacClient {
         self.send_custom_message(&message)
     }
 
-    /// Sends a raw message to the server without any modifications.
+    /// Sends a raw message to the server without replacing `{username}`.
+    ///
+    /// Line breaks and other control characters are handled according to the input policy,
+    /// see [`Self::update_input_policy`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_message",
+            skip_all,
+            fields(
+                address = %self.address,
+                message_bytes = message.len(),
+                authenticated = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
     pub fn send_custom_message(&self, message: &str) -> Result<(), ClientError> {
-        let mut stream = self.get_stream()?;
-
-        // Sending the message to the RAC server.
+        let started = Instant::now();
+        let result = self.send_custom_message_inner(message);
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::SendMessage,
+            started,
+            result,
+        )
+    }
 
-        if self.password.is_some() {
-            stream
-                .write_all(
-                    format!(
-                        "\x02{}\n{}\n{}",
-                        self.username,
-                        self.password.as_deref().unwrap(),
-                        message
-                    )
-                    .as_bytes(),
-                )
-                .map_err(ClientError::StreamWriteError)?;
-            let mut buf = [0u8; 2];
-            let n = stream
-                .read(&mut buf)
-                .map_err(ClientError::StreamReadError)?;
-            if n == 0 {
-                return Ok(());
-            }
-            return match buf[0] {
-                0x01 => Err(ClientError::UserDoesNotExist),
-                0x02 => Err(ClientError::IncorrectPassword),
-                _ => Err(ClientError::UnexpectedResponse(
-                    String::from_utf8_lossy(&buf[..n]).to_string(),
-                )),
-            };
+    /// Runs [`Self::send_custom_message`] without reporting it to the metrics recorder.
+    fn send_custom_message_inner(&self, message: &str) -> Result<(), ClientError> {
+        let messages = self.input_policy.apply(message)?;
+        let requests = SendMessage::for_client(
+            self.version,
+            &self.username,
+            resolve_password(self.version, &self.password, &self.password_provider)?,
+            messages.iter().map(|message| self.encoding.encode(message)),
+        )?;
+        let total = requests.len();
+        for (sent, request) in requests.into_iter().enumerate() {
+            self.run(request)
+                .map_err(|e| ClientError::partial_send(sent, total, e))?;
         }
-
-        // If the connection is RAC, we can send the message directly, without an attempt to authorize.
-        stream
-            .write_all(format!("\x01{}", message).as_bytes())
-            .map_err(ClientError::StreamWriteError)?;
-
         Ok(())
     }
 
@@ -373,9 +695,11 @@ impl RacClient {
     /// This clears the address, username, password, and message size.
     pub fn reset(&mut self) {
         self.current_messages_size = 0;
+        self.last_message_offset = 0;
         self.address.clear();
         self.username.clear();
         self.password = None;
+        self.password_provider = None;
     }
 
     /// Returns the current size of messages known to the client.
@@ -385,6 +709,15 @@ impl RacClient {
         self.current_messages_size
     }
 
+    /// Returns the offset in the message history where the final message of the last fetch
+    /// starts, counted in the bytes sent by the server.
+    ///
+    /// It is the messages size to resume from to fetch that message again, which
+    /// [`HistoryCache`](crate::cache::HistoryCache) uses to detect a history that was reset.
+    pub fn last_message_offset(&self) -> usize {
+        self.last_message_offset
+    }
+
     /// Returns the current state of TLS usage.
     ///
     /// This indicates whether the client is configured to use TLS for its connections.
@@ -392,6 +725,31 @@ impl RacClient {
         self.use_tls
     }
 
+    /// Returns the version of the protocol.
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the timeout for connecting and for every read or write.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns how long the client waits for more digits of the messages size.
+    pub fn size_settle_time(&self) -> Duration {
+        self.size_settle_time
+    }
+
+    /// Returns the encoding of the messages on the server.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Returns what is done with messages that contain line breaks or other control characters.
+    pub fn input_policy(&self) -> InputPolicy {
+        self.input_policy
+    }
+
     /// Returns a reference to the server address.
     pub fn address(&self) -> &str {
         &self.address
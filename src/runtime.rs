@@ -0,0 +1,159 @@
+use crate::transport::AsyncTransport;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A future that completes after a delay, made by [`sleep`].
+///
+/// Once complete, it stays complete, so it can be polled again.
+pub(crate) struct Sleep(Option<Pin<Box<dyn Future<Output = ()> + Send + Sync>>>);
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if let Some(delay) = &mut this.0 {
+            std::task::ready!(delay.as_mut().poll(cx));
+            this.0 = None;
+        }
+        Poll::Ready(())
+    }
+}
+
+impl fmt::Debug for Sleep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Sleep")
+    }
+}
+
+/// The runtime that timers and connections are made with.
+#[cfg(any(feature = "tokio", feature = "smol"))]
+enum Backend {
+    /// `tokio`, with its streams adapted to `futures-io`.
+    #[cfg(feature = "tokio")]
+    Tokio,
+    /// `async-io`, which works with any executor, including `smol`.
+    #[cfg(feature = "smol")]
+    Smol,
+}
+
+/// Picks the runtime to use from the enabled features.
+///
+/// With both of them, `tokio` is used inside of a `tokio` runtime and `async-io` everywhere else,
+/// because `tokio` timers and sockets panic outside of its runtime.
+#[cfg(any(feature = "tokio", feature = "smol"))]
+fn backend() -> Backend {
+    #[cfg(all(feature = "tokio", feature = "smol"))]
+    if tokio::runtime::Handle::try_current().is_err() {
+        return Backend::Smol;
+    }
+    #[cfg(feature = "tokio")]
+    return Backend::Tokio;
+    #[cfg(not(feature = "tokio"))]
+    return Backend::Smol;
+}
+
+/// Returns a future that completes after `duration`.
+#[cfg(any(feature = "tokio", feature = "smol"))]
+pub(crate) fn sleep(duration: Duration) -> Sleep {
+    match backend() {
+        #[cfg(feature = "tokio")]
+        Backend::Tokio => Sleep(Some(Box::pin(tokio::time::sleep(duration)))),
+        #[cfg(feature = "smol")]
+        Backend::Smol => Sleep(Some(Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        }))),
+    }
+}
+
+/// Runs `future`, returning `None` if it takes longer than `duration`.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    use futures_util::future::{Either, select};
+
+    match select(std::pin::pin!(future), sleep(duration)).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Runs `f` on the thread pool of the runtime for blocking work and returns its result.
+#[cfg(any(feature = "tokio", feature = "smol"))]
+pub(crate) async fn spawn_blocking<T, F>(f: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    match backend() {
+        #[cfg(feature = "tokio")]
+        Backend::Tokio => match tokio::task::spawn_blocking(f).await {
+            Ok(output) => output,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => panic!("blocking task failed: {e}"),
+        },
+        #[cfg(feature = "smol")]
+        Backend::Smol => blocking::unblock(f).await,
+    }
+}
+
+/// Opens a TCP connection to `address`.
+#[cfg(any(feature = "tokio", feature = "smol"))]
+pub(crate) async fn connect_tcp(address: &str) -> std::io::Result<Box<dyn AsyncTransport>> {
+    match backend() {
+        #[cfg(feature = "tokio")]
+        Backend::Tokio => {
+            use tokio_util::compat::TokioAsyncReadCompatExt;
+            let stream = tokio::net::TcpStream::connect(address).await?;
+            Ok(Box::new(stream.compat()))
+        }
+        #[cfg(feature = "smol")]
+        Backend::Smol => Ok(Box::new(async_net::TcpStream::connect(address).await?)),
+    }
+}
+
+/// Opens a connection to the Unix domain socket at `path`.
+#[cfg(all(unix, any(feature = "tokio", feature = "smol")))]
+pub(crate) async fn connect_unix(
+    path: &std::path::Path,
+) -> std::io::Result<Box<dyn AsyncTransport>> {
+    match backend() {
+        #[cfg(feature = "tokio")]
+        Backend::Tokio => {
+            use tokio_util::compat::TokioAsyncReadCompatExt;
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            Ok(Box::new(stream.compat()))
+        }
+        #[cfg(feature = "smol")]
+        Backend::Smol => Ok(Box::new(async_net::unix::UnixStream::connect(path).await?)),
+    }
+}
+
+/// Stand-ins for the functions above when no runtime is enabled, so that the build only fails
+/// with the error that asks for one.
+#[cfg(not(any(feature = "tokio", feature = "smol")))]
+mod missing {
+    use super::{AsyncTransport, Duration, Sleep};
+
+    pub(crate) fn sleep(_duration: Duration) -> Sleep {
+        unreachable!("no runtime is enabled")
+    }
+
+    pub(crate) async fn spawn_blocking<T, F: FnOnce() -> T>(_f: F) -> T {
+        unreachable!("no runtime is enabled")
+    }
+
+    pub(crate) async fn connect_tcp(_address: &str) -> std::io::Result<Box<dyn AsyncTransport>> {
+        unreachable!("no runtime is enabled")
+    }
+
+    #[cfg(unix)]
+    pub(crate) async fn connect_unix(
+        _path: &std::path::Path,
+    ) -> std::io::Result<Box<dyn AsyncTransport>> {
+        unreachable!("no runtime is enabled")
+    }
+}
+
+#[cfg(not(any(feature = "tokio", feature = "smol")))]
+pub(crate) use missing::*;
@@ -0,0 +1,261 @@
+use crate::cache::HistoryCache;
+use crate::message::{ChatMessage, ClientKind};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// What to look for in the history.
+///
+/// All specified conditions must match. A query without conditions matches every message.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Text that the message body must contain, ignoring case.
+    pub text: Option<String>,
+    /// Regular expression that the message body must match.
+    pub regex: Option<Regex>,
+    /// Exact author of the message, ignoring case.
+    pub author: Option<String>,
+    /// Client that sent the message.
+    pub client: Option<ClientKind>,
+}
+
+impl Query {
+    /// Parses a query from a search string.
+    ///
+    /// The string consists of words separated by spaces. Words with the `author:`,
+    /// `client:` and `regex:` prefixes set the corresponding condition, everything
+    /// else is joined into the text to look for.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error message if the regex or the client name is invalid.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rac_rs::message::ClientKind;
+    /// use rac_rs::search::Query;
+    ///
+    /// let query = Query::parse("author:alice client:bRAC hello world").unwrap();
+    /// assert_eq!(query.author.as_deref(), Some("alice"));
+    /// assert_eq!(query.client, Some(ClientKind::Brac));
+    /// assert_eq!(query.text.as_deref(), Some("hello world"));
+    /// ```
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut query = Query::default();
+        let mut words = Vec::new();
+        for word in input.split_whitespace() {
+            if let Some(author) = word.strip_prefix("author:") {
+                query.author = Some(author.to_string());
+            } else if let Some(client) = word.strip_prefix("client:") {
+                query.client = Some(client.parse()?);
+            } else if let Some(pattern) = word.strip_prefix("regex:") {
+                query.regex = Some(Regex::new(pattern).map_err(|e| e.to_string())?);
+            } else {
+                words.push(word);
+            }
+        }
+        if !words.is_empty() {
+            query.text = Some(words.join(" "));
+        }
+        Ok(query)
+    }
+}
+
+/// A message found by [`SearchIndex::search`].
+#[derive(Debug, Clone, Copy)]
+pub struct SearchHit<'a> {
+    /// Position of the message in the history.
+    pub index: usize,
+    /// The message itself.
+    pub message: &'a ChatMessage,
+}
+
+/// An inverted index over the message history.
+///
+/// Message bodies are indexed by the trigrams of their lowercased text. A text search
+/// only compares the messages that contain every trigram of the text, while a text shorter
+/// than three characters is compared with every message left by the other conditions.
+/// Authors and clients have their own indexes. Regular expressions can't use the trigram
+/// index and are checked like short texts.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::search::{Query, SearchIndex};
+///
+/// let mut index = SearchIndex::new();
+/// index.extend(["<alice> Hello everyone", "<bob> hi alice", "<alice> see you later"]);
+///
+/// let hits = index.search(&Query::parse("author:alice hello").unwrap());
+/// assert_eq!(hits.len(), 1);
+/// assert_eq!(hits[0].message.body, "Hello everyone");
+///
+/// let hits = index.search(&Query::parse("ALICE").unwrap());
+/// assert_eq!(hits[0].index, 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Parsed messages in the order they were added.
+    messages: Vec<ChatMessage>,
+    /// Positions of messages by trigrams of their lowercased bodies.
+    trigrams: HashMap<[char; 3], Vec<u32>>,
+    /// Positions of messages by their lowercased authors.
+    authors: HashMap<String, Vec<u32>>,
+    /// Positions of messages by the client that sent them.
+    clients: HashMap<ClientKind, Vec<u32>>,
+}
+
+impl SearchIndex {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an index over all lines of the history cache.
+    pub fn from_cache(cache: &HistoryCache) -> Self {
+        let mut index = Self::new();
+        index.extend(cache.lines());
+        index
+    }
+
+    /// Parses and indexes a line, adding it to the end of the history.
+    pub fn push(&mut self, line: &str) {
+        let position = self.messages.len() as u32;
+        let message = ChatMessage::parse(line);
+
+        let body: Vec<char> = message.body.to_lowercase().chars().collect();
+        for trigram in body.windows(3) {
+            let postings = self.trigrams.entry([trigram[0], trigram[1], trigram[2]]);
+            let postings = postings.or_default();
+            // Lines are added in order, so a repeated trigram always ends with this position.
+            if postings.last() != Some(&position) {
+                postings.push(position);
+            }
+        }
+        if let Some(author) = &message.author {
+            self.authors
+                .entry(author.to_lowercase())
+                .or_default()
+                .push(position);
+        }
+        if let Some(client) = message.client {
+            self.clients.entry(client).or_default().push(position);
+        }
+
+        self.messages.push(message);
+    }
+
+    /// Parses and indexes multiple lines.
+    pub fn extend<I, S>(&mut self, lines: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for line in lines {
+            self.push(line.as_ref());
+        }
+    }
+
+    /// Removes all messages from the index.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns all indexed messages.
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Returns the number of indexed messages.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns `true` if there are no indexed messages.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Finds all messages matching the query, in the order they were added.
+    pub fn search(&self, query: &Query) -> Vec<SearchHit<'_>> {
+        let text = query.text.as_ref().map(|t| t.to_lowercase());
+
+        // Narrow down the candidates with the indexes first.
+        let mut candidates: Option<Vec<u32>> = None;
+        if let Some(author) = &query.author {
+            let postings = self.authors.get(&author.to_lowercase());
+            candidates = Some(Self::narrow(candidates, postings));
+        }
+        if let Some(client) = &query.client {
+            candidates = Some(Self::narrow(candidates, self.clients.get(client)));
+        }
+        if let Some(text) = &text {
+            let chars: Vec<char> = text.chars().collect();
+            for trigram in chars.windows(3) {
+                let postings = self.trigrams.get(&[trigram[0], trigram[1], trigram[2]]);
+                candidates = Some(Self::narrow(candidates, postings));
+                if candidates.as_ref().is_some_and(Vec::is_empty) {
+                    break;
+                }
+            }
+        }
+
+        let matches = |message: &ChatMessage| {
+            text.as_ref()
+                .is_none_or(|t| message.body.to_lowercase().contains(t.as_str()))
+                && query
+                    .regex
+                    .as_ref()
+                    .is_none_or(|r| r.is_match(&message.body))
+        };
+
+        match candidates {
+            Some(candidates) => candidates
+                .into_iter()
+                .map(|i| i as usize)
+                .filter(|&i| matches(&self.messages[i]))
+                .map(|index| SearchHit {
+                    index,
+                    message: &self.messages[index],
+                })
+                .collect(),
+            None => self
+                .messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| matches(message))
+                .map(|(index, message)| SearchHit { index, message })
+                .collect(),
+        }
+    }
+
+    /// Intersects the current candidates with postings of another condition.
+    fn narrow(candidates: Option<Vec<u32>>, postings: Option<&Vec<u32>>) -> Vec<u32> {
+        let Some(postings) = postings else {
+            return Vec::new();
+        };
+        let Some(candidates) = candidates else {
+            return postings.clone();
+        };
+
+        // Both lists are sorted, so they can be intersected in a single pass.
+        let mut result = Vec::new();
+        let (mut a, mut b) = (candidates.iter().peekable(), postings.iter().peekable());
+        while let (Some(&&x), Some(&&y)) = (a.peek(), b.peek()) {
+            match x.cmp(&y) {
+                std::cmp::Ordering::Less => {
+                    a.next();
+                }
+                std::cmp::Ordering::Greater => {
+                    b.next();
+                }
+                std::cmp::Ordering::Equal => {
+                    result.push(x);
+                    a.next();
+                    b.next();
+                }
+            }
+        }
+        result
+    }
+}
@@ -1,36 +1,164 @@
+use crate::encoding::TextEncoding;
+use crate::input::InputPolicy;
+use std::fmt;
+use std::time::Duration;
 use thiserror::Error;
+use zeroize::Zeroize;
+
+/// A boxed error from a dependency, such as `native-tls` or `tungstenite`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A client operation, reported to metrics recorders and carried by errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Opening a connection, including the TLS and WebSocket handshakes.
+    Connect,
+    /// `fetch_messages_size`.
+    FetchMessagesSize,
+    /// `fetch_all_messages`.
+    FetchAllMessages,
+    /// `fetch_new_messages`.
+    FetchNewMessages,
+    /// `send_message` and `send_custom_message`.
+    SendMessage,
+    /// `register_user`.
+    RegisterUser,
+}
+
+impl Operation {
+    /// Returns the name of the operation in `snake_case`, suitable for metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Connect => "connect",
+            Operation::FetchMessagesSize => "fetch_messages_size",
+            Operation::FetchAllMessages => "fetch_all_messages",
+            Operation::FetchNewMessages => "fetch_new_messages",
+            Operation::SendMessage => "send_message",
+            Operation::RegisterUser => "register_user",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 /// Represents errors that can occur while interacting with the RAC server.
+///
+/// Errors caused by the network, TLS or WebSocket libraries keep the original error as
+/// their [`source`](std::error::Error::source), and their message doesn't repeat it.
+/// Walk the chain to show the full cause:
+///
+/// ```
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::{ErrorKind, Operation};
+/// use std::error::Error;
+///
+/// let mut client = RacClient::new("127.0.0.1:1", Default::default(), false);
+/// let error = client.fetch_messages_size().unwrap_err();
+///
+/// assert_eq!(error.kind(), ErrorKind::Connect);
+/// assert_eq!(error.operation(), Some(Operation::FetchMessagesSize));
+/// assert!(error.is_retryable());
+///
+/// let mut message = error.to_string();
+/// let mut source = error.source();
+/// while let Some(cause) = source {
+///     message.push_str(&format!(": {cause}"));
+///     source = cause.source();
+/// }
+/// println!("{message}");
+/// ```
 #[derive(Error, Debug)]
 pub enum ClientError {
-    /// Failed to establish a TCP connection to the RAC server.
-    #[error("Failed to connect to the RAC server: {0}")]
-    ConnectionError(std::io::Error),
+    /// Failed to establish a TCP connection to the server.
+    #[error("Failed to connect to the server ({operation})")]
+    ConnectionError {
+        /// The operation that needed the connection.
+        operation: Operation,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The TLS handshake with the server failed.
+    #[error("TLS handshake with the server failed ({operation})")]
+    TlsHandshakeError {
+        /// The operation that needed the connection.
+        operation: Operation,
+        /// The error reported by the TLS library.
+        #[source]
+        source: BoxError,
+    },
+
+    /// The server didn't accept the upgrade to WebSocket.
+    #[error("WebSocket handshake with the server failed ({operation})")]
+    WsHandshakeError {
+        /// The operation that needed the connection.
+        operation: Operation,
+        /// The error reported by the WebSocket library.
+        #[source]
+        source: BoxError,
+    },
 
     /// Failed to write data to the TCP stream.
-    #[error("Failed to write data to the stream: {0}")]
-    StreamWriteError(std::io::Error),
+    #[error("Failed to write data to the stream ({operation})")]
+    StreamWriteError {
+        /// The operation that was running.
+        operation: Operation,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
 
     /// Failed to read data from the TCP stream.
-    #[error("Failed to read data from the stream: {0}")]
-    StreamReadError(std::io::Error),
+    #[error("Failed to read data from the stream ({operation})")]
+    StreamReadError {
+        /// The operation that was running.
+        operation: Operation,
+        /// The underlying I/O error.
+        #[source]
+        source: std::io::Error,
+    },
 
-    /// Failed to read a message to the server via WebSocket.
-    #[error("Failed to read message via WebSocket: {0}")]
-    WsReadError(String),
+    /// Failed to read a message from the server via WebSocket.
+    #[error("Failed to read message via WebSocket ({operation})")]
+    WsReadError {
+        /// The operation that was running.
+        operation: Operation,
+        /// The error reported by the WebSocket library.
+        #[source]
+        source: BoxError,
+    },
 
     /// Failed to send a message to the server via WebSocket.
-    #[error("Failed to send message  via WebSocket: {0}")]
-    WsSendError(String),
+    #[error("Failed to send message via WebSocket ({operation})")]
+    WsSendError {
+        /// The operation that was running.
+        operation: Operation,
+        /// The error reported by the WebSocket library.
+        #[source]
+        source: BoxError,
+    },
 
     /// Failed to parse data received from the server.
-    #[error("Failed to parse data: {0}")]
-    ParseError(String),
-    
+    #[error("Failed to parse data ({operation}): {message}")]
+    ParseError {
+        /// The operation that was running.
+        operation: Operation,
+        /// What couldn't be parsed.
+        message: String,
+    },
+
     /// The server closed the connection while sending a packet.
-    #[error("Server closed the connection while sending a packet")]
-    ServerClosedConnection,
-    
+    #[error("Server closed the connection while sending a packet ({operation})")]
+    ServerClosedConnection {
+        /// The operation that was running.
+        operation: Operation,
+    },
+
     /// The specified user does not exist on the server (RACv2 only).
     #[error("User does not exist on the server")]
     UserDoesNotExist,
@@ -40,8 +168,13 @@ pub enum ClientError {
     IncorrectPassword,
 
     /// Received an unexpected response from the server.
-    #[error("Unexpected response from the server: {0}")]
-    UnexpectedResponse(String),
+    #[error("Unexpected response from the server ({operation}): {response}")]
+    UnexpectedResponse {
+        /// The operation that was running.
+        operation: Operation,
+        /// The response, as text.
+        response: String,
+    },
 
     /// The username is already taken during registration (RACv2 only).
     #[error("Username is already taken")]
@@ -52,20 +185,553 @@ pub enum ClientError {
     #[error("No password specified.")]
     NoPassword,
 
-    /// An error that occurs while initializing a TLS connection.
+    /// Failed to set up TLS before connecting, for example because the system
+    /// certificates couldn't be loaded or the address has no host name.
     #[error("Failed to initialize TLS connection: {0}")]
     TlsInitializationError(String),
 
-    /// An error that occurs when connection to WRAC server is not established first.
+    /// An error that occurs when connection to WRAC server is not established first,
+    /// or it was closed.
     #[error("Not connected to WRAC. Establish connection first.")]
     NoConnectionWRAC,
+
+    /// The server address can't be turned into a WebSocket URL.
+    #[error("Invalid server address `{address}`")]
+    InvalidAddress {
+        /// The address as given to the client.
+        address: String,
+        /// Why the address is invalid.
+        #[source]
+        source: BoxError,
+    },
+
+    /// The operation is not available in the protocol version the client is set to,
+    /// or the server answered it like a RACv1 server, with a status the operation doesn't have.
+    #[error("`{operation}` is not supported by {version}")]
+    UnsupportedOperation {
+        /// The operation that was attempted.
+        operation: Operation,
+        /// The protocol version of the client, or RACv1 if the server doesn't support the operation.
+        version: ProtocolVersion,
+    },
+
+    /// The server has less messages than the client already knows about,
+    /// which means that the history was cleared. Fetch all messages to start over.
+    #[error("Message history was reset on the server")]
+    HistoryReset,
+
+    /// Failed to read or write the local history cache.
+    #[error("Failed to access the history cache")]
+    CacheError(#[source] std::io::Error),
+
+    /// Failed to export or import the message history.
+    #[error("Failed to export or import history")]
+    ExportError(#[source] BoxError),
+
+    /// A credential provider failed to obtain the password.
+    #[error("Failed to obtain credentials")]
+    CredentialError(#[source] BoxError),
+
+    /// The client configuration or a profile is invalid.
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// A username, password or message can't be sent as it is, see [`InputPolicy`].
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    /// A message split into lines by [`InputPolicy::Split`] was sent only in part,
+    /// because sending one of the lines failed.
+    ///
+    /// The first `sent` lines are on the server, so sending the message again repeats them.
+    #[error("Sent {sent} of {total} lines of the message")]
+    PartialSend {
+        /// The number of lines that were sent.
+        sent: usize,
+        /// The number of lines of the message.
+        total: usize,
+        /// The error that stopped the send.
+        #[source]
+        source: Box<ClientError>,
+    },
+}
+
+/// A broad category of [`ClientError`], returned by [`ClientError::kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The TCP connection couldn't be established.
+    Connect,
+    /// The TLS or WebSocket handshake failed, or TLS couldn't be set up.
+    Handshake,
+    /// Reading from or writing to an established connection failed.
+    Io,
+    /// The server sent something the client doesn't understand.
+    Protocol,
+    /// The server rejected the credentials, or there are none.
+    Auth,
+    /// The client is not in a state to run the operation, for example not connected yet.
+    State,
+    /// The operation is not supported by the protocol version of the client.
+    Unsupported,
+    /// A local resource failed: the cache, an export, the configuration, the address or the input.
+    Local,
+}
+
+impl ClientError {
+    /// Returns the name of the variant, suitable for metric labels and log fields.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            ClientError::ConnectionError { .. } => "ConnectionError",
+            ClientError::TlsHandshakeError { .. } => "TlsHandshakeError",
+            ClientError::WsHandshakeError { .. } => "WsHandshakeError",
+            ClientError::StreamWriteError { .. } => "StreamWriteError",
+            ClientError::StreamReadError { .. } => "StreamReadError",
+            ClientError::WsReadError { .. } => "WsReadError",
+            ClientError::WsSendError { .. } => "WsSendError",
+            ClientError::ParseError { .. } => "ParseError",
+            ClientError::ServerClosedConnection { .. } => "ServerClosedConnection",
+            ClientError::UserDoesNotExist => "UserDoesNotExist",
+            ClientError::IncorrectPassword => "IncorrectPassword",
+            ClientError::UnexpectedResponse { .. } => "UnexpectedResponse",
+            ClientError::UsernameAlreadyTaken => "UsernameAlreadyTaken",
+            ClientError::NoPassword => "NoPassword",
+            ClientError::TlsInitializationError(_) => "TlsInitializationError",
+            ClientError::NoConnectionWRAC => "NoConnectionWRAC",
+            ClientError::InvalidAddress { .. } => "InvalidAddress",
+            ClientError::UnsupportedOperation { .. } => "UnsupportedOperation",
+            ClientError::HistoryReset => "HistoryReset",
+            ClientError::CacheError(_) => "CacheError",
+            ClientError::ExportError(_) => "ExportError",
+            ClientError::CredentialError(_) => "CredentialError",
+            ClientError::InvalidConfig(_) => "InvalidConfig",
+            ClientError::InvalidInput(_) => "InvalidInput",
+            ClientError::PartialSend { .. } => "PartialSend",
+        }
+    }
+
+    /// Returns the category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ClientError::ConnectionError { .. } => ErrorKind::Connect,
+            ClientError::TlsHandshakeError { .. }
+            | ClientError::WsHandshakeError { .. }
+            | ClientError::TlsInitializationError(_) => ErrorKind::Handshake,
+            ClientError::StreamWriteError { .. }
+            | ClientError::StreamReadError { .. }
+            | ClientError::WsReadError { .. }
+            | ClientError::WsSendError { .. }
+            | ClientError::ServerClosedConnection { .. } => ErrorKind::Io,
+            ClientError::ParseError { .. } | ClientError::UnexpectedResponse { .. } => {
+                ErrorKind::Protocol
+            }
+            ClientError::UserDoesNotExist
+            | ClientError::IncorrectPassword
+            | ClientError::UsernameAlreadyTaken
+            | ClientError::NoPassword
+            | ClientError::CredentialError(_) => ErrorKind::Auth,
+            ClientError::NoConnectionWRAC | ClientError::HistoryReset => ErrorKind::State,
+            ClientError::UnsupportedOperation { .. } => ErrorKind::Unsupported,
+            ClientError::CacheError(_)
+            | ClientError::ExportError(_)
+            | ClientError::InvalidConfig(_)
+            | ClientError::InvalidInput(_)
+            | ClientError::InvalidAddress { .. } => ErrorKind::Local,
+            ClientError::PartialSend { source, .. } => source.kind(),
+        }
+    }
+
+    /// Returns the operation that failed, if the error is tied to one.
+    ///
+    /// Errors during connecting carry the operation that needed the connection,
+    /// or [`Operation::Connect`] for `test_connection` and `prepare`.
+    pub fn operation(&self) -> Option<Operation> {
+        match self {
+            ClientError::ConnectionError { operation, .. }
+            | ClientError::TlsHandshakeError { operation, .. }
+            | ClientError::WsHandshakeError { operation, .. }
+            | ClientError::StreamWriteError { operation, .. }
+            | ClientError::StreamReadError { operation, .. }
+            | ClientError::WsReadError { operation, .. }
+            | ClientError::WsSendError { operation, .. }
+            | ClientError::ParseError { operation, .. }
+            | ClientError::ServerClosedConnection { operation }
+            | ClientError::UnexpectedResponse { operation, .. }
+            | ClientError::UnsupportedOperation { operation, .. } => Some(*operation),
+            ClientError::PartialSend { .. } => Some(Operation::SendMessage),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if running the operation again may succeed.
+    ///
+    /// Failed connections and handshakes are retryable when caused by a transient network
+    /// problem, like a refused or reset connection or a timeout. Other errors on an
+    /// established connection are retryable for fetches; WebSocket clients have to call
+    /// `prepare` again first. They aren't for sends and registrations, because the request
+    /// may have reached the server before the error, and running it again could post the
+    /// message twice. Protocol, authentication, state and local errors won't go away
+    /// by retrying.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClientError::ConnectionError { .. }
+            | ClientError::TlsHandshakeError { .. }
+            | ClientError::WsHandshakeError { .. } => self.has_transient_cause(),
+            ClientError::TlsInitializationError(_) => false,
+            _ if self.kind() != ErrorKind::Io => false,
+            _ => !matches!(
+                self.operation(),
+                Some(Operation::SendMessage | Operation::RegisterUser)
+            ),
+        }
+    }
+
+    /// Checks whether the chain of sources contains a transient I/O error.
+    fn has_transient_cause(&self) -> bool {
+        use std::error::Error as _;
+        use std::io::ErrorKind as IoKind;
+
+        let mut source = self.source();
+        while let Some(error) = source {
+            if let Some(error) = error.downcast_ref::<std::io::Error>() {
+                return matches!(
+                    error.kind(),
+                    IoKind::ConnectionRefused
+                        | IoKind::ConnectionReset
+                        | IoKind::ConnectionAborted
+                        | IoKind::NotConnected
+                        | IoKind::BrokenPipe
+                        | IoKind::TimedOut
+                        | IoKind::WouldBlock
+                        | IoKind::Interrupted
+                        | IoKind::UnexpectedEof
+                        | IoKind::HostUnreachable
+                        | IoKind::NetworkUnreachable
+                );
+            }
+            source = error.source();
+        }
+        false
+    }
+
+    /// Builds `ConnectionError`.
+    #[cfg(any(
+        feature = "client",
+        feature = "async_client",
+        feature = "wrac",
+        feature = "async_wrac"
+    ))]
+    pub(crate) fn connect(operation: Operation, source: std::io::Error) -> Self {
+        ClientError::ConnectionError { operation, source }
+    }
+
+    /// Builds `StreamReadError`.
+    #[cfg(any(feature = "client", feature = "async_client"))]
+    pub(crate) fn read(operation: Operation, source: std::io::Error) -> Self {
+        ClientError::StreamReadError { operation, source }
+    }
+
+    /// Builds `StreamWriteError`.
+    #[cfg(any(feature = "client", feature = "async_client"))]
+    pub(crate) fn write(operation: Operation, source: std::io::Error) -> Self {
+        ClientError::StreamWriteError { operation, source }
+    }
+
+    /// Builds `WsReadError`.
+    #[cfg(any(feature = "wrac", feature = "async_wrac"))]
+    pub(crate) fn ws_read(operation: Operation, source: impl Into<BoxError>) -> Self {
+        ClientError::WsReadError {
+            operation,
+            source: source.into(),
+        }
+    }
+
+    /// Builds `WsSendError`.
+    #[cfg(any(feature = "wrac", feature = "async_wrac"))]
+    pub(crate) fn ws_send(operation: Operation, source: impl Into<BoxError>) -> Self {
+        ClientError::WsSendError {
+            operation,
+            source: source.into(),
+        }
+    }
+
+    /// Builds `TlsHandshakeError`.
+    #[cfg(any(
+        feature = "client",
+        feature = "async_client",
+        feature = "wrac",
+        feature = "async_wrac"
+    ))]
+    pub(crate) fn tls_handshake(operation: Operation, source: impl Into<BoxError>) -> Self {
+        ClientError::TlsHandshakeError {
+            operation,
+            source: source.into(),
+        }
+    }
+
+    /// Builds `WsHandshakeError`.
+    #[cfg(any(feature = "wrac", feature = "async_wrac"))]
+    pub(crate) fn ws_handshake(operation: Operation, source: impl Into<BoxError>) -> Self {
+        ClientError::WsHandshakeError {
+            operation,
+            source: source.into(),
+        }
+    }
+
+    /// Wraps the error of the request at index `sent` out of `total` requests of one message
+    /// into `PartialSend`, unless it was the first one and nothing was sent.
+    #[cfg(any(
+        feature = "client",
+        feature = "async_client",
+        feature = "wrac",
+        feature = "async_wrac"
+    ))]
+    pub(crate) fn partial_send(sent: usize, total: usize, error: ClientError) -> Self {
+        if sent == 0 {
+            return error;
+        }
+        ClientError::PartialSend {
+            sent,
+            total,
+            source: Box::new(error),
+        }
+    }
+
+    /// Builds `ServerClosedConnection`.
+    #[cfg(any(
+        feature = "client",
+        feature = "async_client",
+        feature = "wrac",
+        feature = "async_wrac"
+    ))]
+    pub(crate) fn closed(operation: Operation) -> Self {
+        ClientError::ServerClosedConnection { operation }
+    }
+}
+
+/// A string that holds a secret, such as a password.
+///
+/// The contents are wiped from memory when the value is dropped and are never shown
+/// by `Debug` or `Display`, so secrets don't end up in logs. Use [`SecretString::expose`]
+/// to get the actual value.
+///
+/// With the `serde` feature, a secret can be deserialized, but not serialized.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::shared::SecretString;
+///
+/// let password = SecretString::from("password123");
+/// assert_eq!(password.expose(), "password123");
+/// assert_eq!(format!("{password}"), "***");
+/// assert_eq!(format!("{password:?}"), "SecretString(***)");
+/// ```
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    /// Wraps a string into a secret.
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    /// Returns the actual value of the secret.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns `true` if the secret is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(***)")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SecretString {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+/// The state of the WebSocket connection of a WRAC client.
+///
+/// A client starts `Disconnected` and is `Connecting` while `prepare` runs. When the
+/// connection is made, it becomes `Connected`, otherwise it goes back to the previous state.
+/// If reading or writing fails, or the server closes the connection, the client becomes
+/// `Closed` until `prepare` is called again. `reset` makes it `Disconnected`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    /// No connection was made yet, or the client was reset.
+    #[default]
+    Disconnected,
+    /// The connection is being made.
+    Connecting,
+    /// The connection is open and requests can be sent.
+    Connected,
+    /// The connection broke or the server closed it.
+    Closed,
 }
 
 /// Represents the credentials required to connect to a RAC server.
+///
+/// The password is redacted in `Debug` output. With the `serde` feature, the password
+/// is never serialized, so credentials can be saved together with other settings
+/// without leaking it.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Credentials {
     /// The username for authentication.
     pub username: String,
     /// The password for authentication. This is only used for `RACv2` connections.
-    pub password: Option<String>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing))]
+    pub password: Option<SecretString>,
+}
+
+/// The protocol used to talk to the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Protocol {
+    /// Plain TCP `RAC` protocol.
+    #[default]
+    Rac,
+    /// WebSocket-based `WRAC` protocol.
+    Wrac,
+}
+
+/// The version of the RAC protocol spoken by the server.
+///
+/// RACv1 has no user accounts: messages are always sent without authentication, the
+/// password is ignored and registering users fails with `ClientError::UnsupportedOperation`.
+/// Fetching messages works the same way in both versions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum ProtocolVersion {
+    /// The legacy protocol without authentication.
+    V1,
+    /// The current protocol with registration and authenticated sends.
+    #[default]
+    V2,
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolVersion::V1 => f.write_str("RACv1"),
+            ProtocolVersion::V2 => f.write_str("RACv2"),
+        }
+    }
+}
+
+/// Settings required to create a client.
+///
+/// With the `serde` feature, the timeout is written as a number of seconds, like in profiles,
+/// and the password is left out.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::shared::{ClientConfig, Credentials};
+///
+/// let config = ClientConfig {
+///     address: "127.0.0.1:42666".to_string(),
+///     credentials: Credentials {
+///         username: "test_user".to_string(),
+///         password: None,
+///     },
+///     ..Default::default()
+/// };
+///
+/// let client = RacClient::from_config(&config);
+/// assert_eq!(client.username(), "test_user");
+/// ```
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClientConfig {
+    /// The address of the server.
+    pub address: String,
+    /// The protocol of the server.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub protocol: Protocol,
+    /// The version of the protocol.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub version: ProtocolVersion,
+    /// Whether to use TLS encryption.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub use_tls: bool,
+    /// The username and optional password.
+    pub credentials: Credentials,
+    /// Timeout for connecting and for every read or write. `None` means waiting forever.
+    #[cfg_attr(feature = "serde", serde(default, with = "timeout_secs"))]
+    pub timeout: Option<Duration>,
+    /// The encoding of the messages on the server.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub encoding: TextEncoding,
+    /// What to do with messages that contain line breaks or other control characters.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub input_policy: InputPolicy,
+}
+
+/// (De)serializes an optional timeout as a positive number of seconds.
+#[cfg(feature = "serde")]
+mod timeout_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        timeout: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match timeout {
+            Some(timeout) => serializer.serialize_some(&timeout.as_secs_f64()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(deserializer)?
+            .map(|secs| {
+                Duration::try_from_secs_f64(secs)
+                    .ok()
+                    .filter(|timeout| !timeout.is_zero())
+                    .ok_or_else(|| {
+                        serde::de::Error::custom("timeout must be a positive number of seconds")
+                    })
+            })
+            .transpose()
+    }
 }
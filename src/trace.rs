@@ -0,0 +1,61 @@
+use crate::framing::FrameError;
+use crate::shared::{ClientError, Operation, ProtocolVersion};
+
+/// Emits a `tracing` event if the `tracing` feature is enabled and does nothing otherwise.
+///
+/// Takes the name of the `tracing` macro to use, followed by its arguments.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+/// Records a field of the current span if the `tracing` feature is enabled.
+macro_rules! trace_record {
+    ($field:literal, $value:expr) => {
+        #[cfg(feature = "tracing")]
+        tracing::Span::current().record($field, $value);
+    };
+}
+
+pub(crate) use {trace_event, trace_record};
+
+/// Builds `ClientError::UnexpectedResponse`, reporting the response as an event.
+pub(crate) fn unexpected_response(operation: Operation, response: String) -> ClientError {
+    trace_event!(warn, %response, "unexpected response from the server");
+    ClientError::UnexpectedResponse {
+        operation,
+        response,
+    }
+}
+
+/// Builds `ClientError::UnsupportedOperation` for a status that the request doesn't define,
+/// which is how servers that only speak RACv1 answer RACv2 requests.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn unsupported_by_server(operation: Operation, response: &str) -> ClientError {
+    trace_event!(warn, %response, "the server doesn't support the request");
+    ClientError::UnsupportedOperation {
+        operation,
+        version: ProtocolVersion::V1,
+    }
+}
+
+/// Builds `ClientError::ParseError` for a messages size that isn't a number.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn size_parse_error(operation: Operation, response: &str) -> ClientError {
+    trace_event!(warn, %response, "failed to parse messages size");
+    ClientError::ParseError {
+        operation,
+        message: "Failed to parse messages size".to_string(),
+    }
+}
+
+/// Builds the error for a response that couldn't be framed.
+pub(crate) fn frame_error(operation: Operation, error: FrameError) -> ClientError {
+    match error {
+        FrameError::Incomplete => ClientError::closed(operation),
+        FrameError::InvalidSize(response) => size_parse_error(operation, &response),
+        FrameError::InvalidStatus(response) => unexpected_response(operation, response),
+    }
+}
@@ -0,0 +1,354 @@
+use std::fmt::Debug;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// A stream that clients run the protocol over, such as a TCP connection.
+///
+/// Any `Read + Write` stream can be used by implementing this trait for it. Clients
+/// change the read timeout to wait for the rest of a messages size that arrived in pieces.
+/// Streams that don't support timeouts keep the default implementation, and clients take
+/// the size from the data that is available.
+pub trait Transport: Read + Write + Debug + Send + Sync {
+    /// Sets the timeout of reads, `None` meaning waiting forever.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ErrorKind::Unsupported` by default.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        let _ = timeout;
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Transport for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+}
+
+#[cfg(any(feature = "client", feature = "wrac"))]
+impl<S: Transport> Transport for native_tls::TlsStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
+
+#[cfg(feature = "chaos")]
+impl<S: Transport> Transport for crate::chaos::ChaosStream<S> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
+
+/// Opens connections for the synchronous clients.
+///
+/// Clients open a connection through their connector for every request, or once in
+/// `prepare` for WebSocket clients. TLS, if enabled, is negotiated over the returned stream.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::transport::{Connector, Transport};
+/// use std::io::{Cursor, Read, Write};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// /// A stream that plays back a recorded response.
+/// #[derive(Debug)]
+/// struct Recorded(Cursor<Vec<u8>>);
+///
+/// impl Read for Recorded {
+///     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+///         self.0.read(buf)
+///     }
+/// }
+///
+/// impl Write for Recorded {
+///     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+///         Ok(buf.len())
+///     }
+///
+///     fn flush(&mut self) -> std::io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// impl Transport for Recorded {}
+///
+/// #[derive(Debug)]
+/// struct Playback;
+///
+/// impl Connector for Playback {
+///     fn connect(
+///         &self,
+///         _address: &str,
+///         _timeout: Option<Duration>,
+///     ) -> std::io::Result<Box<dyn Transport>> {
+///         Ok(Box::new(Recorded(Cursor::new(b"5".to_vec()))))
+///     }
+/// }
+///
+/// let mut client = RacClient::new("recorded", Default::default(), false);
+/// client.update_connector(Some(Arc::new(Playback)));
+/// client.fetch_messages_size().unwrap();
+/// assert_eq!(client.current_messages_size(), 5);
+/// ```
+pub trait Connector: Debug + Send + Sync {
+    /// Opens a connection to `address`, giving up after `timeout`.
+    ///
+    /// Reads and writes on the returned stream should also time out after `timeout`.
+    fn connect(
+        &self,
+        address: &str,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Box<dyn Transport>>;
+}
+
+/// Connects over TCP. Clients use it when no other connector is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpConnector;
+
+impl TcpConnector {
+    /// Connects to the first address that the host resolves to and accepts the connection in time.
+    fn connect_timeout(address: &str, timeout: Duration) -> std::io::Result<TcpStream> {
+        let mut last_error = None;
+        for addr in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "address resolved to nothing",
+            )
+        }))
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(
+        &self,
+        address: &str,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Box<dyn Transport>> {
+        let stream = match timeout {
+            Some(timeout) => Self::connect_timeout(address, timeout)?,
+            None => TcpStream::connect(address)?,
+        };
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Connects to a Unix domain socket, whatever the address of the client is.
+///
+/// The address is still used for TLS and as the `Host` of WebSocket requests.
+///
+/// # Example
+///
+/// ```
+/// use rac_rs::rac::RacClient;
+/// use rac_rs::transport::UnixConnector;
+/// use std::io::{Read, Write};
+/// use std::os::unix::net::UnixListener;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let path = std::env::temp_dir().join(format!("rac-rs-doc-{}.sock", std::process::id()));
+/// let listener = UnixListener::bind(&path).unwrap();
+/// let server = thread::spawn(move || {
+///     let (mut stream, _) = listener.accept().unwrap();
+///     let mut command = [0u8; 1];
+///     stream.read_exact(&mut command).unwrap();
+///     stream.write_all(b"42").unwrap();
+/// });
+///
+/// let mut client = RacClient::new("localhost:42666", Default::default(), false);
+/// client.update_connector(Some(Arc::new(UnixConnector::new(&path))));
+/// client.fetch_messages_size().unwrap();
+/// assert_eq!(client.current_messages_size(), 42);
+///
+/// server.join().unwrap();
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    /// Path to the socket.
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    /// Creates a connector to the socket at `path`.
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Returns the path to the socket.
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    fn connect(
+        &self,
+        _address: &str,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Box<dyn Transport>> {
+        let stream = std::os::unix::net::UnixStream::connect(&self.path)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        Ok(Box::new(stream))
+    }
+}
+
+/// Returns the host name in the address of a client, which TLS checks the certificate
+/// of the server against.
+///
+/// The address is `host:port`, with IPv6 literals in brackets, or a `ws://` or `wss://` URL.
+/// An IPv6 literal without brackets is taken as a whole, because it has no port.
+///
+/// # Errors
+///
+/// Returns `ClientError::TlsInitializationError` if the address has no host name.
+#[cfg(any(
+    feature = "client",
+    feature = "async_client",
+    feature = "wrac",
+    feature = "async_wrac"
+))]
+pub(crate) fn tls_domain(address: &str) -> Result<&str, crate::shared::ClientError> {
+    let authority = address
+        .strip_prefix("wss://")
+        .or_else(|| address.strip_prefix("ws://"))
+        .unwrap_or(address);
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        Some(literal) => literal.split_once(']').map(|(host, _)| host),
+        None if authority.matches(':').count() > 1 => Some(authority),
+        None => authority.split(':').next(),
+    };
+    match host {
+        Some(host) if !host.is_empty() => Ok(host),
+        _ => Err(crate::shared::ClientError::TlsInitializationError(format!(
+            "No host name in the address `{address}`"
+        ))),
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+pub use self::async_transport::{AsyncConnector, AsyncTransport, ConnectFuture};
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+mod async_transport {
+    use super::TcpConnector;
+    #[cfg(unix)]
+    use super::UnixConnector;
+    use crate::runtime;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::fmt::Debug;
+    use std::pin::Pin;
+
+    /// A stream that async clients run the protocol over, such as a TCP connection.
+    ///
+    /// Implemented for every `futures-io` `AsyncRead + AsyncWrite` stream that can be moved
+    /// between threads, so any runtime can be used. Streams of `tokio` are adapted with
+    /// `tokio_util::compat`.
+    pub trait AsyncTransport: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync {}
+
+    impl<T: AsyncRead + AsyncWrite + Unpin + Debug + Send + Sync + ?Sized> AsyncTransport for T {}
+
+    /// The future returned by [`AsyncConnector::connect`].
+    pub type ConnectFuture<'a> =
+        Pin<Box<dyn Future<Output = std::io::Result<Box<dyn AsyncTransport>>> + Send + 'a>>;
+
+    /// Opens connections for the async clients.
+    ///
+    /// Clients apply their timeout to the returned future, and TLS, if enabled,
+    /// is negotiated over the returned stream.
+    ///
+    /// # Example
+    ///
+    /// The server runs on `tokio`, but the connector works the same on any runtime:
+    ///
+    #[cfg_attr(all(feature = "async_client", feature = "tokio"), doc = "```")]
+    #[cfg_attr(
+        not(all(feature = "async_client", feature = "tokio")),
+        doc = "```ignore"
+    )]
+    /// use rac_rs::async_rac::RacClient;
+    /// use rac_rs::transport::{AsyncConnector, AsyncTransport, ConnectFuture};
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use tokio_util::compat::TokioAsyncReadCompatExt;
+    ///
+    /// /// Runs a server in memory for every connection.
+    /// #[derive(Debug)]
+    /// struct InMemory;
+    ///
+    /// impl AsyncConnector for InMemory {
+    ///     fn connect<'a>(&'a self, _address: &'a str) -> ConnectFuture<'a> {
+    ///         Box::pin(async {
+    ///             let (client, mut server) = tokio::io::duplex(1024);
+    ///             tokio::spawn(async move {
+    ///                 let mut command = [0u8; 1];
+    ///                 server.read_exact(&mut command).await?;
+    ///                 server.write_all(b"6").await?;
+    ///                 server.read_exact(&mut command).await?;
+    ///                 server.write_all(b"Hello\n").await
+    ///             });
+    ///             Ok(Box::new(client.compat()) as Box<dyn AsyncTransport>)
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut client = RacClient::new("in-memory", Default::default(), false);
+    /// client.update_connector(Some(Arc::new(InMemory)));
+    /// let messages = client.fetch_all_messages().await.unwrap();
+    /// assert_eq!(messages, vec!["Hello"]);
+    /// # }
+    /// ```
+    pub trait AsyncConnector: Debug + Send + Sync {
+        /// Opens a connection to `address`.
+        fn connect<'a>(&'a self, address: &'a str) -> ConnectFuture<'a>;
+    }
+
+    impl AsyncConnector for TcpConnector {
+        fn connect<'a>(&'a self, address: &'a str) -> ConnectFuture<'a> {
+            Box::pin(runtime::connect_tcp(address))
+        }
+    }
+
+    #[cfg(unix)]
+    impl AsyncConnector for UnixConnector {
+        fn connect<'a>(&'a self, _address: &'a str) -> ConnectFuture<'a> {
+            Box::pin(runtime::connect_unix(self.path()))
+        }
+    }
+}
@@ -1,10 +1,64 @@
-﻿use crate::shared::{ClientError, Credentials};
+﻿#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::credentials::{CredentialProvider, resolve_password};
+use crate::encoding::TextEncoding;
+use crate::framing::{FrameError, SizeDecoder, StatusDecoder};
+use crate::input::InputPolicy;
+use crate::metrics::{self, MetricsRecorder, Operation};
+use crate::protocol::{
+    self, Expect, FetchAll, FetchNew, FetchSize, Messages, Register, Response, SendMessage, Step,
+};
+use crate::shared::{
+    BoxError, ClientConfig, ClientError, ConnectionState, Credentials, ErrorKind, Protocol,
+    ProtocolVersion, SecretString,
+};
+use crate::trace::{self, trace_event, trace_record};
+use crate::transport::{self, Connector, TcpConnector, Transport};
+use native_tls::{TlsConnector, TlsStream};
 use std::borrow::Cow;
-use std::net::TcpStream;
-use tungstenite::{Message, WebSocket, client::IntoClientRequest, connect, stream::MaybeTlsStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tungstenite::{
+    HandshakeError, Message, WebSocket, client::IntoClientRequest, error::UrlError,
+    handshake::client::Request, stream::MaybeTlsStream,
+};
 
 /// Concrete WebSocket stream type we deal with.
-type WsStream = WebSocket<MaybeTlsStream<TcpStream>>;
+type WsStream = WebSocket<MaybeTlsStream<Box<dyn Transport>>>;
+
+/// The WebSocket connection, if there is one, and the state it is in.
+#[derive(Debug, Default)]
+enum Connection {
+    /// No connection was made yet, or the client was reset.
+    #[default]
+    Disconnected,
+    /// `prepare` is running.
+    Connecting,
+    /// The connection is open.
+    Connected(Box<WsStream>),
+    /// The connection broke or the server closed it.
+    Closed,
+}
+
+impl Connection {
+    /// Returns the public view of the state.
+    fn state(&self) -> ConnectionState {
+        match self {
+            Connection::Disconnected => ConnectionState::Disconnected,
+            Connection::Connecting => ConnectionState::Connecting,
+            Connection::Connected(_) => ConnectionState::Connected,
+            Connection::Closed => ConnectionState::Closed,
+        }
+    }
+
+    /// Returns the open connection, or `ClientError::NoConnectionWRAC` if there is none.
+    fn stream(&mut self) -> Result<&mut WsStream, ClientError> {
+        match self {
+            Connection::Connected(ws) => Ok(ws.as_mut()),
+            _ => Err(ClientError::NoConnectionWRAC),
+        }
+    }
+}
 
 /// A WebSocket client for interacting with a WRAC server.
 ///
@@ -19,7 +73,7 @@ type WsStream = WebSocket<MaybeTlsStream<TcpStream>>;
 /// # fn run() -> Result<(), rac_rs::shared::ClientError> {
 /// let credentials = Credentials {
 ///     username: "test_user".to_string(),
-///     password: Some("password123".to_string()),
+///     password: Some("password123".into()),
 /// };
 ///
 /// let mut client = WClient::new(
@@ -37,6 +91,8 @@ type WsStream = WebSocket<MaybeTlsStream<TcpStream>>;
 pub struct WClient {
     /// The current size of messages in the client.
     current_messages_size: usize,
+    /// Where the final message of the last fetch starts in the history.
+    last_message_offset: usize,
     /// The address of the RAC server. Can be a full `ws(s)://` URL or just `host:port`.
     address: String,
     /// Whether to use TLS encryption (`wss://`).
@@ -44,9 +100,26 @@ pub struct WClient {
     /// The username for authentication.
     username: String,
     /// The password for authentication, if required.
-    password: Option<String>,
+    password: Option<SecretString>,
+    /// Source of the password, used when no password is set directly.
+    password_provider: Option<Arc<dyn CredentialProvider>>,
+    /// Receiver of request measurements.
+    metrics: Option<Arc<dyn MetricsRecorder>>,
+    /// Opener of connections, TCP if not set.
+    connector: Option<Arc<dyn Connector>>,
+    /// Injector of network faults, used for resilience tests.
+    #[cfg(feature = "chaos")]
+    chaos: Option<Arc<Chaos>>,
     /// Holds the WebSocket connection to WRAC.
-    ws_connection: Option<WsStream>,
+    connection: Connection,
+    /// The version of the protocol.
+    version: ProtocolVersion,
+    /// Timeout for connecting and for every read or write.
+    timeout: Option<Duration>,
+    /// The encoding of the messages on the server.
+    encoding: TextEncoding,
+    /// What to do with messages that contain control characters.
+    input_policy: InputPolicy,
 }
 
 impl WClient {
@@ -62,14 +135,36 @@ impl WClient {
     pub fn new(address: &str, credentials: Credentials, use_tls: bool) -> Self {
         Self {
             current_messages_size: 0,
+            last_message_offset: 0,
             address: address.to_string(),
             use_tls,
             username: credentials.username,
             password: credentials.password,
-            ws_connection: None,
+            password_provider: None,
+            metrics: None,
+            connector: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            connection: Connection::Disconnected,
+            version: ProtocolVersion::V2,
+            timeout: None,
+            encoding: TextEncoding::default(),
+            input_policy: InputPolicy::default(),
         }
     }
 
+    /// Creates a new client from the settings in `config`.
+    ///
+    /// The `protocol` field of the config is not checked.
+    pub fn from_config(config: &ClientConfig) -> Self {
+        let mut client = Self::new(&config.address, config.credentials.clone(), config.use_tls);
+        client.timeout = config.timeout;
+        client.version = config.version;
+        client.encoding = config.encoding;
+        client.input_policy = config.input_policy;
+        client
+    }
+
     /// Updates the client's credentials.
     ///
     /// This method allows you to change the username and password for the client.
@@ -78,6 +173,35 @@ impl WClient {
         self.password = credentials.password;
     }
 
+    /// Updates the source of the password.
+    ///
+    /// The provider is asked for the password every time the client authenticates,
+    /// unless a password is set directly in the credentials.
+    pub fn update_password_provider(&mut self, provider: Option<Arc<dyn CredentialProvider>>) {
+        self.password_provider = provider;
+    }
+
+    /// Updates the receiver of request counts, latencies, received bytes and errors.
+    pub fn update_metrics(&mut self, metrics: Option<Arc<dyn MetricsRecorder>>) {
+        self.metrics = metrics;
+    }
+
+    /// Updates the opener of connections.
+    ///
+    /// `None` means connecting over TCP, which is the default. The connector gets the host
+    /// and port of the WebSocket URL as the address.
+    pub fn update_connector(&mut self, connector: Option<Arc<dyn Connector>>) {
+        self.connector = connector;
+    }
+
+    /// Updates the injector of network faults.
+    ///
+    /// Every connection made after this goes through the injector. Only use it in tests.
+    #[cfg(feature = "chaos")]
+    pub fn update_chaos(&mut self, chaos: Option<Arc<Chaos>>) {
+        self.chaos = chaos;
+    }
+
     /// Updates the client's TLS usage.
     ///
     /// This method allows you to enable or disable TLS encryption for the connection.
@@ -85,6 +209,36 @@ impl WClient {
         self.use_tls = use_tls;
     }
 
+    /// Updates the version of the protocol spoken by the server.
+    ///
+    /// With RACv1, messages are sent without authentication and `register_user` fails
+    /// with `ClientError::UnsupportedOperation`.
+    pub fn update_version(&mut self, version: ProtocolVersion) {
+        self.version = version;
+    }
+
+    /// Updates the timeout for connecting and for every read or write.
+    ///
+    /// `None` means waiting forever, which is the default.
+    pub fn update_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Updates the encoding of the messages on the server.
+    ///
+    /// Fetched messages are decoded and sent messages are encoded with it.
+    /// Lossy UTF-8 is the default.
+    pub fn update_encoding(&mut self, encoding: TextEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Updates what to do with messages that contain line breaks or other control characters.
+    ///
+    /// Such messages are rejected with `ClientError::InvalidInput` by default.
+    pub fn update_input_policy(&mut self, policy: InputPolicy) {
+        self.input_policy = policy;
+    }
+
     /// Updates the client's address to the server.
     ///
     /// This method allows you to change the address of the RAC server.
@@ -92,6 +246,14 @@ impl WClient {
         self.address = address;
     }
 
+    /// Updates the size of messages known to the client.
+    ///
+    /// The next call to `fetch_new_messages` will fetch messages starting from this size.
+    /// This is used to resume from a size saved earlier, for example in a `HistoryCache`.
+    pub fn update_current_messages_size(&mut self, size: usize) {
+        self.current_messages_size = size;
+    }
+
     /// Turn the user‑supplied `address` into a valid WebSocket URL.
     fn build_url(&self) -> Result<String, ClientError> {
         if self.address.starts_with("ws://") || self.address.starts_with("wss://") {
@@ -102,25 +264,252 @@ impl WClient {
     }
 
     /// Establishes a WebSocket connection to the RAC server.
-    fn get_ws(&self) -> Result<WsStream, ClientError> {
-        let url = self.build_url()?;
-        let (ws, _resp) = connect(url.into_client_request().unwrap())
-            .map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
+    ///
+    /// The connection and the TLS handshake are made here instead of `tungstenite::connect`,
+    /// because the latter doesn't support timeouts and panics if the TLS handshake times out.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "connect", skip_all, fields(address = %self.address, tls = self.use_tls))
+    )]
+    fn get_ws(&self, operation: Operation) -> Result<WsStream, ClientError> {
+        let started = Instant::now();
+        let result = self.get_ws_inner(operation);
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::Connect,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::get_ws`] without reporting it to the metrics recorder.
+    fn get_ws_inner(&self, operation: Operation) -> Result<WsStream, ClientError> {
+        let (request, host, port) = self.build_request()?;
+
+        let address = format!("{host}:{port}");
+        let stream = match &self.connector {
+            Some(connector) => connector.connect(&address, self.timeout),
+            None => TcpConnector.connect(&address, self.timeout),
+        }
+        .map_err(|e| ClientError::connect(operation, e))?;
+
+        let stream = self.wrap_chaos(stream);
+        let stream = if request.uri().scheme_str() == Some("wss") {
+            MaybeTlsStream::NativeTls(Self::tls_handshake(
+                operation,
+                transport::tls_domain(&self.address)?,
+                stream,
+            )?)
+        } else {
+            MaybeTlsStream::Plain(stream)
+        };
+        Self::websocket_upgrade(operation, request, stream)
+    }
+
+    /// Passes the stream through the fault injector, if there is one.
+    ///
+    /// Null padding is never injected, because it would break the WebSocket frames.
+    fn wrap_chaos(&self, stream: Box<dyn Transport>) -> Box<dyn Transport> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            return Box::new(chaos.wrap_framed(stream));
+        }
+        stream
+    }
+
+    /// Builds the WebSocket handshake request and finds the host and port to connect to.
+    fn build_request(&self) -> Result<(Request, String, u16), ClientError> {
+        let invalid = |source: BoxError| ClientError::InvalidAddress {
+            address: self.address.clone(),
+            source,
+        };
+        let request = self
+            .build_url()?
+            .into_client_request()
+            .map_err(|e| invalid(e.into()))?;
+        let uri = request.uri();
+        let host = uri
+            .host()
+            .ok_or_else(|| invalid(tungstenite::Error::Url(UrlError::NoHostName).into()))?
+            .to_string();
+        // The URI has no port if the text after the last colon is not a number up to 65535.
+        let authority = uri.authority().map_or("", |a| a.as_str());
+        let host_port = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let port = match (uri.port_u16(), host_port.rsplit_once(':')) {
+            (Some(port), _) => port,
+            (None, Some((_, port))) if !port.is_empty() && !port.ends_with(']') => {
+                return Err(invalid(format!("invalid port `{port}`").into()));
+            }
+            _ if uri.scheme_str() == Some("wss") => 443,
+            _ => 80,
+        };
+        Ok((request, host, port))
+    }
+
+    /// Performs the TLS handshake over an established connection.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    fn tls_handshake(
+        operation: Operation,
+        host: &str,
+        stream: Box<dyn Transport>,
+    ) -> Result<TlsStream<Box<dyn Transport>>, ClientError> {
+        let connector =
+            TlsConnector::new().map_err(|e| ClientError::TlsInitializationError(e.to_string()))?;
+        connector.connect(host, stream).map_err(|e| match e {
+            native_tls::HandshakeError::Failure(e) => ClientError::tls_handshake(operation, e),
+            // The handshake is only interrupted when a blocking read or write times out.
+            native_tls::HandshakeError::WouldBlock(_) => ClientError::tls_handshake(
+                operation,
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "TLS handshake timed out"),
+            ),
+        })
+    }
+
+    /// Upgrades the connection to WebSocket.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    fn websocket_upgrade(
+        operation: Operation,
+        request: Request,
+        stream: MaybeTlsStream<Box<dyn Transport>>,
+    ) -> Result<WsStream, ClientError> {
+        let (ws, _resp) = tungstenite::client(request, stream).map_err(|e| match e {
+            HandshakeError::Failure(e) => ClientError::ws_handshake(operation, e),
+            // The handshake is only interrupted when a blocking read or write times out.
+            HandshakeError::Interrupted(_) => ClientError::ws_handshake(
+                operation,
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "WebSocket handshake timed out",
+                ),
+            ),
+        })?;
         Ok(ws)
     }
 
     /// Initializes the connection to WRAC server.
+    ///
+    /// If there already is a connection, it is replaced with a new one.
+    /// If connecting fails, the client stays in the state it was in before.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ClientError::InvalidAddress` if the address is not a valid WebSocket URL.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use rac_rs::shared::{ClientError, ConnectionState};
+    /// use rac_rs::wrac::WClient;
+    ///
+    /// let mut client = WClient::new("not a valid address", Default::default(), false);
+    /// let error = client.prepare().unwrap_err();
+    ///
+    /// assert!(matches!(error, ClientError::InvalidAddress { .. }));
+    /// assert_eq!(client.state(), ConnectionState::Disconnected);
+    /// ```
     pub fn prepare(&mut self) -> Result<(), ClientError> {
-        self.ws_connection = Some(self.get_ws()?);
-        Ok(())
+        let previous = std::mem::replace(&mut self.connection, Connection::Connecting);
+        match self.get_ws(Operation::Connect) {
+            Ok(ws) => {
+                if matches!(previous, Connection::Connected(_) | Connection::Closed)
+                    && let Some(metrics) = &self.metrics
+                {
+                    metrics.reconnect(&self.address);
+                }
+                self.connection = Connection::Connected(Box::new(ws));
+                Ok(())
+            }
+            Err(e) => {
+                self.connection = previous;
+                Err(e)
+            }
+        }
     }
 
-    /// Checks if connection is established.
-    fn check_connection(&self) -> Result<(), ClientError> {
-        if self.ws_connection.is_none() {
-            Err(ClientError::NoConnectionWRAC)
-        } else {
-            Ok(())
+    /// Marks the connection as closed if `error` broke it.
+    fn close_if_broken(&mut self, error: &ClientError) {
+        if error.kind() == ErrorKind::Io && matches!(self.connection, Connection::Connected(_)) {
+            trace_event!(debug, "connection closed");
+            self.connection = Connection::Closed;
+        }
+    }
+
+    /// Runs `request` over the open connection.
+    fn run<R: protocol::Request>(&mut self, request: R) -> Result<R::Output, ClientError> {
+        let ws = self.connection.stream()?;
+        Self::exchange(ws, &self.metrics, &self.address, request)
+    }
+
+    /// Runs `request` over `ws`, sending every step as a binary message.
+    fn exchange<R: protocol::Request>(
+        ws: &mut WsStream,
+        metrics: &Option<Arc<dyn MetricsRecorder>>,
+        address: &str,
+        mut request: R,
+    ) -> Result<R::Output, ClientError> {
+        let op = request.operation();
+        let mut received = 0;
+        let mut response = None;
+        loop {
+            let (data, expect) = match request.resume(response.take())? {
+                Step::Send { data, expect } => (data, expect),
+                Step::Done(output) => {
+                    if received > 0 {
+                        trace_record!("bytes_received", received);
+                        metrics::record_bytes(metrics, address, op, received);
+                    }
+                    return Ok(output);
+                }
+            };
+            ws.send(Message::Binary(data.to_vec().into()))
+                .map_err(|e| ClientError::ws_send(op, e))?;
+            response = match expect {
+                Expect::Nothing => None,
+                Expect::Size => {
+                    let data = Self::read_data(op, ws)?;
+                    received += data.len();
+                    let size = SizeDecoder::decode(&data).map_err(|e| trace::frame_error(op, e))?;
+                    Some(Response::Size(size))
+                }
+                Expect::Payload(_) => {
+                    let data = Self::read_data(op, ws)?;
+                    received += data.len();
+                    Some(Response::Payload(data))
+                }
+                // The server replies with an empty message on success and the status on failure.
+                Expect::Status => {
+                    let status = match Self::read_message(op, ws)? {
+                        Message::Binary(buf) => StatusDecoder::decode(&buf),
+                        message => Err(FrameError::InvalidStatus(format!("{message:?}"))),
+                    };
+                    Some(Response::Status(
+                        status.map_err(|e| trace::frame_error(op, e))?,
+                    ))
+                }
+            };
+        }
+    }
+
+    /// Reads the data of the next text or binary message.
+    fn read_data(operation: Operation, ws: &mut WsStream) -> Result<Vec<u8>, ClientError> {
+        match Self::read_message(operation, ws)? {
+            Message::Text(text) => Ok(text.as_bytes().to_vec()),
+            message => Ok(message.into_data().to_vec()),
+        }
+    }
+
+    /// Reads the next text or binary message.
+    fn read_message(operation: Operation, ws: &mut WsStream) -> Result<Message, ClientError> {
+        loop {
+            match ws.read().map_err(|e| ClientError::ws_read(operation, e))? {
+                message @ (Message::Text(_) | Message::Binary(_)) => return Ok(message),
+                Message::Close(_) => return Err(ClientError::closed(operation)),
+                // Pings are answered by `tungstenite`.
+                _ => {}
+            }
         }
     }
 
@@ -130,50 +519,68 @@ impl WClient {
     ///
     /// Returns `ClientError::NoPassword` if no password specified for the client.
     /// Returns `ClientError::UsernameAlreadyTaken` if the username is already in use.
+    /// Returns `ClientError::UnsupportedOperation` if the client or the server only speaks RACv1.
     /// Returns `ClientError::UnexpectedResponse` if got unexpected response from server.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, username = %self.username),
+            err(level = "warn")
+        )
+    )]
     pub fn register_user(&mut self) -> Result<(), ClientError> {
-        let mut ws = self.get_ws()?;
-        if self.password.is_some() {
-            let payload = format!(
-                "\x03{}\n{}",
-                self.username,
-                self.password.as_deref().unwrap()
-            );
-            ws.send(Message::Binary(payload.into()))
-                .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-
-            if let Ok(Message::Binary(buf)) = ws.read() {
-                return match buf.first() {
-                    Some(0x01) => Err(ClientError::UsernameAlreadyTaken),
-                    Some(code) => Err(ClientError::UnexpectedResponse(format!("0x{code:02x}"))),
-                    None => Ok(()),
-                };
-            }
-            return Ok(());
-        }
-        Err(ClientError::NoPassword)
+        let started = Instant::now();
+        let result = self.register_user_inner();
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::RegisterUser,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::register_user`] without reporting it to the metrics recorder.
+    fn register_user_inner(&mut self) -> Result<(), ClientError> {
+        let request = Register::for_client(
+            self.version,
+            &self.username,
+            resolve_password(self.version, &self.password, &self.password_provider)?,
+        )?;
+        // Registration runs on its own connection.
+        let mut ws = self.get_ws(Operation::RegisterUser)?;
+        Self::exchange(&mut ws, &self.metrics, &self.address, request)
     }
 
     /// Fetches the total size of all messages on the server and updates the client's internal state.
     ///
     /// This is useful for determining the amount of data to fetch for all messages.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
     pub fn fetch_messages_size(&mut self) -> Result<(), ClientError> {
-        self.check_connection()?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(vec![0x00].into()))
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        let msg = ws
-            .read()
-            .map_err(|e| ClientError::WsReadError(e.to_string()))?;
-        let txt = match msg {
-            Message::Text(t) => t.to_string(),
-            Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-            _ => String::new(),
-        };
-        self.current_messages_size = txt
-            .trim()
-            .parse::<usize>()
-            .map_err(|_| ClientError::ParseError("Failed to parse messages size".into()))?;
+        let started = Instant::now();
+        let result = self
+            .fetch_messages_size_inner()
+            .inspect_err(|e| self.close_if_broken(e));
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchMessagesSize,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_messages_size`] without reporting it to the metrics recorder.
+    fn fetch_messages_size_inner(&mut self) -> Result<(), ClientError> {
+        self.current_messages_size = self.run(FetchSize::new())?;
         Ok(())
     }
 
@@ -181,25 +588,38 @@ impl WClient {
     ///
     /// This method retrieves all messages stored on the server and updates the
     /// client's internal message size tracker.
-    pub fn fetch_all_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        // Fetching new size explicitly
-        self.fetch_messages_size()?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(vec![0x01].into()))
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        let all_msg = ws
-            .read()
-            .map_err(|e| ClientError::WsReadError(e.to_string()))?;
-        let payload = match all_msg {
-            Message::Text(t) => t.to_string(),
-            Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-            _ => String::new(),
-        };
-        Ok(payload
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect())
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(address = %self.address, bytes_received = tracing::field::Empty),
+            err(level = "warn")
+        )
+    )]
+    pub fn fetch_all_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self
+            .fetch_all_messages_inner()
+            .inspect_err(|e| self.close_if_broken(e));
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchAllMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_all_messages`] without reporting it to the metrics recorder.
+    fn fetch_all_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(FetchAll::new().with_encoding(self.encoding))?;
+        self.current_messages_size = size;
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Fetches only new messages that have arrived since the last fetch.
@@ -207,33 +627,47 @@ impl WClient {
     /// This method compares the current message size on the server with the client's
     /// stored size and retrieves only the difference. The client's internal message
     /// size tracker is updated upon successful fetch.
-    pub fn fetch_new_messages(&mut self) -> Result<Vec<Cow<str>>, ClientError> {
-        let old_size = self.current_messages_size;
-        // Fetching new size via fetch_current_size because it's WRAC
-        self.fetch_messages_size()?;
-        let new_size = self.current_messages_size;
-        if old_size >= new_size {
-            return Ok(Vec::new());
-        }
-        // Because the first one will be closed after our request.
-        let ws = self.ws_connection.as_mut().unwrap();
-        ws.send(Message::Binary(
-            format!("\x00\x02{}", self.current_messages_size).into(),
-        ))
-        .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-        let diff_msg = ws
-            .read()
-            .map_err(|e| ClientError::WsReadError(e.to_string()))?;
-        let payload = match diff_msg {
-            Message::Text(t) => t.to_string(),
-            Message::Binary(b) => String::from_utf8_lossy(&b).into_owned(),
-            _ => String::new(),
-        };
-        Ok(payload
-            .lines()
-            .filter(|l| !l.is_empty())
-            .map(|s| Cow::Owned(s.to_string()))
-            .collect())
+    ///
+    /// A message that the server hasn't finished writing is left for the next fetch,
+    /// so messages are never cut in half, even in the middle of a multi-byte character.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                address = %self.address,
+                from = self.current_messages_size,
+                bytes_received = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
+    pub fn fetch_new_messages(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let started = Instant::now();
+        let result = self
+            .fetch_new_messages_inner()
+            .inspect_err(|e| self.close_if_broken(e));
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::FetchNewMessages,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::fetch_new_messages`] without reporting it to the metrics recorder.
+    fn fetch_new_messages_inner(&mut self) -> Result<Vec<Cow<'static, str>>, ClientError> {
+        let request =
+            FetchNew::new(Protocol::Wrac, self.current_messages_size).with_encoding(self.encoding);
+        let Messages {
+            size,
+            messages,
+            last_message,
+        } = self.run(request)?;
+        self.current_messages_size = size;
+        self.last_message_offset = last_message;
+        Ok(messages)
     }
 
     /// Sends a message to the server.
@@ -244,10 +678,10 @@ impl WClient {
     ///
     /// ```no_run
     /// # use rac_rs::wrac::WClient;
-    /// # use rac_rs::shared::{ClientError, Credentials};
-    /// # async fn run() -> Result<(), ClientError> {
+    /// # use rac_rs::shared::ClientError;
+    /// # fn run() -> Result<(), ClientError> {
     /// # let mut client = WClient::new("", Default::default(), false);
-    /// client.send_message("<{username}> Hello everyone!").await?;
+    /// client.send_message("<{username}> Hello everyone!")?;
     /// # Ok(())
     /// # }
     /// ```
@@ -256,45 +690,72 @@ impl WClient {
         self.send_custom_message(&msg)
     }
 
-    /// Sends a raw message to the server without any modifications.
+    /// Sends a raw message to the server without replacing `{username}`.
+    ///
+    /// Line breaks and other control characters are handled according to the input policy,
+    /// see [`Self::update_input_policy`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            name = "send_message",
+            skip_all,
+            fields(
+                address = %self.address,
+                message_bytes = message.len(),
+                authenticated = tracing::field::Empty
+            ),
+            err(level = "warn")
+        )
+    )]
     pub fn send_custom_message(&mut self, message: &str) -> Result<(), ClientError> {
-        self.check_connection()?;
-        let ws = self.ws_connection.as_mut().unwrap();
-        if self.password.is_some() {
-            let payload = format!(
-                "\x02{}\n{}\n{}",
-                self.username,
-                self.password.as_deref().unwrap(),
-                message
-            );
-            ws.send(Message::Binary(payload.into()))
-                .map_err(|e| ClientError::WsSendError(e.to_string()))?;
-            if let Ok(Message::Binary(buf)) = ws.read() {
-                return match buf.first() {
-                    Some(0x01) => Err(ClientError::UserDoesNotExist),
-                    Some(0x02) => Err(ClientError::IncorrectPassword),
-                    Some(code) => Err(ClientError::UnexpectedResponse(format!("0x{code:02x}"))),
-                    None => Ok(()),
-                };
-            }
-            return Ok(());
+        let started = Instant::now();
+        let result = self
+            .send_custom_message_inner(message)
+            .inspect_err(|e| self.close_if_broken(e));
+        metrics::record_request(
+            &self.metrics,
+            &self.address,
+            Operation::SendMessage,
+            started,
+            result,
+        )
+    }
+
+    /// Runs [`Self::send_custom_message`] without reporting it to the metrics recorder.
+    fn send_custom_message_inner(&mut self, message: &str) -> Result<(), ClientError> {
+        let messages = self.input_policy.apply(message)?;
+        let requests = SendMessage::for_client(
+            self.version,
+            &self.username,
+            resolve_password(self.version, &self.password, &self.password_provider)?,
+            messages.iter().map(|message| self.encoding.encode(message)),
+        )?;
+        let total = requests.len();
+        for (sent, request) in requests.into_iter().enumerate() {
+            self.run(request)
+                .map_err(|e| ClientError::partial_send(sent, total, e))?;
         }
-        ws.send(Message::Binary(format!("\x01{}", message).into()))
-            .map_err(|e| ClientError::WsSendError(e.to_string()))?;
         Ok(())
     }
 
     /// Resets the client's state to its default values and closes WebSocket connection.
     pub fn reset(&mut self) {
         self.current_messages_size = 0;
+        self.last_message_offset = 0;
         self.address.clear();
         self.username.clear();
         self.password = None;
+        self.password_provider = None;
         self.use_tls = false;
-        if let Some(ws) = &mut self.ws_connection {
+        if let Connection::Connected(ws) = &mut self.connection {
             let _ = ws.close(None);
-            self.ws_connection = None;
         }
+        self.connection = Connection::Disconnected;
+    }
+
+    /// Returns the state of the WebSocket connection.
+    pub fn state(&self) -> ConnectionState {
+        self.connection.state()
     }
 
     /// Returns the current size of messages known to the client.
@@ -304,11 +765,40 @@ impl WClient {
         self.current_messages_size
     }
 
+    /// Returns the offset in the message history where the final message of the last fetch
+    /// starts, counted in the bytes sent by the server.
+    ///
+    /// It is the messages size to resume from to fetch that message again, which
+    /// [`HistoryCache`](crate::cache::HistoryCache) uses to detect a history that was reset.
+    pub fn last_message_offset(&self) -> usize {
+        self.last_message_offset
+    }
+
     /// Returns the current state of TLS usage.
     pub fn tls(&self) -> bool {
         self.use_tls
     }
 
+    /// Returns the version of the protocol.
+    pub fn version(&self) -> ProtocolVersion {
+        self.version
+    }
+
+    /// Returns the timeout for connecting and for every read or write.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns the encoding of the messages on the server.
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Returns what is done with messages that contain line breaks or other control characters.
+    pub fn input_policy(&self) -> InputPolicy {
+        self.input_policy
+    }
+
     /// Returns a reference to the server address.
     pub fn address(&self) -> &str {
         &self.address
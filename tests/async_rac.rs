@@ -0,0 +1,5 @@
+//! Runs the async RAC client on `tokio` against an in-process server.
+
+mod common;
+
+common::client_suite!(async, rac, rac_rs::async_rac::RacClient);
@@ -0,0 +1,5 @@
+//! Runs the async WRAC client on `tokio` against an in-process server.
+
+mod common;
+
+common::client_suite!(async, wrac, rac_rs::async_wrac::WClient);
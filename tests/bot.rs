@@ -0,0 +1,187 @@
+//! Runs a bot with a scripted client.
+
+use rac_rs::bot::{Bot, BotClient, RateLimit, StopHandle};
+use rac_rs::shared::ClientError;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A client that returns the given batches of lines on the first polls and stops the bot
+/// once they run out.
+struct Scripted {
+    polls: VecDeque<Vec<String>>,
+    stop: StopHandle,
+    replies: Vec<(Instant, String)>,
+}
+
+impl Scripted {
+    fn new(bot: &Bot, polls: &[&[&str]]) -> Self {
+        Self {
+            polls: polls
+                .iter()
+                .map(|lines| lines.iter().map(|line| line.to_string()).collect())
+                .collect(),
+            stop: bot.stop_handle(),
+            replies: Vec::new(),
+        }
+    }
+
+    /// Returns the replies without the times they were sent at.
+    fn replies(&self) -> Vec<&str> {
+        self.replies
+            .iter()
+            .map(|(_, reply)| reply.as_str())
+            .collect()
+    }
+
+    fn next_poll(&mut self) -> Vec<String> {
+        self.polls.pop_front().unwrap_or_else(|| {
+            self.stop.stop();
+            Vec::new()
+        })
+    }
+}
+
+impl BotClient for Scripted {
+    fn username(&self) -> &str {
+        "bot"
+    }
+
+    fn start(&mut self) -> Result<(), ClientError> {
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        Ok(self.next_poll())
+    }
+
+    fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.replies.push((Instant::now(), message.to_string()));
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+impl rac_rs::bot::AsyncBotClient for Scripted {
+    fn username(&self) -> &str {
+        "bot"
+    }
+
+    async fn start(&mut self) -> Result<(), ClientError> {
+        Ok(())
+    }
+
+    async fn poll(&mut self) -> Result<Vec<String>, ClientError> {
+        Ok(self.next_poll())
+    }
+
+    async fn reply(&mut self, message: &str) -> Result<(), ClientError> {
+        self.replies.push((Instant::now(), message.to_string()));
+        Ok(())
+    }
+}
+
+/// Creates a bot that doesn't wait between polls.
+fn bot() -> Bot {
+    let mut bot = Bot::new("!");
+    bot.update_poll_interval(Duration::ZERO);
+    bot
+}
+
+#[test]
+fn sends_placeholders_in_replies_as_they_are() {
+    let mut bot = bot();
+    bot.add_command("echo", |ctx| Some(ctx.args.to_string()));
+    let mut client = Scripted::new(&bot, &[&["<alice> !echo {username} and {text}"]]);
+
+    bot.run(&mut client).unwrap();
+    assert_eq!(client.replies(), ["<bot> {username} and {text}"]);
+}
+
+#[test]
+fn dispatches_commands_before_triggers() {
+    let mut bot = bot();
+    bot.add_command("hello", |_| Some("command".to_string()));
+    bot.add_command("quiet", |_| None);
+    bot.add_trigger("hello", |_| None).unwrap();
+    bot.add_trigger("hello|quiet", |ctx| {
+        Some(format!("trigger {}", &ctx.captures.as_ref()?[0]))
+    })
+    .unwrap();
+
+    assert_eq!(
+        bot.dispatch("<alice> !hello", "bot").as_deref(),
+        Some("command")
+    );
+    // A command that doesn't reply leaves the message to no one.
+    assert_eq!(bot.dispatch("<alice> !quiet", "bot"), None);
+    // Unknown commands and other messages go to the triggers in order, and a trigger
+    // that doesn't reply passes the message on.
+    assert_eq!(
+        bot.dispatch("<alice> !unknown hello", "bot").as_deref(),
+        Some("trigger hello")
+    );
+    assert_eq!(
+        bot.dispatch("<alice> say hello", "bot").as_deref(),
+        Some("trigger hello")
+    );
+    assert_eq!(bot.dispatch("<alice> goodbye", "bot"), None);
+}
+
+#[test]
+fn waits_for_the_oldest_reply_to_leave_the_window() {
+    let per = Duration::from_millis(200);
+    let mut bot = bot();
+    bot.update_rate_limit(RateLimit {
+        max_replies: 2,
+        per,
+    });
+    bot.add_command("ping", |_| Some("pong".to_string()));
+    let pings: &[&str] = &["<alice> !ping"; 4];
+    let mut client = Scripted::new(&bot, &[pings]);
+
+    bot.run(&mut client).unwrap();
+    let times: Vec<_> = client.replies.iter().map(|(time, _)| *time).collect();
+    assert_eq!(times.len(), 4);
+    assert!(times[1] - times[0] < per);
+    assert!(times[2] - times[0] >= per);
+    assert!(times[3] - times[1] >= per);
+    assert!(times[3] - times[0] < 2 * per);
+}
+
+#[test]
+fn stops_before_the_next_reply() {
+    let mut bot = bot();
+    let stop = bot.stop_handle();
+    bot.add_command("stop", move |_| {
+        stop.stop();
+        Some("stopping".to_string())
+    });
+    let mut client = Scripted::new(&bot, &[&["<alice> !stop"], &["<alice> !stop"]]);
+
+    bot.run(&mut client).unwrap();
+    assert!(client.replies.is_empty());
+    assert_eq!(client.polls.len(), 1);
+}
+
+#[cfg(all(
+    feature = "tokio",
+    any(feature = "async_client", feature = "async_wrac")
+))]
+#[tokio::test]
+async fn runs_with_an_async_client() {
+    let mut bot = bot();
+    bot.update_reply_format("[{username}] {text}");
+    bot.add_command("ping", |_| Some("pong".to_string()));
+    bot.add_trigger(r"^hi (\w+)$", |ctx| {
+        Some(format!("hi {}", &ctx.captures.as_ref()?[1]))
+    })
+    .unwrap();
+    let mut client = Scripted::new(
+        &bot,
+        &[&["<alice> !ping", "<bot> !ping"], &[], &["<bob> hi there"]],
+    );
+
+    bot.run_async(&mut client).await.unwrap();
+    assert_eq!(client.replies(), ["[bot] pong", "[bot] hi there"]);
+    assert!(client.polls.is_empty());
+}
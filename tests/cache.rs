@@ -0,0 +1,85 @@
+//! Resumes the history cache against an in-process server.
+
+mod common;
+
+use common::Server;
+use rac_rs::cache::HistoryCache;
+use rac_rs::rac::RacClient;
+use rac_rs::shared::ClientError;
+use std::path::{Path, PathBuf};
+
+/// Returns an empty cache directory for a test.
+fn cache_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rac-rs-cache-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Fetches the whole history of the server into the cache in `dir`.
+fn fill_cache(server: &Server, dir: &Path) {
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    let mut cache = HistoryCache::open(dir, &server.address).unwrap();
+    let lines = client.fetch_all_messages().unwrap();
+    cache
+        .replace(
+            &lines,
+            client.current_messages_size(),
+            client.last_message_offset(),
+        )
+        .unwrap();
+}
+
+/// Opens the cache in `dir` again and fetches the new messages from where it stopped.
+fn resume(server: &Server, dir: &Path) -> Result<Vec<String>, ClientError> {
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    let cache = HistoryCache::open(dir, &server.address).unwrap();
+    client.update_current_messages_size(cache.resume_size());
+    let lines = client.fetch_new_messages()?;
+    let new = cache.check_resume(&lines)?;
+    Ok(new.iter().map(|line| line.to_string()).collect())
+}
+
+#[test]
+fn resumes_after_the_final_cached_line() {
+    let dir = cache_dir("resume");
+    let server = Server::rac("один\nдва\n".as_bytes());
+    fill_cache(&server, &dir);
+
+    server.state().history.extend_from_slice("три\n".as_bytes());
+    assert_eq!(resume(&server, &dir).unwrap(), ["три"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn detects_a_reset_history_that_grew_past_the_cached_size() {
+    let dir = cache_dir("reset");
+    let server = Server::rac(b"one\ntwo\n");
+    fill_cache(&server, &dir);
+
+    server.state().history = b"three\nfour\nfive\n".to_vec();
+    let error = resume(&server, &dir).unwrap_err();
+    assert!(matches!(error, ClientError::HistoryReset));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn resumes_a_history_with_crlf_line_breaks() {
+    let dir = cache_dir("crlf");
+    let server = Server::rac(b"one\r\ntwo\r\n");
+    fill_cache(&server, &dir);
+
+    server.state().history.extend_from_slice(b"three\r\n");
+    assert_eq!(resume(&server, &dir).unwrap(), ["three"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn resumes_a_history_with_empty_lines_and_null_bytes() {
+    let dir = cache_dir("empty-lines");
+    let server = Server::rac(b"one\n\ntwo\0\0\n\n\n");
+    fill_cache(&server, &dir);
+
+    server.state().history.extend_from_slice(b"\nthree\n");
+    assert_eq!(resume(&server, &dir).unwrap(), ["three"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,28 @@
+//! Runs the synchronous RAC client through the fault injector.
+
+mod common;
+
+use common::Server;
+use rac_rs::chaos::{Chaos, ChaosConfig};
+use rac_rs::rac::RacClient;
+use std::sync::Arc;
+
+#[test]
+fn null_padding_does_not_shift_offsets() {
+    let server = Server::rac(b"one\ntwo\n");
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    client.update_chaos(Some(Arc::new(Chaos::new(ChaosConfig {
+        seed: 3,
+        fragment_rate: 1.0,
+        null_padding_rate: 1.0,
+        ..Default::default()
+    }))));
+
+    assert_eq!(client.fetch_all_messages().unwrap(), ["one", "two"]);
+    assert_eq!(client.current_messages_size(), 8);
+    for i in 0..10 {
+        server.state().history.extend(format!("msg {i}\n").bytes());
+        assert_eq!(client.fetch_new_messages().unwrap(), [format!("msg {i}")]);
+        assert_eq!(client.current_messages_size(), server.state().history.len());
+    }
+}
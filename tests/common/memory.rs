@@ -0,0 +1,184 @@
+//! An in-memory connection, usable by the synchronous and the async clients.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::Waker;
+use std::time::Duration;
+
+/// Bytes sent in one direction.
+#[derive(Debug, Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// One direction of a connection.
+#[derive(Debug, Default)]
+struct Channel {
+    buffer: Mutex<Buffer>,
+    ready: Condvar,
+}
+
+impl Channel {
+    fn push(&self, bytes: &[u8]) -> std::io::Result<usize> {
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.closed {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        buffer.data.extend(bytes);
+        self.wake(buffer);
+        Ok(bytes.len())
+    }
+
+    fn close(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.closed = true;
+        self.wake(buffer);
+    }
+
+    fn wake(&self, mut buffer: std::sync::MutexGuard<'_, Buffer>) {
+        if let Some(waker) = buffer.waker.take() {
+            waker.wake();
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// One end of an in-memory connection.
+///
+/// Reads return at most `max_read` bytes, so responses reach the client in pieces
+/// of that size however they were written.
+#[derive(Debug)]
+pub struct Pipe {
+    incoming: Arc<Channel>,
+    outgoing: Arc<Channel>,
+    max_read: usize,
+    timeout: Mutex<Option<Duration>>,
+}
+
+impl Pipe {
+    /// Creates both ends of a connection, with reads of the first one limited to `max_read`.
+    pub fn pair(max_read: usize) -> (Self, Self) {
+        let (a, b) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+        let client = Self {
+            incoming: a.clone(),
+            outgoing: b.clone(),
+            max_read,
+            timeout: Mutex::new(None),
+        };
+        let server = Self {
+            incoming: b,
+            outgoing: a,
+            max_read: usize::MAX,
+            timeout: Mutex::new(None),
+        };
+        (client, server)
+    }
+
+    /// Moves the available bytes into `buf`, returning `None` if there are none yet.
+    fn drain_into(&self, buffer: &mut Buffer, buf: &mut [u8]) -> Option<usize> {
+        if buffer.data.is_empty() && !buffer.closed {
+            return None;
+        }
+        let n = buf.len().min(self.max_read).min(buffer.data.len());
+        for (slot, byte) in buf.iter_mut().zip(buffer.data.drain(..n)) {
+            *slot = byte;
+        }
+        Some(n)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        self.incoming.close();
+        self.outgoing.close();
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let timeout = *self.timeout.lock().unwrap();
+        let mut buffer = self.incoming.buffer.lock().unwrap();
+        loop {
+            if let Some(n) = self.drain_into(&mut buffer, buf) {
+                return Ok(n);
+            }
+            buffer = match timeout {
+                None => self.incoming.ready.wait(buffer).unwrap(),
+                Some(timeout) => {
+                    let (buffer, result) =
+                        self.incoming.ready.wait_timeout(buffer, timeout).unwrap();
+                    if result.timed_out() && buffer.data.is_empty() && !buffer.closed {
+                        return Err(ErrorKind::WouldBlock.into());
+                    }
+                    buffer
+                }
+            };
+        }
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.outgoing.push(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "client", feature = "wrac"))]
+impl rac_rs::transport::Transport for Pipe {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        *self.timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+mod async_io {
+    use super::Pipe;
+    use futures_io::{AsyncRead, AsyncWrite};
+    use std::io::Write;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    impl AsyncRead for Pipe {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<std::io::Result<usize>> {
+            let mut buffer = self.incoming.buffer.lock().unwrap();
+            match self.drain_into(&mut buffer, buf) {
+                Some(n) => Poll::Ready(Ok(n)),
+                None => {
+                    buffer.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    impl AsyncWrite for Pipe {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Poll::Ready(self.get_mut().write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            self.outgoing.close();
+            Poll::Ready(Ok(()))
+        }
+    }
+}
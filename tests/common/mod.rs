@@ -0,0 +1,288 @@
+//! In-process RAC and WRAC servers for the integration tests.
+//!
+//! Every test file compiles this module on its own and uses a part of it.
+#![allow(dead_code)]
+
+mod memory;
+mod suite;
+
+pub use memory::Pipe;
+use rac_rs::shared::Credentials;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+#[allow(unused_imports)]
+pub(crate) use suite::client_suite;
+
+/// Credentials of a user that the tests register on the server.
+pub fn alice() -> Credentials {
+    Credentials {
+        username: "alice".to_string(),
+        password: Some("secret".into()),
+    }
+}
+
+/// The history and the users of a test server.
+#[derive(Debug, Default)]
+pub struct State {
+    /// The message history, as it is stored on the server.
+    pub history: Vec<u8>,
+    /// Registered users and their passwords.
+    pub users: HashMap<Vec<u8>, Vec<u8>>,
+    /// How replies are split, as the size of the pieces and the pause between them.
+    pub chunks: Option<(usize, Duration)>,
+    /// How many more authenticated sends are accepted, after which they fail
+    /// with an incorrect password.
+    pub sends_left: Option<usize>,
+    /// Whether the server works like a RACv1 server, which doesn't know authenticated sends
+    /// and registrations.
+    pub legacy: bool,
+    /// What the server replies to requests it doesn't know, nothing if `None`.
+    pub unknown_reply: Option<u8>,
+}
+
+impl State {
+    /// Handles a request that sends a message or registers a user, returning the status.
+    fn apply(&mut self, request: &[u8]) -> Option<u8> {
+        match request.split_first() {
+            Some((2 | 3, _)) if self.legacy => self.unknown_reply,
+            Some((1, message)) => {
+                self.push(message);
+                None
+            }
+            Some((2, rest)) => {
+                let mut parts = rest.splitn(3, |&b| b == b'\n');
+                let (user, password) = (parts.next().unwrap_or_default(), parts.next());
+                let message = parts.next().unwrap_or_default();
+                match self.users.get(user) {
+                    None => Some(0x01),
+                    Some(known) if Some(known.as_slice()) != password => Some(0x02),
+                    Some(_) if self.sends_left == Some(0) => Some(0x02),
+                    Some(_) => {
+                        self.sends_left = self.sends_left.map(|left| left - 1);
+                        self.push(message);
+                        None
+                    }
+                }
+            }
+            Some((3, rest)) => {
+                let mut parts = rest.splitn(2, |&b| b == b'\n');
+                let user = parts.next().unwrap_or_default().to_vec();
+                if self.users.contains_key(&user) {
+                    return Some(0x01);
+                }
+                self.users
+                    .insert(user, parts.next().unwrap_or_default().to_vec());
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, message: &[u8]) {
+        self.history.extend_from_slice(message);
+        self.history.push(b'\n');
+    }
+
+    /// Returns the reply to a request for messages, `0x01` or `0x02{offset}`.
+    fn messages(&self, request: &[u8]) -> Vec<u8> {
+        match request.split_first() {
+            Some((2, offset)) => {
+                let offset: usize = String::from_utf8_lossy(offset).parse().unwrap();
+                self.history[offset.min(self.history.len())..].to_vec()
+            }
+            _ => self.history.clone(),
+        }
+    }
+}
+
+/// Handles a connection to a server.
+type Handler<S> = fn(S, Arc<Mutex<State>>);
+
+/// A server shared between the test and the threads that handle connections.
+#[derive(Debug, Clone)]
+pub struct Server {
+    /// The address the server listens on.
+    pub address: String,
+    state: Arc<Mutex<State>>,
+    handle_pipe: Handler<Pipe>,
+}
+
+impl Server {
+    /// Locks the state, to inspect or change it.
+    pub fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// Splits every reply into pieces of `size` bytes, sent `pause` apart.
+    pub fn split_replies(&self, size: usize, pause: Duration) {
+        self.state().chunks = Some((size, pause));
+    }
+
+    /// Returns the message history as text.
+    pub fn history(&self) -> String {
+        String::from_utf8_lossy(&self.state().history).into_owned()
+    }
+
+    /// Registers a user.
+    pub fn add_user(&self, username: &str, password: &str) {
+        self.state().users.insert(username.into(), password.into());
+    }
+
+    /// Returns a connector that connects clients to this server in memory,
+    /// delivering responses in pieces of up to `max_read` bytes.
+    pub fn in_memory(&self, max_read: usize) -> Arc<InMemory> {
+        Arc::new(InMemory {
+            server: self.clone(),
+            max_read,
+        })
+    }
+
+    fn listen(history: &[u8], handle: Handler<TcpStream>, handle_pipe: Handler<Pipe>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let state = Arc::new(Mutex::new(State {
+            history: history.to_vec(),
+            ..Default::default()
+        }));
+        let server = Self {
+            address: listener.local_addr().unwrap().to_string(),
+            state: state.clone(),
+            handle_pipe,
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (stream, state) = (stream.unwrap(), state.clone());
+                stream.set_nodelay(true).unwrap();
+                thread::spawn(move || handle(stream, state));
+            }
+        });
+        server
+    }
+
+    /// Starts a RAC server with the given history.
+    pub fn rac(history: &[u8]) -> Self {
+        Self::listen(history, handle_rac, handle_rac)
+    }
+
+    /// Starts a WRAC server with the given history.
+    #[cfg(any(feature = "wrac", feature = "async_wrac"))]
+    pub fn wrac(history: &[u8]) -> Self {
+        Self::listen(history, handle_wrac, handle_wrac)
+    }
+}
+
+/// Connects clients to a [`Server`] without a network.
+#[derive(Debug)]
+pub struct InMemory {
+    server: Server,
+    max_read: usize,
+}
+
+impl InMemory {
+    fn open(&self) -> Pipe {
+        let (client, server) = Pipe::pair(self.max_read);
+        let (handle, state) = (self.server.handle_pipe, self.server.state.clone());
+        thread::spawn(move || handle(server, state));
+        client
+    }
+}
+
+#[cfg(any(feature = "client", feature = "wrac"))]
+impl rac_rs::transport::Connector for InMemory {
+    fn connect(
+        &self,
+        _address: &str,
+        _timeout: Option<Duration>,
+    ) -> std::io::Result<Box<dyn rac_rs::transport::Transport>> {
+        Ok(Box::new(self.open()))
+    }
+}
+
+#[cfg(any(feature = "async_client", feature = "async_wrac"))]
+impl rac_rs::transport::AsyncConnector for InMemory {
+    fn connect<'a>(&'a self, _address: &'a str) -> rac_rs::transport::ConnectFuture<'a> {
+        let pipe = self.open();
+        Box::pin(async move { Ok(Box::new(pipe) as Box<dyn rac_rs::transport::AsyncTransport>) })
+    }
+}
+
+/// Writes `reply`, split as configured.
+fn write_reply(stream: &mut impl Write, state: &Mutex<State>, reply: &[u8]) {
+    let chunks = state.lock().unwrap().chunks;
+    let Some((size, pause)) = chunks else {
+        let _ = stream.write_all(reply);
+        return;
+    };
+    for (i, chunk) in reply.chunks(size).enumerate() {
+        if i > 0 {
+            thread::sleep(pause);
+        }
+        if stream.write_all(chunk).is_err() {
+            return;
+        }
+    }
+}
+
+/// Handles a RAC connection. Every request is expected to arrive in a single read.
+fn handle_rac<S: Read + Write>(mut stream: S, state: Arc<Mutex<State>>) {
+    let mut buf = vec![0; 65536];
+    let n = stream.read(&mut buf).unwrap_or(0);
+    match buf[..n].split_first() {
+        None => {}
+        Some((0, _)) => {
+            let size = state.lock().unwrap().history.len().to_string();
+            write_reply(&mut stream, &state, size.as_bytes());
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n > 0 {
+                let reply = state.lock().unwrap().messages(&buf[..n]);
+                write_reply(&mut stream, &state, &reply);
+            }
+        }
+        Some(_) => {
+            let status = state.lock().unwrap().apply(&buf[..n]);
+            if let Some(status) = status {
+                write_reply(&mut stream, &state, &[status]);
+            }
+        }
+    }
+}
+
+/// Handles a WRAC connection, replying to every request with a binary message.
+#[cfg(any(feature = "wrac", feature = "async_wrac"))]
+fn handle_wrac<S: Read + Write>(stream: S, state: Arc<Mutex<State>>) {
+    use tungstenite::Message;
+
+    let Ok(mut ws) = tungstenite::accept(stream) else {
+        return;
+    };
+    while let Ok(message) = ws.read() {
+        let request = match message {
+            Message::Binary(data) => data.to_vec(),
+            Message::Text(text) => text.as_bytes().to_vec(),
+            Message::Close(_) => return,
+            _ => continue,
+        };
+        let mut state = state.lock().unwrap();
+        let reply = match request.as_slice() {
+            [0] => Some(state.history.len().to_string().into_bytes()),
+            [0, rest @ ..] => Some(state.messages(rest)),
+            [1] => Some(state.history.clone()),
+            [1, ..] => state.apply(&request).map(|status| vec![status]),
+            _ => Some(
+                state
+                    .apply(&request)
+                    .map(|status| vec![status])
+                    .unwrap_or_default(),
+            ),
+        };
+        drop(state);
+        if let Some(reply) = reply
+            && ws.send(Message::Binary(reply.into())).is_err()
+        {
+            return;
+        }
+    }
+}
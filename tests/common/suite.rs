@@ -0,0 +1,260 @@
+//! The tests that the synchronous and async clients share, written once for both.
+//!
+//! Only the client test files expand the macro, so it is unused in the others.
+#![allow(unused_macros)]
+
+/// Generates the tests of a client, with the mode of the client (`sync` or `async`),
+/// the protocol of the server (`rac` or `wrac`) and the type of the client.
+///
+/// The body of every test is the same for both modes, so they can't drift apart.
+/// Calls that are awaited in async clients are wrapped in `client_suite!(@call ...)`.
+/// Async tests run on `tokio`.
+macro_rules! client_suite {
+    (@test sync $name:ident $body:block) => {
+        #[test]
+        fn $name() $body
+    };
+    (@test async $name:ident $body:block) => {
+        #[tokio::test]
+        async fn $name() $body
+    };
+    (@call sync, $call:expr) => {
+        $call
+    };
+    (@call async, $call:expr) => {
+        $call.await
+    };
+    // The WebSocket handshake takes reads of a single byte for an attack, so WRAC reads more.
+    (@smallest_read rac) => {
+        1
+    };
+    (@smallest_read wrac) => {
+        3
+    };
+    (@prepare rac, $mode:ident, $client:ident) => {};
+    (@prepare wrac, $mode:ident, $client:ident) => {
+        crate::common::client_suite!(@call $mode, $client.prepare()).unwrap()
+    };
+
+    // Tests of WRAC clients only.
+    (@extra wrac, $mode:ident, $client:ty) => {
+        use rac_rs::shared::ConnectionState;
+
+        crate::common::client_suite!(@test $mode rejects_invalid_addresses_without_panicking {
+            let server = Server::wrac(b"");
+            for address in ["not a valid address", "ws://", "", "host:port", "[::1", "ws://host:99999/"] {
+                let mut client = <$client>::new(address, alice(), false);
+                let error = crate::common::client_suite!(@call $mode, client.prepare()).unwrap_err();
+                assert!(matches!(error, ClientError::InvalidAddress { .. }), "{address}: {error:?}");
+                assert_eq!(client.state(), ConnectionState::Disconnected);
+                let error = crate::common::client_suite!(@call $mode, client.fetch_all_messages()).unwrap_err();
+                assert!(matches!(error, ClientError::NoConnectionWRAC), "{address}: {error:?}");
+                let error = crate::common::client_suite!(@call $mode, client.register_user()).unwrap_err();
+                assert!(matches!(error, ClientError::InvalidAddress { .. }), "{address}: {error:?}");
+
+                // A connection that is open stays open.
+                client.update_address(server.address.clone());
+                crate::common::client_suite!(@prepare wrac, $mode, client);
+                client.update_address(address.to_string());
+                let error = crate::common::client_suite!(@call $mode, client.prepare()).unwrap_err();
+                assert!(matches!(error, ClientError::InvalidAddress { .. }), "{address}: {error:?}");
+                assert_eq!(client.state(), ConnectionState::Connected);
+                crate::common::client_suite!(@call $mode, client.fetch_messages_size()).unwrap();
+            }
+        });
+    };
+    // Tests of RAC clients only.
+    (@extra rac, $mode:ident, $client:ty) => {
+        use rac_rs::framing::SIZE_SETTLE_TIME;
+        use std::time::Duration;
+
+        /// A history of 50 messages, 350 bytes long.
+        fn history() -> Vec<u8> {
+            (0..50)
+                .flat_map(|i| format!("msg {i:02}\n").into_bytes())
+                .collect()
+        }
+
+        crate::common::client_suite!(@test $mode reads_replies_split_into_bytes {
+            let server = Server::rac(&history());
+            server.split_replies(1, Duration::from_millis(2));
+            let mut client = <$client>::new(&server.address, Default::default(), false);
+
+            let messages = crate::common::client_suite!(@call $mode, client.fetch_all_messages()).unwrap();
+            assert_eq!(client.current_messages_size(), 350);
+            assert_eq!(messages.len(), 50);
+            assert_eq!(messages[49], "msg 49");
+        });
+
+        crate::common::client_suite!(@test $mode waits_for_slow_digits_up_to_the_settle_time {
+            let server = Server::rac(&history());
+            server.split_replies(1, Duration::from_millis(60));
+            let mut client = <$client>::new(&server.address, Default::default(), false);
+            client.update_size_settle_time(Duration::from_millis(500));
+
+            crate::common::client_suite!(@call $mode, client.fetch_messages_size()).unwrap();
+            assert_eq!(client.current_messages_size(), 350);
+        });
+
+        crate::common::client_suite!(@test $mode cuts_a_size_split_by_a_longer_pause {
+            // A known limit of the settle time: the size is taken from the digits before the
+            // pause, which ends it as far as the client can tell.
+            let server = Server::rac(&history());
+            server.split_replies(1, Duration::from_millis(200));
+            let mut client = <$client>::new(&server.address, Default::default(), false);
+            assert_eq!(client.size_settle_time(), SIZE_SETTLE_TIME);
+
+            crate::common::client_suite!(@call $mode, client.fetch_messages_size()).unwrap();
+            assert_eq!(client.current_messages_size(), 3);
+        });
+
+        crate::common::client_suite!(@test $mode needs_a_host_name_for_tls {
+            let server = Server::rac(b"");
+            let mut client = <$client>::new(":42666", Default::default(), true);
+            client.update_connector(Some(server.in_memory(64)));
+
+            let error = crate::common::client_suite!(@call $mode, client.fetch_messages_size()).unwrap_err();
+            assert!(matches!(error, ClientError::TlsInitializationError(_)));
+        });
+    };
+
+    ($mode:ident, $protocol:ident, $client:ty) => {
+        use common::{Server, alice};
+        use rac_rs::input::InputPolicy;
+        use rac_rs::shared::{ClientError, Credentials, Operation, ProtocolVersion};
+
+        /// Two messages in Cyrillic and with an emoji, which take 2 and 4 bytes per character.
+        const HISTORY: &str = "Привет, мир\n🦀 краб\n";
+
+        crate::common::client_suite!(@extra $protocol, $mode, $client);
+
+        crate::common::client_suite!(@test $mode decodes_messages_split_mid_codepoint {
+            let server = Server::$protocol(HISTORY.as_bytes());
+            let mut client = <$client>::new("in-memory", Default::default(), false);
+            let max_read = crate::common::client_suite!(@smallest_read $protocol);
+            client.update_connector(Some(server.in_memory(max_read)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+
+            assert_eq!(
+                crate::common::client_suite!(@call $mode, client.fetch_all_messages()).unwrap(),
+                ["Привет, мир", "🦀 краб"]
+            );
+            assert_eq!(client.current_messages_size(), HISTORY.len());
+        });
+
+        crate::common::client_suite!(@test $mode leaves_a_message_cut_mid_line_for_the_next_fetch {
+            // The history ends in the middle of the emoji.
+            let (complete, rest) = HISTORY.as_bytes().split_at(HISTORY.find('🦀').unwrap() + 2);
+            let server = Server::$protocol(complete);
+            let mut client = <$client>::new("in-memory", Default::default(), false);
+            client.update_connector(Some(server.in_memory(3)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+
+            let messages = crate::common::client_suite!(@call $mode, client.fetch_all_messages()).unwrap();
+            assert_eq!(messages, ["Привет, мир"]);
+            assert_eq!(client.current_messages_size(), "Привет, мир\n".len());
+
+            server.state().history.extend_from_slice(rest);
+            let messages = crate::common::client_suite!(@call $mode, client.fetch_new_messages()).unwrap();
+            assert_eq!(messages, ["🦀 краб"]);
+            assert_eq!(client.current_messages_size(), HISTORY.len());
+        });
+
+        crate::common::client_suite!(@test $mode rejects_escapes_and_splits_control_characters {
+            let server = Server::$protocol(b"");
+            server.add_user("alice", "secret");
+            let mut client = <$client>::new("in-memory", alice(), false);
+            client.update_connector(Some(server.in_memory(64)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+
+            let error = crate::common::client_suite!(@call $mode, client.send_message("one\ntwo")).unwrap_err();
+            assert!(matches!(error, ClientError::InvalidInput(_)));
+            client.update_input_policy(InputPolicy::Escape);
+            crate::common::client_suite!(@call $mode, client.send_message("one\ntwo\u{1b}")).unwrap();
+            client.update_input_policy(InputPolicy::Split);
+            crate::common::client_suite!(@call $mode, client.send_message("three\r\n\nfour")).unwrap();
+
+            assert_eq!(server.history(), "one\\ntwo\\u{1b}\nthree\nfour\n");
+        });
+
+        crate::common::client_suite!(@test $mode rejects_usernames_that_would_add_lines {
+            let server = Server::$protocol(b"");
+            let credentials = Credentials {
+                username: "bob\n<admin>".to_string(),
+                password: None,
+            };
+            let mut client = <$client>::new("in-memory", credentials, false);
+            client.update_connector(Some(server.in_memory(64)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+            client.update_version(ProtocolVersion::V1);
+            client.update_input_policy(InputPolicy::Split);
+
+            let error =
+                crate::common::client_suite!(@call $mode, client.send_message("<{username}> hi")).unwrap_err();
+            assert!(matches!(error, ClientError::InvalidInput(_)));
+            assert_eq!(server.history(), "");
+        });
+
+        crate::common::client_suite!(@test $mode reports_lines_sent_before_an_error {
+            let server = Server::$protocol(b"");
+            server.add_user("alice", "secret");
+            server.state().sends_left = Some(2);
+            let mut client = <$client>::new("in-memory", alice(), false);
+            client.update_connector(Some(server.in_memory(64)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+            client.update_input_policy(InputPolicy::Split);
+
+            let error =
+                crate::common::client_suite!(@call $mode, client.send_message("one\ntwo\nthree")).unwrap_err();
+            let ClientError::PartialSend {
+                sent,
+                total,
+                source,
+            } = error
+            else {
+                panic!("expected a partial send, got {error:?}");
+            };
+            assert_eq!((sent, total), (2, 3));
+            assert!(matches!(*source, ClientError::IncorrectPassword));
+            assert!(
+                !ClientError::PartialSend {
+                    sent,
+                    total,
+                    source
+                }
+                .is_retryable()
+            );
+            assert_eq!(server.history(), "one\ntwo\n");
+        });
+
+        crate::common::client_suite!(@test $mode reports_operations_a_racv1_server_does_not_know {
+            // The server answers with the size of its empty history, as if they were fetches.
+            let server = Server::$protocol(b"");
+            server.state().legacy = true;
+            server.state().unknown_reply = Some(b'0');
+            let mut client = <$client>::new("in-memory", alice(), false);
+            client.update_connector(Some(server.in_memory(64)));
+            crate::common::client_suite!(@prepare $protocol, $mode, client);
+
+            let error = crate::common::client_suite!(@call $mode, client.register_user()).unwrap_err();
+            assert!(matches!(
+                error,
+                ClientError::UnsupportedOperation {
+                    operation: Operation::RegisterUser,
+                    version: ProtocolVersion::V1,
+                }
+            ));
+            let error = crate::common::client_suite!(@call $mode, client.send_message("hi")).unwrap_err();
+            assert!(matches!(
+                error,
+                ClientError::UnsupportedOperation {
+                    operation: Operation::SendMessage,
+                    version: ProtocolVersion::V1,
+                }
+            ));
+            assert_eq!(server.history(), "");
+        });
+    };
+}
+
+pub(crate) use client_suite;
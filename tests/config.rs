@@ -0,0 +1,65 @@
+//! Saves and loads client settings with serde.
+
+use rac_rs::shared::{ClientConfig, Credentials};
+use std::time::Duration;
+
+/// Settings with a password and a timeout.
+fn config() -> ClientConfig {
+    ClientConfig {
+        address: "127.0.0.1:42666".to_string(),
+        credentials: Credentials {
+            username: "alice".to_string(),
+            password: Some("hunter2".into()),
+        },
+        timeout: Some(Duration::from_millis(2500)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn leaves_the_password_out() {
+    let credentials = serde_json::to_value(&config().credentials).unwrap();
+    assert_eq!(credentials, serde_json::json!({ "username": "alice" }));
+
+    let json = serde_json::to_string(&config()).unwrap();
+    assert!(!json.contains("hunter2"), "{json}");
+    assert!(!json.contains("password"), "{json}");
+
+    let loaded: ClientConfig = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.credentials.username, "alice");
+    assert!(loaded.credentials.password.is_none());
+}
+
+#[test]
+fn loads_a_password_that_was_written_by_hand() {
+    let credentials: Credentials =
+        serde_json::from_str(r#"{ "username": "alice", "password": "hunter2" }"#).unwrap();
+    assert_eq!(credentials.password.unwrap().expose(), "hunter2");
+}
+
+#[test]
+fn writes_the_timeout_in_seconds() {
+    let json = serde_json::to_value(config()).unwrap();
+    assert_eq!(json["timeout"], serde_json::json!(2.5));
+
+    let loaded: ClientConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(loaded.timeout, Some(Duration::from_millis(2500)));
+    assert_eq!(loaded.address, "127.0.0.1:42666");
+
+    let mut json = serde_json::to_value(ClientConfig::default()).unwrap();
+    assert!(json["timeout"].is_null());
+    json.as_object_mut().unwrap().remove("timeout");
+    let loaded: ClientConfig = serde_json::from_value(json).unwrap();
+    assert_eq!(loaded.timeout, None);
+}
+
+#[test]
+fn rejects_timeouts_that_are_not_positive() {
+    for timeout in ["0", "-1", "1e300"] {
+        let json = format!(
+            r#"{{ "address": "", "credentials": {{ "username": "" }}, "timeout": {timeout} }}"#
+        );
+        let error = serde_json::from_str::<ClientConfig>(&json).unwrap_err();
+        assert!(error.to_string().contains("positive"), "{timeout}: {error}");
+    }
+}
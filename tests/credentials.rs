@@ -0,0 +1,112 @@
+//! Resolves passwords from the environment, files and commands.
+
+use rac_rs::credentials::{CommandProvider, CredentialProvider, EnvProvider, FileProvider};
+use rac_rs::shared::ClientError;
+use std::path::PathBuf;
+
+/// Returns a path in the temporary directory that is unique to the test.
+fn temp_path(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rac-rs-credentials-{}-{test}", std::process::id()))
+}
+
+/// Checks that the provider fails with a message that mentions `problem`.
+fn assert_fails(provider: &dyn CredentialProvider, problem: &str) {
+    match provider.password() {
+        Err(ClientError::CredentialError(e)) => {
+            assert!(
+                e.to_string().contains(problem),
+                "{e:?} doesn't mention {problem:?}"
+            )
+        }
+        other => panic!("expected {problem:?}, got {other:?}"),
+    }
+}
+
+#[test]
+fn reads_the_password_from_the_environment() {
+    // SAFETY: no other test reads or writes these variables.
+    unsafe {
+        std::env::set_var("RAC_RS_TEST_PASSWORD", "secret");
+        std::env::set_var("RAC_RS_TEST_EMPTY_PASSWORD", "");
+    }
+
+    let provider = EnvProvider::new("RAC_RS_TEST_PASSWORD");
+    assert_eq!(provider.password().unwrap().expose(), "secret");
+    assert_fails(
+        &EnvProvider::new("RAC_RS_TEST_EMPTY_PASSWORD"),
+        "empty password",
+    );
+    assert_fails(
+        &EnvProvider::new("RAC_RS_TEST_UNSET_PASSWORD"),
+        "is not set",
+    );
+}
+
+#[test]
+fn reads_the_password_from_a_file_without_the_line_break() {
+    let path = temp_path("file");
+    std::fs::write(&path, "secret\r\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(0o600);
+        std::fs::set_permissions(&path, permissions).unwrap();
+    }
+
+    let password = FileProvider::new(&path).password();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(password.unwrap().expose(), "secret");
+    assert_fails(&FileProvider::new(&path), "failed to read");
+}
+
+#[cfg(unix)]
+#[test]
+fn rejects_a_file_that_others_can_read() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = temp_path("shared-file");
+    std::fs::write(&path, "secret\n").unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+    let provider = FileProvider::new(&path);
+    assert_fails(&provider, "accessible by other users");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn takes_the_first_line_of_the_command_output() {
+    let provider = CommandProvider::new("printf", ["secret\\nsecond line\\n"]);
+    assert_eq!(provider.password().unwrap().expose(), "secret");
+}
+
+#[cfg(unix)]
+#[test]
+fn reports_commands_that_fail_or_print_nothing() {
+    let provider = CommandProvider::new("sh", ["-c", "echo locked >&2; exit 3"]);
+    assert_fails(&provider, "exit status: 3: locked");
+    assert_fails(
+        &CommandProvider::new("true", std::iter::empty::<&str>()),
+        "empty password",
+    );
+    assert_fails(
+        &CommandProvider::new("rac-rs-missing-program", std::iter::empty::<&str>()),
+        "failed to run",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn runs_the_command_again_only_after_a_refresh() {
+    let path = temp_path("command-runs");
+    let script = format!("echo run >> {0}; wc -l < {0}", path.display());
+    let provider = CommandProvider::new("sh", ["-c", script.as_str()]);
+
+    let first = provider.password().unwrap();
+    assert_eq!(provider.password().unwrap().expose(), first.expose());
+    provider.refresh();
+    let second = provider.password().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(first.expose().trim(), "1");
+    assert_eq!(second.expose().trim(), "2");
+}
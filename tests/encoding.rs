@@ -0,0 +1,64 @@
+//! Fetches and sends messages in the encoding of the server.
+
+mod common;
+
+use common::{Server, alice};
+use rac_rs::encoding::TextEncoding;
+use rac_rs::rac::RacClient;
+use rac_rs::shared::ClientError;
+
+/// `<bob> Привет` in windows-1251.
+const CP1251_LINE: &[u8] = b"<bob> \xcf\xf0\xe8\xe2\xe5\xf2\n";
+
+/// Creates a client of `server` that uses `encoding`.
+fn client(server: &Server, encoding: TextEncoding) -> RacClient {
+    let mut client = RacClient::new(&server.address, alice(), false);
+    client.update_encoding(encoding);
+    client
+}
+
+#[test]
+fn round_trips_messages_in_a_legacy_encoding() {
+    let server = Server::rac(CP1251_LINE);
+    server.add_user("alice", "secret");
+    let mut client = client(&server, TextEncoding::for_label("cp1251").unwrap());
+
+    client.send_message("<{username}> Пока 🦀").unwrap();
+    assert_eq!(
+        server.state().history[CP1251_LINE.len()..],
+        b"<alice> \xcf\xee\xea\xe0 &#129408;\n"[..]
+    );
+    assert_eq!(
+        client.fetch_all_messages().unwrap(),
+        ["<bob> Привет", "<alice> Пока &#129408;"]
+    );
+    assert_eq!(client.current_messages_size(), server.state().history.len());
+}
+
+#[test]
+fn decodes_invalid_utf8_lossily_or_fails_strictly() {
+    let server = Server::rac(CP1251_LINE);
+
+    let mut lossy = client(&server, TextEncoding::Utf8Lossy);
+    assert_eq!(
+        lossy.fetch_all_messages().unwrap(),
+        [format!("<bob> {}", "\u{fffd}".repeat(6))]
+    );
+    assert_eq!(lossy.current_messages_size(), CP1251_LINE.len());
+
+    let mut strict = client(&server, TextEncoding::Utf8Strict);
+    let error = strict.fetch_all_messages().unwrap_err();
+    assert!(matches!(error, ClientError::ParseError { .. }), "{error:?}");
+    assert_eq!(strict.current_messages_size(), 0);
+}
+
+#[test]
+fn sends_utf8_unchanged() {
+    let server = Server::rac(b"");
+    server.add_user("alice", "secret");
+    let mut client = client(&server, TextEncoding::Utf8Strict);
+
+    client.send_message("<{username}> Привет 🦀").unwrap();
+    assert_eq!(server.history(), "<alice> Привет 🦀\n");
+    assert_eq!(client.fetch_all_messages().unwrap(), ["<alice> Привет 🦀"]);
+}
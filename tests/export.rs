@@ -0,0 +1,104 @@
+//! Imports exported history into in-process servers.
+
+mod common;
+
+use common::{Server, alice};
+use rac_rs::cache::HistoryCache;
+use rac_rs::encoding::TextEncoding;
+use rac_rs::export::{
+    ExportFormat, ExportRecord, import, seed_cache, send_records, send_records_async,
+};
+use rac_rs::rac::RacClient;
+use rac_rs::shared::ClientError;
+
+/// An archive with a line that the default input policy rejects.
+const ARCHIVE: &str = "<alice> one\n<alice> two\u{7}\n<alice> three\n";
+
+#[test]
+fn reports_records_that_fail_and_sends_the_rest() {
+    let server = Server::rac(b"");
+    server.add_user("alice", "secret");
+    let client = RacClient::new(&server.address, alice(), false);
+    let records = import(ExportFormat::PlainText, ARCHIVE.as_bytes()).unwrap();
+
+    let failed = send_records(&records, |line| client.send_custom_message(line));
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].index, 1);
+    assert!(matches!(failed[0].error, ClientError::InvalidInput(_)));
+    assert_eq!(server.history(), "<alice> one\n<alice> three\n");
+}
+
+#[tokio::test]
+async fn reports_records_that_fail_and_sends_the_rest_async() {
+    let server = Server::rac(b"");
+    server.add_user("alice", "secret");
+    let client = rac_rs::async_rac::RacClient::new(&server.address, alice(), false);
+    let records = import(ExportFormat::PlainText, ARCHIVE.as_bytes()).unwrap();
+
+    let client = &client;
+    let failed = send_records_async(&records, |line| async move {
+        client.send_custom_message(&line).await
+    })
+    .await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].index, 1);
+    assert_eq!(server.history(), "<alice> one\n<alice> three\n");
+}
+
+#[test]
+fn counts_offsets_in_the_encoding_of_the_server() {
+    let lines = ["<алиса> Привет", "<bob> hi"];
+    let records = ExportRecord::from_lines(&lines, 0, TextEncoding::Utf8Lossy);
+    assert_eq!(records[1].offset, lines[0].len() + 1);
+
+    #[cfg(feature = "encoding")]
+    {
+        let encoding = TextEncoding::for_label("windows-1251").unwrap();
+        let records = ExportRecord::from_lines(&lines, 10, encoding);
+        assert_eq!(records[1].offset, 10 + lines[0].chars().count() + 1);
+        assert_eq!(records[1].end_offset(encoding), records[1].offset + 9);
+    }
+}
+
+#[test]
+fn estimates_offsets_from_the_decoded_lines() {
+    let server = Server::rac(b"<alice> one\r\n<bob> two\r\n");
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    let lines = client.fetch_all_messages().unwrap();
+    let records = ExportRecord::from_lines(&lines, 0, TextEncoding::Utf8Lossy);
+
+    // The `\r` that ends every line on the server is not counted.
+    assert_eq!(records[1].offset, "<alice> one\n".len());
+    assert_eq!(client.last_message_offset(), "<alice> one\r\n".len());
+}
+
+#[test]
+fn seeds_a_cache_that_refetches_on_the_first_resume() {
+    let dir = std::env::temp_dir().join(format!("rac-rs-export-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let server = Server::rac(b"<alice> one\r\n<bob> two\r\n");
+    let records = import(
+        ExportFormat::PlainText,
+        "<alice> one\n<bob> two\n".as_bytes(),
+    )
+    .unwrap();
+    let size = server.state().history.len();
+
+    let mut cache = HistoryCache::open(&dir, &server.address).unwrap();
+    seed_cache(&mut cache, &records, size).unwrap();
+    assert_eq!(cache.lines(), ["<alice> one", "<bob> two"]);
+    assert_eq!(cache.resume_size(), size);
+
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    client.update_current_messages_size(cache.resume_size());
+    server
+        .state()
+        .history
+        .extend_from_slice(b"<carol> three\r\n");
+    let lines = client.fetch_new_messages().unwrap();
+    assert!(matches!(
+        cache.check_resume(&lines),
+        Err(ClientError::HistoryReset)
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,241 @@
+//! Runs the requests of many tasks at the same time through clones of the async handles
+//! and the halves of a split client.
+
+mod common;
+
+use common::{InMemory, Server, alice};
+use futures_io::{AsyncRead, AsyncWrite};
+use rac_rs::async_rac::{RacClient, RacHandle};
+use rac_rs::async_wrac::{WClient, WHandle};
+use rac_rs::shared::{ClientError, ConnectionState};
+use rac_rs::transport::{AsyncConnector, AsyncTransport, ConnectFuture};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// The number of tasks that send at the same time.
+const SENDERS: usize = 4;
+/// The number of messages every sending task sends.
+const MESSAGES: usize = 5;
+
+/// The message that `task` sends `i`-th, before `{username}` is replaced.
+fn message(task: usize, i: usize) -> String {
+    format!("<{{username}}> task {task} message {i}")
+}
+
+/// Sorts `messages`, checking that none of them appears twice.
+fn sorted_unique(mut messages: Vec<String>) -> Vec<String> {
+    messages.sort();
+    let count = messages.len();
+    messages.dedup();
+    assert_eq!(messages.len(), count, "a message was fetched twice");
+    messages
+}
+
+/// Sends from [`SENDERS`] tasks and fetches new messages from two more until the sends are done,
+/// returning the sent messages and everything fetched, including one final fetch.
+macro_rules! send_while_fetching {
+    ($handle:expr) => {{
+        let handle = $handle;
+        let done = Arc::new(AtomicBool::new(false));
+        let fetchers: Vec<_> = (0..2)
+            .map(|_| {
+                let (handle, done) = (handle.clone(), done.clone());
+                tokio::spawn(async move {
+                    let mut fetched = Vec::new();
+                    while !done.load(Ordering::Relaxed) {
+                        fetched.extend(handle.fetch_new_messages().await.unwrap());
+                        tokio::task::yield_now().await;
+                    }
+                    fetched
+                })
+            })
+            .collect();
+        let senders: Vec<_> = (0..SENDERS)
+            .map(|task| {
+                let handle = handle.clone();
+                tokio::spawn(async move {
+                    let mut results = Vec::new();
+                    for i in 0..MESSAGES {
+                        let text = message(task, i).replace("{username}", handle.username());
+                        results.push((text, handle.send_message(&message(task, i)).await));
+                    }
+                    results
+                })
+            })
+            .collect();
+        let mut sent = Vec::new();
+        for sender in senders {
+            sent.extend(sender.await.unwrap());
+        }
+        done.store(true, Ordering::Relaxed);
+        let mut fetched = Vec::new();
+        for fetcher in fetchers {
+            fetched.extend(fetcher.await.unwrap());
+        }
+        fetched.extend(handle.fetch_new_messages().await.unwrap());
+        let fetched = fetched.into_iter().map(|m| m.into_owned()).collect();
+        (sent, fetched, handle.current_messages_size())
+    }};
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn rac_handle_clones_fetch_every_message_once() {
+    let server = Server::rac(b"");
+    server.add_user("alice", "secret");
+    let handle = RacHandle::new(RacClient::new(&server.address, alice(), false));
+    handle.fetch_messages_size().await.unwrap();
+
+    let (sent, fetched, size) = send_while_fetching!(handle);
+
+    let sent: Vec<_> = sent
+        .into_iter()
+        .map(|(text, result)| result.map(|()| text).unwrap())
+        .collect();
+    assert_eq!(sent.len(), SENDERS * MESSAGES);
+    assert_eq!(sorted_unique(fetched), sorted_unique(sent));
+    assert_eq!(size, server.state().history.len());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn whandle_clones_pair_every_reply_with_its_request() {
+    let server = Server::wrac(b"");
+    server.add_user("alice", "secret");
+    // Half of the sends fail with a status, so a status paired with the wrong send
+    // shows up as a fetched message that failed to send or the other way around.
+    let accepted = SENDERS * MESSAGES / 2;
+    server.state().sends_left = Some(accepted);
+    let handle = WHandle::new(WClient::new(&server.address, alice(), false));
+    handle.prepare().await.unwrap();
+    handle.fetch_messages_size().await.unwrap();
+
+    let (sent, fetched, size) = send_while_fetching!(handle);
+
+    let mut accepted_messages = Vec::new();
+    for (text, result) in sent {
+        match result {
+            Ok(()) => accepted_messages.push(text),
+            Err(ClientError::IncorrectPassword) => {}
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+    assert_eq!(accepted_messages.len(), accepted);
+    let history = sorted_unique(server.history().lines().map(str::to_string).collect());
+    assert_eq!(history, sorted_unique(accepted_messages));
+    assert_eq!(sorted_unique(fetched), history);
+    assert_eq!(size, server.state().history.len());
+}
+
+/// Connects in memory over connections whose writes stop making progress while `stalled` is set.
+#[derive(Debug)]
+struct Stalling {
+    inner: Arc<InMemory>,
+    stalled: Arc<AtomicBool>,
+}
+
+impl AsyncConnector for Stalling {
+    fn connect<'a>(&'a self, address: &'a str) -> ConnectFuture<'a> {
+        Box::pin(async move {
+            let inner = self.inner.connect(address).await?;
+            let stalled = self.stalled.clone();
+            Ok(Box::new(StallingStream { inner, stalled }) as Box<dyn AsyncTransport>)
+        })
+    }
+}
+
+/// A connection made by [`Stalling`].
+#[derive(Debug)]
+struct StallingStream {
+    inner: Box<dyn AsyncTransport>,
+    stalled: Arc<AtomicBool>,
+}
+
+impl AsyncRead for StallingStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for StallingStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        if self.stalled.load(Ordering::Relaxed) {
+            return Poll::Pending;
+        }
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+#[tokio::test]
+async fn split_halves_send_and_fetch_together() {
+    let server = Server::wrac(b"<bob> hello\n");
+    server.add_user("alice", "secret");
+    let (sender, mut fetcher) = WClient::new(&server.address, alice(), false).split();
+    fetcher.prepare().await.unwrap();
+    fetcher.fetch_messages_size().await.unwrap();
+
+    let (sent, fetched) = tokio::join!(
+        sender.send_message("<{username}> hi"),
+        fetcher.fetch_new_messages(),
+    );
+    sent.unwrap();
+    let mut fetched = fetched.unwrap();
+    fetched.extend(fetcher.fetch_new_messages().await.unwrap());
+
+    assert_eq!(fetched, ["<alice> hi"]);
+    assert_eq!(
+        fetcher.current_messages_size(),
+        server.state().history.len()
+    );
+    assert_eq!(sender.state(), ConnectionState::Connected);
+}
+
+#[tokio::test]
+async fn dropping_a_send_midway_closes_the_connection() {
+    let server = Server::wrac(b"");
+    server.add_user("alice", "secret");
+    let stalled = Arc::new(AtomicBool::new(false));
+    let mut client = WClient::new(&server.address, alice(), false);
+    client.update_connector(Some(Arc::new(Stalling {
+        inner: server.in_memory(usize::MAX),
+        stalled: stalled.clone(),
+    })));
+    let (sender, mut fetcher) = client.split();
+    fetcher.prepare().await.unwrap();
+
+    stalled.store(true, Ordering::Relaxed);
+    let send = sender.send_message("<{username}> lost");
+    tokio::time::timeout(Duration::from_millis(50), send)
+        .await
+        .unwrap_err();
+    stalled.store(false, Ordering::Relaxed);
+
+    // The dropped send may still reach the server without a ticket for its reply,
+    // which the next request would take for its own.
+    let error = sender.send_message("<{username}> next").await.unwrap_err();
+    assert!(matches!(error, ClientError::ServerClosedConnection { .. }));
+    assert!(!server.history().contains("next"));
+    assert_eq!(sender.state(), ConnectionState::Closed);
+
+    fetcher.prepare().await.unwrap();
+    sender.send_message("<{username}> found").await.unwrap();
+    assert_eq!(sender.state(), ConnectionState::Connected);
+    assert!(server.history().ends_with("<alice> found\n"));
+}
@@ -0,0 +1,117 @@
+//! Records the metrics that clients report against an in-process server.
+
+mod common;
+
+use common::Server;
+use rac_rs::metrics::{MetricsRecorder, Operation};
+use rac_rs::rac::RacClient;
+use rac_rs::shared::ClientError;
+use rac_rs::wrac::WClient;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A measurement reported to the recorder.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Event {
+    /// A finished operation, with the variant of its error if it failed.
+    Request(Operation, Option<&'static str>),
+    /// A received response.
+    Bytes(Operation, usize),
+    /// A replaced WebSocket connection.
+    Reconnect,
+}
+
+/// Keeps every measurement in the order it was reported.
+#[derive(Debug, Default)]
+struct Recorder(Mutex<Vec<Event>>);
+
+impl Recorder {
+    /// Returns the measurements reported so far and forgets them.
+    fn take(&self) -> Vec<Event> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl MetricsRecorder for Recorder {
+    fn request(
+        &self,
+        _server: &str,
+        operation: Operation,
+        _latency: Duration,
+        error: Option<&ClientError>,
+    ) {
+        let error = error.map(ClientError::variant_name);
+        self.0
+            .lock()
+            .unwrap()
+            .push(Event::Request(operation, error));
+    }
+
+    fn bytes_received(&self, _server: &str, operation: Operation, bytes: usize) {
+        self.0.lock().unwrap().push(Event::Bytes(operation, bytes));
+    }
+
+    fn reconnect(&self, _server: &str) {
+        self.0.lock().unwrap().push(Event::Reconnect);
+    }
+}
+
+#[test]
+fn reports_the_connection_and_the_request() {
+    let server = Server::rac(b"Hello\n");
+    let recorder = Arc::new(Recorder::default());
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    client.update_metrics(Some(recorder.clone()));
+
+    client.fetch_all_messages().unwrap();
+    let events = recorder.take();
+    assert_eq!(events.len(), 3, "{events:?}");
+    assert_eq!(events[0], Event::Request(Operation::Connect, None));
+    assert!(matches!(events[1], Event::Bytes(Operation::FetchAllMessages, n) if n > 6));
+    assert_eq!(events[2], Event::Request(Operation::FetchAllMessages, None));
+}
+
+#[test]
+fn reports_a_failed_connection_once() {
+    // Nothing listens on the port once the listener is dropped.
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .to_string();
+    let recorder = Arc::new(Recorder::default());
+    let mut client = RacClient::new(&address, Default::default(), false);
+    client.update_metrics(Some(recorder.clone()));
+
+    let error = client.fetch_messages_size().unwrap_err();
+    assert!(matches!(error, ClientError::ConnectionError { .. }));
+    assert_eq!(
+        recorder.take(),
+        [Event::Request(Operation::Connect, Some("ConnectionError"))]
+    );
+}
+
+#[test]
+fn reports_requests_over_an_open_websocket_and_reconnects() {
+    let server = Server::wrac(b"Hello\n");
+    let recorder = Arc::new(Recorder::default());
+    let mut client = WClient::new(&server.address, Default::default(), false);
+    client.update_metrics(Some(recorder.clone()));
+
+    client.prepare().unwrap();
+    assert_eq!(recorder.take(), [Event::Request(Operation::Connect, None)]);
+
+    client.fetch_messages_size().unwrap();
+    assert_eq!(
+        recorder.take(),
+        [
+            Event::Bytes(Operation::FetchMessagesSize, 1),
+            Event::Request(Operation::FetchMessagesSize, None)
+        ]
+    );
+
+    client.prepare().unwrap();
+    assert_eq!(
+        recorder.take(),
+        [Event::Request(Operation::Connect, None), Event::Reconnect]
+    );
+}
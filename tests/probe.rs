@@ -0,0 +1,55 @@
+//! Probes in-process servers.
+
+mod common;
+
+use common::Server;
+use rac_rs::probe::probe_with_timeout;
+use rac_rs::shared::{Protocol, ProtocolVersion};
+use std::time::Duration;
+
+/// How long the probe waits for every step, which is plenty for a server in the same process.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[test]
+fn detects_racv2_without_changing_the_history() {
+    let server = Server::rac(b"hello\n");
+
+    let capabilities = probe_with_timeout(&server.address, TIMEOUT).unwrap();
+    assert_eq!(capabilities.protocol, Protocol::Rac);
+    assert!(!capabilities.use_tls);
+    assert_eq!(capabilities.version, ProtocolVersion::V2);
+    assert!(capabilities.authenticated_send);
+    assert_eq!(capabilities.messages_size, 6);
+    assert_eq!(server.history(), "hello\n");
+}
+
+#[test]
+fn detects_racv1_when_the_server_accepts_any_user() {
+    let server = Server::rac(b"");
+    server.state().legacy = true;
+
+    let capabilities = probe_with_timeout(&server.address, TIMEOUT).unwrap();
+    assert_eq!(capabilities.version, ProtocolVersion::V1);
+    assert!(!capabilities.authenticated_send);
+    assert!(!capabilities.registration);
+}
+
+#[test]
+fn detects_racv1_when_the_server_does_not_know_the_send() {
+    let server = Server::rac(b"");
+    server.state().legacy = true;
+    server.state().unknown_reply = Some(b'0');
+
+    let capabilities = probe_with_timeout(&server.address, TIMEOUT).unwrap();
+    assert_eq!(capabilities.version, ProtocolVersion::V1);
+}
+
+#[test]
+fn detects_wrac_from_a_url() {
+    let server = Server::wrac(b"");
+    let address = format!("ws://{}", server.address);
+
+    let capabilities = probe_with_timeout(&address, TIMEOUT).unwrap();
+    assert_eq!(capabilities.protocol, Protocol::Wrac);
+    assert_eq!(capabilities.version, ProtocolVersion::V2);
+}
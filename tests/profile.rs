@@ -0,0 +1,89 @@
+//! Loads server profiles from TOML.
+
+use rac_rs::profile::Profiles;
+use rac_rs::shared::{ClientError, Protocol, ProtocolVersion};
+use std::time::Duration;
+
+/// Parses a file with a single profile named `test` that has the given extra settings.
+fn profile(settings: &str) -> Result<Profiles, ClientError> {
+    Profiles::from_toml(&format!(
+        "[profiles.test]\naddress = \"127.0.0.1:42666\"\nusername = \"alice\"\n{settings}"
+    ))
+}
+
+/// Checks that the settings are rejected with a message that mentions `problem`.
+fn assert_rejected(settings: &str, problem: &str) {
+    match profile(settings) {
+        Err(ClientError::InvalidConfig(message)) => {
+            assert!(
+                message.contains(problem),
+                "{message:?} doesn't mention {problem:?}"
+            )
+        }
+        other => panic!("expected {problem:?} for {settings:?}, got {other:?}"),
+    }
+}
+
+#[test]
+fn builds_the_config_of_a_profile() {
+    let profiles = Profiles::from_toml(
+        r#"
+        default = "chat"
+
+        [profiles.chat]
+        address = "wss://chat.example.com/ws"
+        protocol = "wrac"
+        version = "v1"
+        username = "alice"
+        timeout = 2.5
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(profiles.default_name(), Some("chat"));
+    let config = profiles.config("chat").unwrap();
+    assert_eq!(config.address, "wss://chat.example.com/ws");
+    assert_eq!(config.protocol, Protocol::Wrac);
+    assert_eq!(config.version, ProtocolVersion::V1);
+    assert_eq!(config.credentials.username, "alice");
+    assert_eq!(config.timeout, Some(Duration::from_millis(2500)));
+}
+
+#[test]
+fn loads_profiles_from_a_file() {
+    let path = std::env::temp_dir().join(format!("rac-rs-profiles-{}.toml", std::process::id()));
+    std::fs::write(
+        &path,
+        "[profiles.local]\naddress = \"127.0.0.1:42666\"\nusername = \"bob\"\n",
+    )
+    .unwrap();
+
+    let profiles = Profiles::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(profiles.unwrap().names().collect::<Vec<_>>(), ["local"]);
+}
+
+#[test]
+fn rejects_timeouts_that_are_not_a_duration() {
+    assert_rejected("timeout = 0", "timeout");
+    assert_rejected("timeout = -1.0", "timeout");
+    assert_rejected("timeout = 1e20", "timeout");
+    assert_rejected("timeout = inf", "timeout");
+    assert_rejected("timeout = nan", "timeout");
+}
+
+#[test]
+fn rejects_invalid_profiles() {
+    assert_rejected("password = \"a\"\npassword_env = \"B\"", "only one of");
+    assert_rejected("version = \"v1\"\npassword = \"a\"", "RACv1");
+    assert_rejected("password_command = []", "`password_command` is empty");
+    assert_rejected("colour = \"red\"", "unknown field");
+    assert!(matches!(
+        Profiles::from_toml("default = \"missing\"\n[profiles]"),
+        Err(ClientError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        Profiles::from_toml("[profiles.test]\naddress = \"no-port\"\nusername = \"alice\""),
+        Err(ClientError::InvalidConfig(message)) if message.contains("host:port")
+    ));
+}
@@ -0,0 +1,5 @@
+//! Runs the synchronous RAC client against an in-process server.
+
+mod common;
+
+common::client_suite!(sync, rac, rac_rs::rac::RacClient);
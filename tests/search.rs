@@ -0,0 +1,87 @@
+//! Searches an indexed history by text, author and client.
+
+use rac_rs::message::ClientKind;
+use rac_rs::search::{Query, SearchIndex};
+
+/// Returns the positions of the messages that match `query`.
+fn search(index: &SearchIndex, query: &str) -> Vec<usize> {
+    let query = Query::parse(query).unwrap();
+    index.search(&query).iter().map(|hit| hit.index).collect()
+}
+
+#[test]
+fn finds_texts_shorter_than_a_trigram() {
+    let mut index = SearchIndex::new();
+    index.extend(["<alice> ok", "<bob> no", "<carol> OK then", "<dave> 🙂"]);
+
+    assert_eq!(search(&index, "ok"), [0, 2]);
+    assert_eq!(search(&index, "n"), [1, 2]);
+    assert_eq!(search(&index, "🙂"), [3]);
+    assert_eq!(search(&index, "author:carol k"), [2]);
+    assert_eq!(search(&index, "x"), [] as [usize; 0]);
+}
+
+#[test]
+fn finds_multi_byte_text() {
+    let mut index = SearchIndex::new();
+    index.extend([
+        "<alice> Привет, мир 🙂🙂",
+        "<bob> 日本語のテキスト",
+        "<carol> 🙂 again",
+    ]);
+
+    assert_eq!(search(&index, "мир 🙂"), [0]);
+    assert_eq!(search(&index, "本語の"), [1]);
+    assert_eq!(search(&index, "🙂🙂"), [0]);
+    assert_eq!(search(&index, "🙂 a"), [2]);
+    assert_eq!(search(&index, "語のテキ"), [1]);
+    assert_eq!(search(&index, "語テキ"), [] as [usize; 0]);
+}
+
+#[test]
+fn ignores_the_case_of_texts_and_authors() {
+    let mut index = SearchIndex::new();
+    index.extend(["<Alice> HELLO there", "<bob> Привет", "<ALICE> hello again"]);
+
+    assert_eq!(search(&index, "HeLLo"), [0, 2]);
+    assert_eq!(search(&index, "пРИВЕТ"), [1]);
+    assert_eq!(search(&index, "author:alice"), [0, 2]);
+    assert_eq!(search(&index, "author:ALICE there"), [0]);
+    // Regular expressions keep their own case rules.
+    assert_eq!(search(&index, "regex:hello"), [2]);
+    assert_eq!(search(&index, "regex:(?i)hello"), [0, 2]);
+}
+
+#[test]
+fn filters_by_author_and_client() {
+    let mut index = SearchIndex::new();
+    index.extend([
+        "\u{B9AC}\u{3E70}<alice> hi from bRAC",
+        "═══<alice> hi from CRAB",
+        "<alice> hi without a client",
+        "<bob> hi without a client",
+        "a line without an author",
+    ]);
+    let with_client = |client, text: Option<&str>| {
+        let query = Query {
+            client: Some(client),
+            text: text.map(str::to_string),
+            ..Default::default()
+        };
+        let hits = index.search(&query);
+        hits.iter().map(|hit| hit.index).collect::<Vec<_>>()
+    };
+
+    assert_eq!(search(&index, "author:alice"), [0, 1, 2]);
+    assert_eq!(search(&index, "author:alice without"), [2]);
+    assert_eq!(search(&index, "author:dave"), [] as [usize; 0]);
+    assert_eq!(with_client(ClientKind::Crab, None), [1]);
+    assert_eq!(with_client(ClientKind::Unsigned, None), [2, 3]);
+    assert_eq!(
+        with_client(ClientKind::Unsigned, Some("bob")),
+        [] as [usize; 0]
+    );
+    assert_eq!(with_client(ClientKind::Mefidroniy, None), [] as [usize; 0]);
+    assert_eq!(search(&index, "author:alice client:bRAC hi"), [0]);
+    assert_eq!(search(&index, "author"), [4]);
+}
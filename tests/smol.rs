@@ -0,0 +1,50 @@
+//! Runs the async clients on `async-io`, without a `tokio` runtime.
+
+mod common;
+
+use async_io::block_on;
+use common::Server;
+use rac_rs::shared::{ClientError, Credentials};
+
+fn credentials(username: &str) -> Credentials {
+    Credentials {
+        username: username.to_string(),
+        password: Some("secret".into()),
+    }
+}
+
+#[test]
+fn async_rac_runs_on_smol() {
+    let server = Server::rac(b"Hello\n");
+    let mut client =
+        rac_rs::async_rac::RacClient::new(&server.address, credentials("alice"), false);
+
+    block_on(async {
+        client.register_user().await.unwrap();
+        assert!(matches!(
+            client.register_user().await,
+            Err(ClientError::UsernameAlreadyTaken)
+        ));
+        assert_eq!(client.fetch_all_messages().await.unwrap(), ["Hello"]);
+        client.send_message("<{username}> Hi").await.unwrap();
+        assert_eq!(client.fetch_new_messages().await.unwrap(), ["<alice> Hi"]);
+    });
+}
+
+#[test]
+fn async_wrac_runs_on_smol() {
+    let server = Server::wrac(b"Hello\n");
+    let mut client = rac_rs::async_wrac::WClient::new(&server.address, credentials("bob"), false);
+
+    block_on(async {
+        client.prepare().await.unwrap();
+        client.register_user().await.unwrap();
+        assert!(matches!(
+            client.register_user().await,
+            Err(ClientError::UsernameAlreadyTaken)
+        ));
+        assert_eq!(client.fetch_all_messages().await.unwrap(), ["Hello"]);
+        client.send_message("<{username}> Hi").await.unwrap();
+        assert_eq!(client.fetch_new_messages().await.unwrap(), ["<bob> Hi"]);
+    });
+}
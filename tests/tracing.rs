@@ -0,0 +1,251 @@
+//! Records the spans and events that clients emit with the `tracing` feature.
+
+mod common;
+
+use common::{Server, alice};
+use rac_rs::rac::RacClient;
+use rac_rs::wrac::WClient;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// A span with the values of its fields.
+#[derive(Debug, Clone)]
+struct SpanData {
+    metadata: &'static Metadata<'static>,
+    name: &'static str,
+    parent: Option<u64>,
+    fields: HashMap<String, String>,
+}
+
+/// An event with the values of its fields, including `message`.
+#[derive(Debug, Clone)]
+struct EventData {
+    level: Level,
+    span: Option<u64>,
+    fields: HashMap<String, String>,
+}
+
+/// What the recorder has seen so far.
+#[derive(Debug, Default)]
+struct Recorded {
+    spans: Vec<SpanData>,
+    events: Vec<EventData>,
+    /// Spans that are entered, innermost last.
+    stack: Vec<u64>,
+}
+
+/// Keeps every span and event of the thread it is the default subscriber of.
+#[derive(Debug, Clone, Default)]
+struct Recorder(Arc<Mutex<Recorded>>);
+
+impl Recorder {
+    /// Runs `f` with the recorder as the default subscriber.
+    fn record<T>(&self, f: impl FnOnce() -> T) -> T {
+        tracing::subscriber::with_default(self.clone(), f)
+    }
+
+    /// Returns the spans named `name`.
+    fn spans(&self, name: &str) -> Vec<SpanData> {
+        let recorded = self.0.lock().unwrap();
+        recorded
+            .spans
+            .iter()
+            .filter(|span| span.name == name)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the span with the id of a recorded event or span.
+    fn span(&self, id: u64) -> SpanData {
+        self.0.lock().unwrap().spans[id as usize - 1].clone()
+    }
+
+    /// Returns the events with the message `message`.
+    fn events(&self, message: &str) -> Vec<EventData> {
+        let recorded = self.0.lock().unwrap();
+        recorded
+            .events
+            .iter()
+            .filter(|event| event.fields.get("message").is_some_and(|m| m == message))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns every recorded value of every field.
+    fn values(&self) -> Vec<String> {
+        let recorded = self.0.lock().unwrap();
+        let spans = recorded.spans.iter().map(|span| &span.fields);
+        let events = recorded.events.iter().map(|event| &event.fields);
+        spans
+            .chain(events)
+            .flat_map(|fields| fields.values().cloned())
+            .collect()
+    }
+}
+
+/// Collects the values of fields as text.
+struct Fields<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &Attributes<'_>) -> Id {
+        let mut recorded = self.0.lock().unwrap();
+        let parent = match attributes.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attributes.is_contextual() => recorded.stack.last().copied(),
+            None => None,
+        };
+        let mut fields = HashMap::new();
+        attributes.record(&mut Fields(&mut fields));
+        recorded.spans.push(SpanData {
+            metadata: attributes.metadata(),
+            name: attributes.metadata().name(),
+            parent,
+            fields,
+        });
+        Id::from_u64(recorded.spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut recorded = self.0.lock().unwrap();
+        let fields = &mut recorded.spans[span.into_u64() as usize - 1].fields;
+        values.record(&mut Fields(fields));
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut recorded = self.0.lock().unwrap();
+        let mut fields = HashMap::new();
+        event.record(&mut Fields(&mut fields));
+        let span = recorded.stack.last().copied();
+        recorded.events.push(EventData {
+            level: *event.metadata().level(),
+            span,
+            fields,
+        });
+    }
+
+    fn enter(&self, span: &Id) {
+        self.0.lock().unwrap().stack.push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.0.lock().unwrap().stack.pop();
+    }
+
+    fn current_span(&self) -> Current {
+        let recorded = self.0.lock().unwrap();
+        match recorded.stack.last() {
+            Some(&id) => Current::new(Id::from_u64(id), recorded.spans[id as usize - 1].metadata),
+            None => Current::none(),
+        }
+    }
+}
+
+#[test]
+fn records_fetches_with_their_byte_counts() {
+    let server = Server::rac(b"<alice> one\n\0\0<bob> two\n");
+    let mut client = RacClient::new(&server.address, Default::default(), false);
+    let recorder = Recorder::default();
+
+    recorder.record(|| client.fetch_all_messages()).unwrap();
+
+    let [fetch] = &recorder.spans("fetch_all_messages")[..] else {
+        panic!("{:?}", recorder.spans("fetch_all_messages"));
+    };
+    assert_eq!(fetch.fields["address"], server.address);
+    // The size, `24`, and the 24 bytes of the history.
+    assert_eq!(fetch.fields["bytes_received"], "26");
+
+    let [connect] = &recorder.spans("connect")[..] else {
+        panic!("{:?}", recorder.spans("connect"));
+    };
+    assert_eq!(connect.fields["tls"], "false");
+    assert_eq!(
+        recorder.span(connect.parent.unwrap()).name,
+        "fetch_all_messages"
+    );
+
+    let [stripped] = &recorder.events("stripped null bytes from the response")[..] else {
+        panic!("no event about the null bytes");
+    };
+    assert_eq!(
+        recorder.span(stripped.span.unwrap()).name,
+        "fetch_all_messages"
+    );
+}
+
+#[test]
+fn records_sends_and_registrations_without_the_password() {
+    let server = Server::rac(b"");
+    let mut client = RacClient::new(&server.address, alice(), false);
+    let recorder = Recorder::default();
+
+    recorder.record(|| client.register_user()).unwrap();
+    recorder
+        .record(|| client.send_message("<{username}> hi"))
+        .unwrap();
+    recorder.record(|| client.register_user()).unwrap_err();
+
+    let registrations = recorder.spans("register_user");
+    assert_eq!(registrations.len(), 2);
+    assert_eq!(registrations[0].fields["username"], "alice");
+    let [send] = &recorder.spans("send_message")[..] else {
+        panic!("{:?}", recorder.spans("send_message"));
+    };
+    assert_eq!(send.fields["message_bytes"], "10");
+    assert_eq!(send.fields["authenticated"], "true");
+
+    let failures: Vec<_> = recorder
+        .0
+        .lock()
+        .unwrap()
+        .events
+        .iter()
+        .filter(|event| event.level == Level::WARN)
+        .map(|event| event.fields["error"].clone())
+        .collect();
+    assert_eq!(failures, ["Username is already taken"]);
+
+    let values = recorder.values();
+    assert!(
+        values.iter().all(|value| !value.contains("secret")),
+        "{values:?}"
+    );
+}
+
+#[test]
+fn records_the_websocket_upgrade_inside_the_connect_span() {
+    let server = Server::wrac(b"");
+    let mut client = WClient::new(&server.address, Default::default(), false);
+    let recorder = Recorder::default();
+
+    recorder.record(|| client.prepare()).unwrap();
+
+    let [upgrade] = &recorder.spans("websocket_upgrade")[..] else {
+        panic!("{:?}", recorder.spans("websocket_upgrade"));
+    };
+    let connect = recorder.span(upgrade.parent.unwrap());
+    assert_eq!(connect.name, "connect");
+    assert_eq!(connect.fields["address"], server.address);
+}
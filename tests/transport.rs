@@ -0,0 +1,145 @@
+//! Runs the clients over connectors and transports supplied by the caller.
+
+mod common;
+
+use common::{InMemory, Server, alice};
+use rac_rs::transport::{AsyncConnector, ConnectFuture, Connector, Transport};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Connects in memory, keeping the addresses it was asked for and what every connection wrote.
+#[derive(Debug)]
+struct Tapping {
+    inner: Arc<InMemory>,
+    addresses: Mutex<Vec<String>>,
+    written: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl Tapping {
+    fn new(server: &Server) -> Arc<Self> {
+        Arc::new(Self {
+            inner: server.in_memory(usize::MAX),
+            addresses: Mutex::default(),
+            written: Arc::default(),
+        })
+    }
+
+    fn addresses(&self) -> Vec<String> {
+        self.addresses.lock().unwrap().clone()
+    }
+
+    fn written(&self) -> Vec<Vec<u8>> {
+        self.written.lock().unwrap().clone()
+    }
+}
+
+impl Connector for Tapping {
+    fn connect(
+        &self,
+        address: &str,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<Box<dyn Transport>> {
+        self.addresses.lock().unwrap().push(address.to_string());
+        let mut written = self.written.lock().unwrap();
+        written.push(Vec::new());
+        Ok(Box::new(Tapped {
+            inner: Connector::connect(&*self.inner, address, timeout)?,
+            written: self.written.clone(),
+            connection: written.len() - 1,
+        }))
+    }
+}
+
+impl AsyncConnector for Tapping {
+    fn connect<'a>(&'a self, address: &'a str) -> ConnectFuture<'a> {
+        self.addresses.lock().unwrap().push(address.to_string());
+        AsyncConnector::connect(&*self.inner, address)
+    }
+}
+
+/// A connection made by [`Tapping`].
+#[derive(Debug)]
+struct Tapped {
+    inner: Box<dyn Transport>,
+    written: Arc<Mutex<Vec<Vec<u8>>>>,
+    connection: usize,
+}
+
+impl Read for Tapped {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for Tapped {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written.lock().unwrap()[self.connection].extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Transport for Tapped {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
+
+#[test]
+fn runs_rac_over_a_custom_transport() {
+    let server = Server::rac(b"<bob> hello\n");
+    server.add_user("alice", "secret");
+    let connector = Tapping::new(&server);
+    let mut client = rac_rs::rac::RacClient::new("rac.example:42666", alice(), false);
+    client.update_connector(Some(connector.clone()));
+
+    client.send_message("<{username}> hi").unwrap();
+    assert_eq!(
+        client.fetch_all_messages().unwrap(),
+        ["<bob> hello", "<alice> hi"]
+    );
+
+    assert_eq!(connector.addresses(), ["rac.example:42666"; 2]);
+    let written = connector.written();
+    assert_eq!(written[0], b"\x02alice\nsecret\n<alice> hi");
+    assert_eq!(written[1][0], 0x00);
+}
+
+#[test]
+fn runs_wrac_over_one_custom_connection() {
+    let server = Server::wrac(b"");
+    server.add_user("alice", "secret");
+    let connector = Tapping::new(&server);
+    let mut client = rac_rs::wrac::WClient::new("ws://wrac.example:52666/", alice(), false);
+    client.update_connector(Some(connector.clone()));
+
+    client.prepare().unwrap();
+    client.send_message("<{username}> hi").unwrap();
+    assert_eq!(client.fetch_all_messages().unwrap(), ["<alice> hi"]);
+
+    assert_eq!(connector.addresses(), ["wrac.example:52666"]);
+    let upgrade = String::from_utf8_lossy(&connector.written()[0]).into_owned();
+    assert!(upgrade.starts_with("GET / HTTP/1.1\r\n"), "{upgrade}");
+    assert!(
+        upgrade.contains("Host: wrac.example:52666\r\n"),
+        "{upgrade}"
+    );
+}
+
+#[tokio::test]
+async fn runs_async_rac_over_a_custom_connector() {
+    let server = Server::rac(b"");
+    server.add_user("alice", "secret");
+    let connector = Tapping::new(&server);
+    let mut client = rac_rs::async_rac::RacClient::new("rac.example:42666", alice(), false);
+    client.update_connector(Some(connector.clone()));
+
+    client.send_message("<{username}> hi").await.unwrap();
+    assert_eq!(client.fetch_all_messages().await.unwrap(), ["<alice> hi"]);
+    assert_eq!(connector.addresses(), ["rac.example:42666"; 2]);
+}
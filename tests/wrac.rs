@@ -0,0 +1,5 @@
+//! Runs the synchronous WRAC client against an in-process server.
+
+mod common;
+
+common::client_suite!(sync, wrac, rac_rs::wrac::WClient);